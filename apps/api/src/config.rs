@@ -7,6 +7,19 @@ pub struct Config {
     pub refresh_token_expiration: i64,
     pub server_host: String,
     pub server_port: u16,
+    pub signed_prekey_grace_period_seconds: i64,
+    pub signed_prekey_prune_interval_seconds: u64,
+    /// Sender certificates are only issued, and sealed sender only usable, when this is set
+    pub sender_certificate_secret: Option<String>,
+    pub sender_certificate_ttl_seconds: i64,
+    /// How often the server pings each WebSocket
+    pub ws_heartbeat_interval_seconds: u64,
+    /// A WebSocket that sends nothing, pongs included, for this long is closed
+    pub ws_idle_timeout_seconds: u64,
+    /// Frames a WebSocket may have waiting to be written before it is treated as too slow
+    pub ws_outbound_queue_capacity: usize,
+    /// How often devices that stopped sending heartbeats are taken offline
+    pub presence_sweep_interval_seconds: u64,
 }
 
 impl Config {
@@ -21,6 +34,39 @@ impl Config {
             refresh_token_expiration: std::env::var("REFRESH_TOKEN_EXPIRATION")?.parse()?,
             server_host: std::env::var("SERVER_HOST")?,
             server_port: std::env::var("SERVER_PORT")?.parse()?,
+            signed_prekey_grace_period_seconds: optional_var("SIGNED_PREKEY_GRACE_PERIOD_SECONDS")?
+                .unwrap_or(30 * 24 * 60 * 60), // 30 days
+            signed_prekey_prune_interval_seconds: optional_var(
+                "SIGNED_PREKEY_PRUNE_INTERVAL_SECONDS",
+            )?
+            .unwrap_or(60 * 60), // 1 hour
+            sender_certificate_secret: optional_var("SENDER_CERTIFICATE_SECRET")?,
+            sender_certificate_ttl_seconds: optional_var("SENDER_CERTIFICATE_TTL_SECONDS")?
+                .unwrap_or(24 * 60 * 60), // 1 day
+            ws_heartbeat_interval_seconds: optional_var("WS_HEARTBEAT_INTERVAL_SECONDS")?
+                .unwrap_or(30),
+            ws_idle_timeout_seconds: optional_var("WS_IDLE_TIMEOUT_SECONDS")?.unwrap_or(90),
+            ws_outbound_queue_capacity: optional_var("WS_OUTBOUND_QUEUE_CAPACITY")?.unwrap_or(256),
+            presence_sweep_interval_seconds: optional_var("PRESENCE_SWEEP_INTERVAL_SECONDS")?
+                .unwrap_or(15),
         })
     }
+
+    /// A device drops offline once it misses three heartbeats in a row.
+    pub fn presence_ttl_seconds(&self) -> i64 {
+        self.ws_heartbeat_interval_seconds as i64 * 3
+    }
+}
+
+/// Read an optional, parseable environment variable.
+fn optional_var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value.parse()?)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
 }
@@ -1,4 +1,6 @@
+use super::identity::notify_identity_changed;
 use crate::config::Config;
+use crate::websocket::connection::ConnectionManager;
 use actix_web::{delete, get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use application::auth::{
     dtos::*,
@@ -9,14 +11,13 @@ use sea_orm::DatabaseConnection;
 use uuid::Uuid;
 
 /// Extract user_id and device_id from JWT claims in request extensions
-fn extract_auth_claims(req: &HttpRequest) -> Option<(Uuid, i64)> {
+pub(crate) fn extract_auth_claims(req: &HttpRequest) -> Option<(Uuid, i64)> {
     req.extensions()
         .get::<Claims>()
-        .map(|claims| {
+        .and_then(|claims| {
             let user_id = claims.sub.parse::<Uuid>().ok()?;
             Some((user_id, claims.device_id))
         })
-        .flatten()
 }
 
 // ============ OTP Endpoints ============
@@ -60,6 +61,7 @@ pub async fn verify_otp(
     db: web::Data<DatabaseConnection>,
     redis_conn: web::Data<MultiplexedConnection>,
     config: web::Data<Config>,
+    manager: web::Data<ConnectionManager>,
     req: web::Json<VerifyOtpRequest>,
 ) -> impl Responder {
     let mut conn = redis_conn.get_ref().clone();
@@ -71,7 +73,12 @@ pub async fn verify_otp(
     };
 
     match VerifyOtpUseCase::execute(db.get_ref(), &mut conn, &auth_config, req.into_inner()).await {
-        Ok(response) => HttpResponse::Ok().json(response),
+        Ok(response) => {
+            if response.identity_changed {
+                notify_identity_changed(db.get_ref(), manager.get_ref(), response.user_id).await;
+            }
+            HttpResponse::Ok().json(response)
+        }
         Err(e) => {
             let error_msg = e.to_string();
             if error_msg.contains("Invalid or expired OTP") {
@@ -80,6 +87,12 @@ pub async fn verify_otp(
                     error_code: "INVALID_OTP".to_string(),
                     retry_after_seconds: None,
                 })
+            } else if error_msg.starts_with("Invalid key bundle") {
+                HttpResponse::BadRequest().json(AuthErrorResponse {
+                    error: error_msg,
+                    error_code: "INVALID_KEYS".to_string(),
+                    retry_after_seconds: None,
+                })
             } else {
                 HttpResponse::InternalServerError().json(AuthErrorResponse {
                     error: error_msg,
@@ -249,11 +262,19 @@ pub async fn complete_linking(
 ) -> impl Responder {
     match CompleteLinkingUseCase::execute(db.get_ref(), req.into_inner()).await {
         Ok(response) => HttpResponse::Ok().json(response),
-        Err(e) => HttpResponse::BadRequest().json(AuthErrorResponse {
-            error: e.to_string(),
-            error_code: "INVALID_REQUEST".to_string(),
-            retry_after_seconds: None,
-        }),
+        Err(e) => {
+            let error_msg = e.to_string();
+            let error_code = if error_msg.starts_with("Invalid key bundle") {
+                "INVALID_KEYS"
+            } else {
+                "INVALID_REQUEST"
+            };
+            HttpResponse::BadRequest().json(AuthErrorResponse {
+                error: error_msg,
+                error_code: error_code.to_string(),
+                retry_after_seconds: None,
+            })
+        }
     }
 }
 
@@ -261,9 +282,10 @@ pub async fn complete_linking(
 pub async fn approve_linking(
     http_req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
     req: web::Json<ApproveLinkingRequest>,
 ) -> impl Responder {
-    let (_, device_id) = match extract_auth_claims(&http_req) {
+    let (user_id, device_id) = match extract_auth_claims(&http_req) {
         Some(claims) => claims,
         None => {
             return HttpResponse::Unauthorized().json(AuthErrorResponse {
@@ -275,7 +297,12 @@ pub async fn approve_linking(
     };
 
     match ApproveLinkingUseCase::execute(db.get_ref(), device_id, req.into_inner()).await {
-        Ok(response) => HttpResponse::Ok().json(response),
+        Ok(response) => {
+            if response.identity_changed {
+                notify_identity_changed(db.get_ref(), manager.get_ref(), user_id).await;
+            }
+            HttpResponse::Ok().json(response)
+        }
         Err(e) => HttpResponse::BadRequest().json(AuthErrorResponse {
             error: e.to_string(),
             error_code: "INVALID_REQUEST".to_string(),
@@ -316,6 +343,7 @@ pub async fn list_devices(
 pub async fn unlink_device(
     http_req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
     path: web::Path<i64>,
 ) -> impl Responder {
     let (user_id, current_device_id) = match extract_auth_claims(&http_req) {
@@ -334,7 +362,12 @@ pub async fn unlink_device(
     match UnlinkDeviceUseCase::execute(db.get_ref(), user_id, current_device_id, target_device_id)
         .await
     {
-        Ok(response) => HttpResponse::Ok().json(response),
+        Ok(response) => {
+            if response.identity_changed {
+                notify_identity_changed(db.get_ref(), manager.get_ref(), user_id).await;
+            }
+            HttpResponse::Ok().json(response)
+        }
         Err(e) => {
             let error_msg = e.to_string();
             if error_msg.contains("Unauthorized") {
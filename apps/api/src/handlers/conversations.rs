@@ -0,0 +1,385 @@
+use super::auth::extract_auth_claims;
+use crate::websocket::{
+    connection::ConnectionManager,
+    messages::{OutboundEvent, ServerEvent},
+};
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse, Responder};
+use application::chat::{
+    conversations::{
+        CreateConversationUseCase, GetConversationUseCase, LeaveConversationUseCase,
+        ListConversationsUseCase, NOT_A_MEMBER,
+    },
+    dtos::{
+        AddMembersRequest, ConversationDto, ConversationsResponse, CreateConversationRequest,
+        CreateInviteRequest, InvitesResponse, MembershipChange, SetMemberRoleRequest,
+        UpdateGroupRequest,
+    },
+    groups::{
+        AddMembersUseCase, RemoveMemberUseCase, SetMemberRoleUseCase, UpdateGroupUseCase,
+        PERMISSION_DENIED,
+    },
+    invites::{
+        CreateInviteUseCase, JoinViaInviteUseCase, ListInvitesUseCase, RevokeInviteUseCase,
+        INVITE_INVALID,
+    },
+};
+use sea_orm::DatabaseConnection;
+use uuid::Uuid;
+
+#[post("/api/v1/conversations")]
+pub async fn create_conversation(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    req: web::Json<CreateConversationRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match CreateConversationUseCase::execute(db.get_ref(), user_id, req.into_inner()).await {
+        Ok(conversation) => HttpResponse::Ok().json(conversation),
+        Err(e) => {
+            if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else if e.starts_with("Invalid") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[get("/api/v1/conversations")]
+pub async fn list_conversations(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match ListConversationsUseCase::execute(db.get_ref(), user_id).await {
+        Ok(conversations) => HttpResponse::Ok().json(ConversationsResponse { conversations }),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+#[get("/api/v1/conversations/{conversation_id}")]
+pub async fn get_conversation(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match GetConversationUseCase::execute(db.get_ref(), user_id, path.into_inner()).await {
+        Ok(conversation) => HttpResponse::Ok().json(conversation),
+        Err(e) => {
+            if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[post("/api/v1/conversations/{conversation_id}/leave")]
+pub async fn leave_conversation(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match LeaveConversationUseCase::execute(db.get_ref(), user_id, path.into_inner()).await {
+        Ok(changes) => {
+            for change in &changes {
+                notify_membership_changed(manager.get_ref(), change).await;
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => {
+            if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[patch("/api/v1/conversations/{conversation_id}")]
+pub async fn update_group(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<Uuid>,
+    req: web::Json<UpdateGroupRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match UpdateGroupUseCase::execute(db.get_ref(), user_id, path.into_inner(), req.into_inner())
+        .await
+    {
+        Ok(conversation) => {
+            notify_group_updated(manager.get_ref(), user_id, &conversation).await;
+            HttpResponse::Ok().json(conversation)
+        }
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[post("/api/v1/conversations/{conversation_id}/members")]
+pub async fn add_members(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<Uuid>,
+    req: web::Json<AddMembersRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match AddMembersUseCase::execute(
+        db.get_ref(),
+        user_id,
+        path.into_inner(),
+        req.into_inner().user_ids,
+    )
+    .await
+    {
+        Ok(change) => {
+            notify_membership_changed(manager.get_ref(), &change).await;
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[delete("/api/v1/conversations/{conversation_id}/members/{user_id}")]
+pub async fn remove_member(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let (conversation_id, member_id) = path.into_inner();
+
+    match RemoveMemberUseCase::execute(db.get_ref(), user_id, conversation_id, member_id).await {
+        Ok(change) => {
+            notify_membership_changed(manager.get_ref(), &change).await;
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[put("/api/v1/conversations/{conversation_id}/members/{user_id}/role")]
+pub async fn set_member_role(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<(Uuid, Uuid)>,
+    req: web::Json<SetMemberRoleRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let (conversation_id, member_id) = path.into_inner();
+
+    match SetMemberRoleUseCase::execute(db.get_ref(), user_id, conversation_id, member_id, req.role)
+        .await
+    {
+        Ok(change) => {
+            if let Some(change) = change {
+                notify_membership_changed(manager.get_ref(), &change).await;
+            }
+            HttpResponse::NoContent().finish()
+        }
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[post("/api/v1/conversations/{conversation_id}/invites")]
+pub async fn create_invite(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<Uuid>,
+    req: web::Json<CreateInviteRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match CreateInviteUseCase::execute(db.get_ref(), user_id, path.into_inner(), req.into_inner())
+        .await
+    {
+        Ok(invite) => HttpResponse::Ok().json(invite),
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[get("/api/v1/conversations/{conversation_id}/invites")]
+pub async fn list_invites(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match ListInvitesUseCase::execute(db.get_ref(), user_id, path.into_inner()).await {
+        Ok(invites) => HttpResponse::Ok().json(InvitesResponse { invites }),
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[delete("/api/v1/conversations/{conversation_id}/invites/{token}")]
+pub async fn revoke_invite(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<(Uuid, String)>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let (conversation_id, token) = path.into_inner();
+
+    match RevokeInviteUseCase::execute(db.get_ref(), user_id, conversation_id, &token).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => group_error_response(e),
+    }
+}
+
+#[post("/api/v1/invites/{token}/join")]
+pub async fn join_via_invite(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match JoinViaInviteUseCase::execute(db.get_ref(), user_id, &path.into_inner()).await {
+        Ok(joined) => {
+            if let Some(change) = &joined.change {
+                notify_membership_changed(manager.get_ref(), change).await;
+            }
+            HttpResponse::Ok().json(joined.conversation)
+        }
+        Err(e) => group_error_response(e),
+    }
+}
+
+fn group_error_response(e: String) -> HttpResponse {
+    if e == PERMISSION_DENIED || e == NOT_A_MEMBER {
+        HttpResponse::Forbidden().json(serde_json::json!({ "error": e }))
+    } else if e == INVITE_INVALID {
+        HttpResponse::Gone().json(serde_json::json!({ "error": e }))
+    } else if e.contains("not found") {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+    } else if e.starts_with("Invalid") {
+        HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+    } else {
+        HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+    }
+}
+
+/// Tell every online device of the change's recipients about it.
+pub(crate) async fn notify_membership_changed(
+    manager: &ConnectionManager,
+    change: &MembershipChange,
+) {
+    let outbound = OutboundEvent::server(ServerEvent::MembershipChanged {
+        conversation_id: change.conversation_id,
+        actor_id: change.actor_id,
+        action: change.action,
+        user_ids: change.user_ids.clone(),
+        role: change.role,
+    });
+
+    for recipient in &change.recipients {
+        manager.send_to_user(recipient, &outbound).await;
+    }
+}
+
+async fn notify_group_updated(
+    manager: &ConnectionManager,
+    actor_id: Uuid,
+    conversation: &ConversationDto,
+) {
+    let outbound = OutboundEvent::server(ServerEvent::GroupUpdated {
+        conversation_id: conversation.conversation_id,
+        actor_id,
+        name: conversation.name.clone(),
+        avatar: conversation.avatar.clone(),
+    });
+
+    for member in &conversation.members {
+        manager.send_to_user(&member.user_id, &outbound).await;
+    }
+}
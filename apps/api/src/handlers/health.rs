@@ -1,4 +1,5 @@
-use actix_web::{get, HttpResponse, Responder};
+use crate::websocket::connection::ConnectionManager;
+use actix_web::{get, web, HttpResponse, Responder};
 use serde_json::json;
 
 #[get("/health")]
@@ -9,3 +10,9 @@ pub async fn health_check() -> impl Responder {
         "version": "1.0.0"
     }))
 }
+
+/// WebSocket connection counters for the node that answers.
+#[get("/metrics/websocket")]
+pub async fn websocket_metrics(manager: web::Data<ConnectionManager>) -> impl Responder {
+    HttpResponse::Ok().json(manager.metrics().await)
+}
@@ -0,0 +1,152 @@
+use super::auth::extract_auth_claims;
+use crate::websocket::{
+    connection::ConnectionManager,
+    messages::{OutboundEvent, ServerEvent},
+};
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse, Responder};
+use application::identity::{
+    dtos::{IdentityChangesQuery, IdentityChangesResponse, VerifyContactRequest},
+    use_cases::{
+        GetIdentityChangeNoticeUseCase, GetSafetyNumberUseCase, SyncIdentityChangesUseCase,
+        UnverifyContactUseCase, VerifyContactUseCase, MAX_IDENTITY_CHANGES_PAGE,
+    },
+};
+use sea_orm::DatabaseConnection;
+use uuid::Uuid;
+
+#[get("/api/v1/conversations/{conversation_id}/members/{user_id}/safety-number")]
+pub async fn get_safety_number(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<(Uuid, Uuid)>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let (conversation_id, peer_user_id) = path.into_inner();
+
+    match GetSafetyNumberUseCase::execute(db.get_ref(), user_id, conversation_id, peer_user_id)
+        .await
+    {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else if e.starts_with("Cannot") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[put("/api/v1/contacts/{user_id}/verification")]
+pub async fn verify_contact(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<Uuid>,
+    req: web::Json<VerifyContactRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match VerifyContactUseCase::execute(db.get_ref(), user_id, path.into_inner(), req.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else if e.contains("does not match") {
+                HttpResponse::Conflict().json(serde_json::json!({ "error": e }))
+            } else if e.starts_with("Cannot") || e.starts_with("Invalid") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[delete("/api/v1/contacts/{user_id}/verification")]
+pub async fn unverify_contact(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    path: web::Path<Uuid>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match UnverifyContactUseCase::execute(db.get_ref(), user_id, path.into_inner()).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+#[get("/api/v1/identity/changes")]
+pub async fn sync_identity_changes(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    query: web::Query<IdentityChangesQuery>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match SyncIdentityChangesUseCase::execute(
+        db.get_ref(),
+        user_id,
+        query.after_change_id,
+        query.limit.unwrap_or(MAX_IDENTITY_CHANGES_PAGE),
+    )
+    .await
+    {
+        Ok(changes) => HttpResponse::Ok().json(IdentityChangesResponse { changes }),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+/// Push the user's latest identity change to every online member of their conversations.
+pub(crate) async fn notify_identity_changed(
+    db: &DatabaseConnection,
+    manager: &ConnectionManager,
+    user_id: Uuid,
+) {
+    let notice = match GetIdentityChangeNoticeUseCase::execute(db, user_id).await {
+        Ok(Some(notice)) => notice,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to load identity change for user {}: {}", user_id, e);
+            return;
+        }
+    };
+
+    let outbound = OutboundEvent::server(ServerEvent::IdentityChanged {
+        change_id: notice.change.change_id,
+        user_id,
+        changed_at: notice.change.changed_at,
+    });
+
+    for recipient in notice.recipients {
+        manager.send_to_user(&recipient, &outbound).await;
+    }
+}
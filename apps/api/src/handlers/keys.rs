@@ -1,23 +1,212 @@
-use actix_web::{get, web, HttpResponse, Responder};
-use application::keys::use_cases::GetPreKeyBundleUseCase;
+use super::auth::extract_auth_claims;
+use crate::config::Config;
+use crate::websocket::{
+    connection::ConnectionManager,
+    messages::{OutboundEvent, ServerEvent},
+};
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use application::keys::{
+    dtos::{SignedPreKeyDto, UploadPreKeysRequest, UserPreKeyBundlesResponse},
+    use_cases::{
+        CheckPreKeyStockUseCase, GetPreKeyBundleUseCase, GetPreKeyCountUseCase,
+        GetUserPreKeyBundlesUseCase, RotateSignedPreKeyUseCase, UploadPreKeysUseCase,
+        BUNDLE_RATE_LIMITED, PREKEY_ID_EXISTS, SIGNED_PREKEY_ID_EXISTS,
+    },
+};
+use infrastructure::redis::RedisClient;
+use redis::aio::MultiplexedConnection;
 use sea_orm::DatabaseConnection;
 use uuid::Uuid;
 
+// The `*` segment is matched literally, so this must be registered before `get_prekey_bundle`.
+#[get(r"/api/v1/keys/{user_id}/devices/{all:\*}")]
+pub async fn get_all_prekey_bundles(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    redis_conn: web::Data<MultiplexedConnection>,
+    manager: web::Data<ConnectionManager>,
+    path: web::Path<(Uuid, String)>,
+) -> impl Responder {
+    let (requester_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let (user_id, _) = path.into_inner();
+    let mut redis = RedisClient::new(redis_conn.get_ref().clone());
+
+    match GetUserPreKeyBundlesUseCase::execute(db.get_ref(), &mut redis, requester_id, user_id)
+        .await
+    {
+        Ok(devices) => {
+            for bundle in &devices {
+                notify_if_prekeys_low(db.get_ref(), manager.get_ref(), user_id, bundle.device_id)
+                    .await;
+            }
+            HttpResponse::Ok().json(UserPreKeyBundlesResponse { user_id, devices })
+        }
+        Err(e) => {
+            if e == "Device not found" {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else if e == BUNDLE_RATE_LIMITED {
+                HttpResponse::TooManyRequests().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
 #[get("/api/v1/keys/{user_id}/devices/{device_id}")]
 pub async fn get_prekey_bundle(
+    http_req: HttpRequest,
     db: web::Data<DatabaseConnection>,
+    redis_conn: web::Data<MultiplexedConnection>,
+    manager: web::Data<ConnectionManager>,
     path: web::Path<(Uuid, i64)>,
 ) -> impl Responder {
+    let (requester_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
     let (user_id, device_id) = path.into_inner();
+    let mut redis = RedisClient::new(redis_conn.get_ref().clone());
 
-    match GetPreKeyBundleUseCase::execute(db.get_ref(), user_id, device_id).await {
-        Ok(response) => HttpResponse::Ok().json(response),
+    match GetPreKeyBundleUseCase::execute(
+        db.get_ref(),
+        &mut redis,
+        requester_id,
+        user_id,
+        device_id,
+    )
+    .await
+    {
+        Ok(response) => {
+            notify_if_prekeys_low(db.get_ref(), manager.get_ref(), user_id, device_id).await;
+            HttpResponse::Ok().json(response)
+        }
         Err(e) => {
             if e == "Device not found" {
                 HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else if e == BUNDLE_RATE_LIMITED {
+                HttpResponse::TooManyRequests().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[post("/api/v1/keys/prekeys")]
+pub async fn upload_prekeys(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    req: web::Json<UploadPreKeysRequest>,
+) -> impl Responder {
+    let (_, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match UploadPreKeysUseCase::execute(db.get_ref(), device_id, req.into_inner().one_time_prekeys)
+        .await
+    {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            if e.starts_with("Invalid key bundle") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else if e == PREKEY_ID_EXISTS {
+                HttpResponse::Conflict().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[get("/api/v1/keys/prekeys/count")]
+pub async fn get_prekey_count(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+) -> impl Responder {
+    let (_, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match GetPreKeyCountUseCase::execute(db.get_ref(), device_id).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+#[post("/api/v1/keys/signed-prekey")]
+pub async fn rotate_signed_prekey(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<Config>,
+    req: web::Json<SignedPreKeyDto>,
+) -> impl Responder {
+    let (_, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    let grace_period = chrono::Duration::seconds(config.signed_prekey_grace_period_seconds);
+
+    match RotateSignedPreKeyUseCase::execute(
+        db.get_ref(),
+        device_id,
+        req.into_inner(),
+        grace_period,
+    )
+    .await
+    {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            if e.starts_with("Invalid key bundle") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else if e == SIGNED_PREKEY_ID_EXISTS {
+                HttpResponse::Conflict().json(serde_json::json!({ "error": e }))
+            } else if e == "Device not found" {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
             } else {
                 HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
             }
         }
     }
 }
+
+/// Push `PreKeysLow` to the device if it is connected and its one-time prekeys are running out.
+pub(crate) async fn notify_if_prekeys_low(
+    db: &DatabaseConnection,
+    manager: &ConnectionManager,
+    user_id: Uuid,
+    device_id: i64,
+) {
+    let remaining = match CheckPreKeyStockUseCase::execute(db, device_id).await {
+        Ok(Some(remaining)) => remaining,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Failed to count prekeys for device {}: {}", device_id, e);
+            return;
+        }
+    };
+
+    let outbound = OutboundEvent::server(ServerEvent::PreKeysLow { remaining });
+    manager.send_to_device(&user_id, device_id, &outbound).await;
+}
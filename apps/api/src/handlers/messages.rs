@@ -0,0 +1,44 @@
+use super::auth::extract_auth_claims;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use application::chat::{
+    dtos::SyncMessagesQuery,
+    sync_messages::{SyncMessagesUseCase, MAX_SYNC_PAGE},
+};
+use sea_orm::DatabaseConnection;
+
+/// REST equivalent of the WebSocket `SyncRequest`, for clients on unreliable connections.
+#[get("/api/v1/messages/sync")]
+pub async fn sync_messages(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    query: web::Query<SyncMessagesQuery>,
+) -> impl Responder {
+    let (user_id, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match SyncMessagesUseCase::execute(
+        db.get_ref(),
+        user_id,
+        device_id,
+        query.cursor.as_deref(),
+        query.limit.unwrap_or(MAX_SYNC_PAGE),
+    )
+    .await
+    {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) => {
+            if e.starts_with("Invalid") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else if e.contains("not found") {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
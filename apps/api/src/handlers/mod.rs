@@ -1,3 +1,8 @@
 pub mod auth;
+pub mod conversations;
 pub mod health;
+pub mod identity;
 pub mod keys;
+pub mod messages;
+pub mod presence;
+pub mod sealed_sender;
@@ -0,0 +1,114 @@
+use super::auth::extract_auth_claims;
+use crate::config::Config;
+use crate::websocket::{
+    connection::ConnectionManager,
+    messages::{OutboundEvent, ServerEvent},
+};
+use actix_web::{get, put, web, HttpRequest, HttpResponse, Responder};
+use application::presence::{
+    dtos::{PresenceChange, PresenceSettingsDto},
+    use_cases::{
+        GetPresenceSettingsUseCase, MarkDeviceOfflineUseCase, MarkDeviceOnlineUseCase,
+        RefreshDevicePresenceUseCase, UpdatePresenceSettingsUseCase,
+    },
+};
+use infrastructure::redis::RedisClient;
+use sea_orm::DatabaseConnection;
+use uuid::Uuid;
+
+#[get("/api/v1/presence/settings")]
+pub async fn get_presence_settings(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match GetPresenceSettingsUseCase::execute(db.get_ref(), user_id).await {
+        Ok(settings) => HttpResponse::Ok().json(settings),
+        Err(e) => settings_error_response(e),
+    }
+}
+
+#[put("/api/v1/presence/settings")]
+pub async fn update_presence_settings(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    req: web::Json<PresenceSettingsDto>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match UpdatePresenceSettingsUseCase::execute(db.get_ref(), user_id, req.into_inner()).await {
+        Ok(settings) => HttpResponse::Ok().json(settings),
+        Err(e) => settings_error_response(e),
+    }
+}
+
+fn settings_error_response(e: String) -> HttpResponse {
+    if e.contains("not found") {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+    } else {
+        HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+    }
+}
+
+/// Marks a device online on connect or offline on disconnect, and tells the user's watchers
+/// when that changes whether the user as a whole is online.
+pub(crate) async fn update_presence(
+    db: &DatabaseConnection,
+    redis: &mut RedisClient,
+    manager: &ConnectionManager,
+    config: &Config,
+    user_id: Uuid,
+    device_id: i64,
+    online: bool,
+) {
+    let result = if online {
+        let ttl = config.presence_ttl_seconds();
+        MarkDeviceOnlineUseCase::execute(db, redis, user_id, device_id, ttl).await
+    } else {
+        MarkDeviceOfflineUseCase::execute(db, redis, user_id, device_id).await
+    };
+
+    match result {
+        Ok(Some(change)) => send_presence_change(manager, change).await,
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to update presence for User {}: {}", user_id, e),
+    }
+}
+
+/// Heartbeats only extend the device's expiry; watchers hear nothing unless the device had
+/// already expired.
+pub(crate) async fn refresh_presence(
+    db: &DatabaseConnection,
+    redis: &mut RedisClient,
+    manager: &ConnectionManager,
+    config: &Config,
+    user_id: Uuid,
+    device_id: i64,
+) {
+    let ttl = config.presence_ttl_seconds();
+    match RefreshDevicePresenceUseCase::execute(db, redis, user_id, device_id, ttl).await {
+        Ok(Some(change)) => send_presence_change(manager, change).await,
+        Ok(None) => {}
+        Err(e) => tracing::error!("Failed to refresh presence for User {}: {}", user_id, e),
+    }
+}
+
+pub(crate) async fn send_presence_change(manager: &ConnectionManager, change: PresenceChange) {
+    let outbound = OutboundEvent::server(ServerEvent::Presence(change.presence));
+    for recipient in &change.recipients {
+        manager.send_to_user(recipient, &outbound).await;
+    }
+}
@@ -0,0 +1,206 @@
+use super::auth::extract_auth_claims;
+use crate::config::Config;
+use crate::websocket::{
+    connection::ConnectionManager,
+    messages::{OutboundEvent, ServerEvent},
+};
+use actix_web::{get, post, put, web, HttpRequest, HttpResponse, Responder};
+use application::sealed_sender::{
+    dtos::{
+        AckSealedMessagesRequest, SealedMessageDto, SealedMessagesQuery, SendSealedMessageRequest,
+        SendSealedMessageResponse, SetUnidentifiedAccessRequest,
+    },
+    use_cases::{
+        AckSealedMessagesUseCase, IssueSenderCertificateUseCase, SendSealedMessageUseCase,
+        SetUnidentifiedAccessUseCase, SyncSealedMessagesUseCase, MAX_SEALED_MESSAGES_PAGE,
+        SEALED_ACCESS_DENIED, SEALED_MESSAGE_TOO_LARGE, SEALED_SEND_RATE_LIMITED,
+    },
+};
+use chat_core::signal::sealed_sender::ServerKey;
+use infrastructure::redis::RedisClient;
+use redis::aio::MultiplexedConnection;
+use sea_orm::DatabaseConnection;
+use uuid::Uuid;
+
+#[get("/api/v1/certificate/delivery")]
+pub async fn get_sender_certificate(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    config: web::Data<Config>,
+    server_key: Option<web::Data<ServerKey>>,
+) -> impl Responder {
+    let (user_id, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    // Only registered when SENDER_CERTIFICATE_SECRET is set
+    let Some(server_key) = server_key else {
+        return HttpResponse::ServiceUnavailable()
+            .json(serde_json::json!({ "error": "Sealed sender is not enabled" }));
+    };
+
+    match IssueSenderCertificateUseCase::execute(
+        db.get_ref(),
+        server_key.get_ref(),
+        user_id,
+        device_id,
+        config.sender_certificate_ttl_seconds,
+    )
+    .await
+    {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            if e == "Device not found" {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[put("/api/v1/profile/unidentified-access")]
+pub async fn set_unidentified_access(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    req: web::Json<SetUnidentifiedAccessRequest>,
+) -> impl Responder {
+    let (user_id, _) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+    let req = req.into_inner();
+
+    match SetUnidentifiedAccessUseCase::execute(
+        db.get_ref(),
+        user_id,
+        req.access_key,
+        req.allow_unrestricted,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            if e.starts_with("Access key") {
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+/// Deliberately unauthenticated: the sender proves nothing about who they are, only that
+/// they hold the recipient's access key.
+#[post("/api/v1/messages/sealed")]
+pub async fn send_sealed_message(
+    db: web::Data<DatabaseConnection>,
+    redis_conn: web::Data<MultiplexedConnection>,
+    manager: web::Data<ConnectionManager>,
+    req: web::Json<SendSealedMessageRequest>,
+) -> impl Responder {
+    let req = req.into_inner();
+    let (recipient_id, recipient_device_id) = (req.recipient_id, req.recipient_device_id);
+    let mut redis = RedisClient::new(redis_conn.get_ref().clone());
+
+    match SendSealedMessageUseCase::execute(db.get_ref(), &mut redis, req).await {
+        Ok(message) => {
+            let delivery_id = message.delivery_id;
+            forward_sealed_message(
+                manager.get_ref(),
+                recipient_id,
+                recipient_device_id,
+                message,
+            )
+            .await;
+            HttpResponse::Ok().json(SendSealedMessageResponse { delivery_id })
+        }
+        Err(e) => {
+            if e == SEALED_ACCESS_DENIED {
+                HttpResponse::Unauthorized().json(serde_json::json!({ "error": e }))
+            } else if e == SEALED_MESSAGE_TOO_LARGE {
+                HttpResponse::PayloadTooLarge().json(serde_json::json!({ "error": e }))
+            } else if e == SEALED_SEND_RATE_LIMITED {
+                HttpResponse::TooManyRequests().json(serde_json::json!({ "error": e }))
+            } else if e == "Device not found" {
+                HttpResponse::NotFound().json(serde_json::json!({ "error": e }))
+            } else {
+                HttpResponse::InternalServerError().json(serde_json::json!({ "error": e }))
+            }
+        }
+    }
+}
+
+#[get("/api/v1/messages/sealed")]
+pub async fn sync_sealed_messages(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    query: web::Query<SealedMessagesQuery>,
+) -> impl Responder {
+    let (_, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match SyncSealedMessagesUseCase::execute(
+        db.get_ref(),
+        device_id,
+        query.after_delivery_id,
+        query.limit.unwrap_or(MAX_SEALED_MESSAGES_PAGE),
+    )
+    .await
+    {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+#[post("/api/v1/messages/sealed/ack")]
+pub async fn ack_sealed_messages(
+    http_req: HttpRequest,
+    db: web::Data<DatabaseConnection>,
+    req: web::Json<AckSealedMessagesRequest>,
+) -> impl Responder {
+    let (_, device_id) = match extract_auth_claims(&http_req) {
+        Some(claims) => claims,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Unauthorized" }))
+        }
+    };
+
+    match AckSealedMessagesUseCase::execute(db.get_ref(), device_id, req.into_inner().delivery_ids)
+        .await
+    {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": e })),
+    }
+}
+
+/// Push a queued sealed message to the recipient device if it is online. It stays queued
+/// until the device acknowledges it.
+pub(crate) async fn forward_sealed_message(
+    manager: &ConnectionManager,
+    recipient_id: Uuid,
+    recipient_device_id: i64,
+    message: SealedMessageDto,
+) {
+    // Sealed: the event must not name the sender
+    let outbound = OutboundEvent::server(ServerEvent::SealedMessage {
+        delivery_id: message.delivery_id,
+        content: message.content,
+        received_at: message.received_at,
+    });
+    manager
+        .send_to_device(&recipient_id, recipient_device_id, &outbound)
+        .await;
+}
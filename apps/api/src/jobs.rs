@@ -0,0 +1,47 @@
+use crate::handlers::presence::send_presence_change;
+use crate::websocket::connection::ConnectionManager;
+use actix_web::web;
+use application::keys::use_cases::PruneSignedPreKeysUseCase;
+use application::presence::use_cases::ExpirePresenceUseCase;
+use infrastructure::redis::RedisClient;
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+
+/// Periodically delete replaced signed prekeys whose grace period has expired.
+pub fn spawn_signed_prekey_pruner(db: DatabaseConnection, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match PruneSignedPreKeysUseCase::execute(&db).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {} expired signed prekeys", pruned),
+                Err(e) => tracing::error!("Failed to prune signed prekeys: {}", e),
+            }
+        }
+    });
+}
+
+/// Periodically take offline users whose devices stopped sending heartbeats without
+/// disconnecting, e.g. because their node died.
+pub fn spawn_presence_sweeper(
+    db: DatabaseConnection,
+    mut redis: RedisClient,
+    manager: web::Data<ConnectionManager>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match ExpirePresenceUseCase::execute(&db, &mut redis).await {
+                Ok(changes) => {
+                    for change in changes {
+                        send_presence_change(manager.get_ref(), change).await;
+                    }
+                }
+                Err(e) => tracing::error!("Failed to expire presence: {}", e),
+            }
+        }
+    });
+}
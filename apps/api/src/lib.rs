@@ -1,4 +1,5 @@
 pub mod config;
 pub mod handlers;
+pub mod jobs;
 pub mod middleware;
 pub mod websocket;
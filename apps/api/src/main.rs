@@ -4,11 +4,13 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 pub mod config;
 pub mod handlers;
+mod jobs;
 mod middleware;
 mod websocket;
 
+use chat_core::signal::sealed_sender::ServerKey;
 use config::Config;
-use handlers::{auth, health, keys};
+use handlers::{auth, conversations, health, identity, keys, messages, presence, sealed_sender};
 use middleware::auth::AuthMiddleware;
 use websocket::{connection::ConnectionManager, handler::websocket_handler};
 
@@ -29,7 +31,29 @@ async fn main() -> anyhow::Result<()> {
     let db = infrastructure::database::init_database(&config.database_url).await?;
     let redis_conn = infrastructure::database::init_redis(&config.redis_url).await?;
 
-    let connection_manager = web::Data::new(ConnectionManager::new());
+    // Other API instances reach this node's sockets through Redis pub/sub
+    let connection_manager = web::Data::new(ConnectionManager::with_relay(
+        infrastructure::redis::RedisClient::new(redis_conn.clone()),
+        infrastructure::database::init_redis_pubsub(&config.redis_url).await?,
+    ));
+    let server_key = config
+        .sender_certificate_secret
+        .as_ref()
+        .map(|secret| web::Data::new(ServerKey::from_secret(secret.as_bytes())));
+    if server_key.is_none() {
+        tracing::warn!("SENDER_CERTIFICATE_SECRET is not set; sealed sender is disabled");
+    }
+
+    jobs::spawn_signed_prekey_pruner(
+        db.clone(),
+        std::time::Duration::from_secs(config.signed_prekey_prune_interval_seconds),
+    );
+    jobs::spawn_presence_sweeper(
+        db.clone(),
+        infrastructure::redis::RedisClient::new(redis_conn.clone()),
+        connection_manager.clone(),
+        std::time::Duration::from_secs(config.presence_sweep_interval_seconds),
+    );
 
     let server_addr = format!("{}:{}", config.server_host, config.server_port);
     tracing::info!("Server listening on {}", server_addr);
@@ -49,8 +73,14 @@ async fn main() -> anyhow::Result<()> {
             .app_data(web::Data::new(redis_conn.clone()))
             .app_data(config_data.clone())
             .app_data(connection_manager.clone())
+            .configure(|cfg| {
+                if let Some(server_key) = &server_key {
+                    cfg.app_data(server_key.clone());
+                }
+            })
             // Health
             .service(health::health_check)
+            .service(health::websocket_metrics)
             // Auth - OTP
             .service(auth::request_otp)
             .service(auth::verify_otp)
@@ -68,7 +98,40 @@ async fn main() -> anyhow::Result<()> {
             .service(auth::list_devices)
             .service(auth::unlink_device)
             // Keys
+            .service(keys::upload_prekeys)
+            .service(keys::get_prekey_count)
+            .service(keys::rotate_signed_prekey)
+            .service(keys::get_all_prekey_bundles)
             .service(keys::get_prekey_bundle)
+            // Conversations
+            .service(conversations::create_conversation)
+            .service(conversations::list_conversations)
+            .service(conversations::get_conversation)
+            .service(conversations::leave_conversation)
+            .service(conversations::update_group)
+            .service(conversations::add_members)
+            .service(conversations::remove_member)
+            .service(conversations::set_member_role)
+            .service(conversations::create_invite)
+            .service(conversations::list_invites)
+            .service(conversations::revoke_invite)
+            .service(conversations::join_via_invite)
+            // Messages
+            .service(messages::sync_messages)
+            // Presence
+            .service(presence::get_presence_settings)
+            .service(presence::update_presence_settings)
+            // Identity Verification
+            .service(identity::get_safety_number)
+            .service(identity::verify_contact)
+            .service(identity::unverify_contact)
+            .service(identity::sync_identity_changes)
+            // Sealed Sender
+            .service(sealed_sender::get_sender_certificate)
+            .service(sealed_sender::set_unidentified_access)
+            .service(sealed_sender::send_sealed_message)
+            .service(sealed_sender::sync_sealed_messages)
+            .service(sealed_sender::ack_sealed_messages)
             // WebSocket
             .service(websocket_handler)
     })
@@ -1,61 +1,401 @@
-use actix_ws::Session;
-use std::collections::HashMap;
+use super::messages::{Encoding, OutboundEvent, ServerEvent, DEFAULT_PROTOCOL_VERSION};
+use actix_ws::{CloseReason, Message, Session};
+use futures::StreamExt;
+use infrastructure::redis::RedisClient;
+use redis::aio::{PubSub, PubSubSink};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{
+    self,
+    error::{SendTimeoutError, TrySendError},
+};
+use tokio::sync::{Mutex, Notify, RwLock};
 use uuid::Uuid;
 
 pub type ConnectionId = Uuid;
 
+/// How long a close frame may wait behind frames the client has not read.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the queue may stay too full to take the oldest deferred frame before the
+/// connection counts as persistently overflowed.
+const OVERFLOW_GRACE: Duration = Duration::from_secs(5);
+
+/// Frames deferred behind a full queue, in queue capacities; a burst beyond this is
+/// treated as persistent overflow without waiting out the grace period.
+const MAX_DEFERRED_QUEUES: usize = 4;
+
 #[derive(Clone)]
 pub struct WsConnection {
     pub user_id: Uuid,
     pub device_id: i64,
     pub conn_id: ConnectionId,
-    pub session: Session,
+    /// Server-to-client schema negotiated on connect
+    pub protocol_version: u16,
+    /// Wire format of server frames negotiated on connect
+    pub encoding: Encoding,
+    /// Frames waiting for the connection's writer task
+    outbound: mpsc::Sender<Message>,
+    /// Frames that found the queue full; later frames queue behind them
+    deferred: Arc<std::sync::Mutex<Deferred>>,
+    /// Set once the queue stayed full past the grace period; nothing is queued after
+    overflowed: Arc<AtomicBool>,
+    overflow: Arc<Notify>,
+    /// Last sequence number queued. Held while queueing so frames queue in sequence order.
+    last_seq: Arc<std::sync::Mutex<u64>>,
+}
+
+impl WsConnection {
+    /// Spawns the task that writes the connection's queued frames to `session`, so that
+    /// senders never wait on a client that reads slowly.
+    pub fn new(
+        user_id: Uuid,
+        device_id: i64,
+        session: Session,
+        protocol_version: u16,
+        encoding: Encoding,
+        queue_capacity: usize,
+    ) -> Self {
+        let (outbound, frames) = mpsc::channel(queue_capacity);
+        actix_web::rt::spawn(write_frames(session, frames));
+
+        Self {
+            user_id,
+            device_id,
+            conn_id: Uuid::new_v4(),
+            protocol_version,
+            encoding,
+            outbound,
+            deferred: Arc::new(std::sync::Mutex::new(Deferred::default())),
+            overflowed: Arc::new(AtomicBool::new(false)),
+            overflow: Arc::new(Notify::new()),
+            last_seq: Arc::new(std::sync::Mutex::new(0)),
+        }
+    }
+
+    /// Encode `event` for this connection's protocol version and encoding and queue it.
+    ///
+    /// When the client falls behind, typing indicators are dropped once the queue is half
+    /// full. Anything else that finds it full is deferred until the writer makes room, so
+    /// a short burst only delays delivery. The connection is marked overflowed, and loses
+    /// everything still deferred, once the queue stays full past the grace period or the
+    /// deferred frames outgrow their bound; stored messages reach the device through sync
+    /// when it reconnects.
+    pub fn send(&self, event: &OutboundEvent) {
+        if self.overflowed.load(Ordering::Relaxed) {
+            return;
+        }
+        if matches!(event.event, ServerEvent::Typing { .. })
+            && self.outbound.capacity() < self.outbound.max_capacity() / 2
+        {
+            tracing::debug!(
+                "Dropping typing event for backed up connection {}",
+                self.conn_id
+            );
+            return;
+        }
+
+        let mut last_seq = self.last_seq.lock().unwrap();
+        let seq = *last_seq + 1;
+        let frame = if self.protocol_version == DEFAULT_PROTOCOL_VERSION {
+            self.encoding
+                .encode(&event.to_v1(self.user_id, self.device_id))
+        } else {
+            self.encoding
+                .encode(&event.envelope(self.protocol_version, seq))
+        };
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::error!("Failed to encode event for {}: {}", self.conn_id, e);
+                return;
+            }
+        };
+
+        let mut deferred = self.deferred.lock().unwrap();
+        if deferred.flushing {
+            if deferred.frames.len() >= self.outbound.max_capacity() * MAX_DEFERRED_QUEUES {
+                deferred.frames.clear();
+                self.mark_overflowed();
+                return;
+            }
+            deferred.frames.push_back(frame);
+            *last_seq = seq;
+            return;
+        }
+
+        match self.outbound.try_send(frame) {
+            Ok(()) => *last_seq = seq,
+            Err(TrySendError::Full(frame)) => {
+                tracing::debug!("Deferring events for backed up connection {}", self.conn_id);
+                deferred.frames.push_back(frame);
+                deferred.flushing = true;
+                *last_seq = seq;
+                tokio::spawn(self.clone().flush_deferred());
+            }
+            // The writer stops once the session is closed, and the connection with it
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    /// Moves deferred frames onto the queue as the writer makes room. Frames sent meanwhile
+    /// are deferred too until this catches up, so none can overtake another.
+    async fn flush_deferred(self) {
+        loop {
+            let frame = {
+                let mut deferred = self.deferred.lock().unwrap();
+                match deferred.frames.pop_front() {
+                    Some(frame) => frame,
+                    None => {
+                        deferred.flushing = false;
+                        return;
+                    }
+                }
+            };
+            match self.outbound.send_timeout(frame, OVERFLOW_GRACE).await {
+                Ok(()) => {}
+                Err(SendTimeoutError::Timeout(_)) => {
+                    self.deferred.lock().unwrap().frames.clear();
+                    self.mark_overflowed();
+                    return;
+                }
+                Err(SendTimeoutError::Closed(_)) => return,
+            }
+        }
+    }
+
+    fn mark_overflowed(&self) {
+        if !self.overflowed.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Outbound queue of connection {} overflowed", self.conn_id);
+            self.overflow.notify_one();
+        }
+    }
+
+    /// Resolves once the connection's queue has overflowed; the caller should close it.
+    pub async fn overflowed(&self) {
+        self.overflow.notified().await
+    }
+}
+
+#[derive(Default)]
+struct Deferred {
+    frames: VecDeque<Message>,
+    /// A task is moving `frames` onto the queue; new frames must wait behind it
+    flushing: bool,
+}
+
+async fn write_frames(mut session: Session, mut frames: mpsc::Receiver<Message>) {
+    while let Some(frame) = frames.recv().await {
+        let written = match frame {
+            Message::Text(text) => session.text(text).await,
+            Message::Binary(bytes) => session.binary(bytes).await,
+            _ => unreachable!("events encode to text or binary frames"),
+        };
+        if written.is_err() {
+            break;
+        }
+    }
+}
+
+/// Close `session` without waiting indefinitely on a client that stopped reading; the
+/// session counts as closed for every clone either way.
+pub async fn close_session(session: Session, reason: CloseReason) {
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, session.close(Some(reason))).await;
+}
+
+type Connections = Arc<RwLock<HashMap<ConnectionId, WsConnection>>>;
+type UserConnections = Arc<RwLock<HashMap<Uuid, Vec<ConnectionId>>>>;
+
+/// Routes frames between API nodes. Each node subscribes to `ws:user:{user_id}` while it
+/// holds a connection for that user, so a frame for a user with no local connection is
+/// published there and written out by whichever nodes do.
+struct Relay {
+    node_id: Uuid,
+    publisher: Mutex<RedisClient>,
+    /// Also serialises subscription changes with the connection bookkeeping that decides them
+    subscriptions: Mutex<PubSubSink>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RelayEnvelope<E = OutboundEvent> {
+    origin: Uuid,
+    user_id: Uuid,
+    /// `None` targets every connection of the user
+    device_id: Option<i64>,
+    event: E,
+}
+
+fn user_channel(user_id: &Uuid) -> String {
+    format!("ws:user:{}", user_id)
+}
+
+/// Counters for this node's connections; each node reports its own.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConnectionMetrics {
+    pub open_connections: usize,
+    /// Connections closed because they went quiet past the idle timeout
+    pub reaped_connections: u64,
+    /// Connections closed because they read too slowly to keep their queue from overflowing
+    pub overflowed_connections: u64,
 }
 
 pub struct ConnectionManager {
-    connections: Arc<RwLock<HashMap<ConnectionId, WsConnection>>>,
-    user_connections: Arc<RwLock<HashMap<Uuid, Vec<ConnectionId>>>>,
+    connections: Connections,
+    user_connections: UserConnections,
+    relay: Option<Relay>,
+    reaped: AtomicU64,
+    overflowed: AtomicU64,
 }
 
 impl ConnectionManager {
+    /// A manager that only knows about connections on this node.
     pub fn new() -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             user_connections: Arc::new(RwLock::new(HashMap::new())),
+            relay: None,
+            reaped: AtomicU64::new(0),
+            overflowed: AtomicU64::new(0),
         }
     }
 
+    /// A manager that reaches connections on other nodes through Redis pub/sub.
+    pub fn with_relay(publisher: RedisClient, pubsub: PubSub) -> Self {
+        let (sink, mut stream) = pubsub.split();
+        let manager = Self {
+            relay: Some(Relay {
+                node_id: Uuid::new_v4(),
+                publisher: Mutex::new(publisher),
+                subscriptions: Mutex::new(sink),
+            }),
+            ..Self::new()
+        };
+
+        let node_id = manager.relay.as_ref().map(|relay| relay.node_id);
+        let connections = manager.connections.clone();
+        let user_connections = manager.user_connections.clone();
+        tokio::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                let envelope = match msg
+                    .get_payload::<String>()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|payload| Ok(serde_json::from_str::<RelayEnvelope>(&payload)?))
+                {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        tracing::warn!("Dropping malformed relay message: {}", e);
+                        continue;
+                    }
+                };
+                // Our own user-wide publishes were already written out locally
+                if Some(envelope.origin) == node_id {
+                    continue;
+                }
+                deliver_local(
+                    &connections,
+                    &user_connections,
+                    &envelope.user_id,
+                    envelope.device_id,
+                    &envelope.event,
+                )
+                .await;
+            }
+            tracing::error!("Redis relay subscription closed");
+        });
+
+        manager
+    }
+
     pub async fn add_connection(&self, conn: WsConnection) {
         let conn_id = conn.conn_id;
         let user_id = conn.user_id;
+        let mut subscriptions = match &self.relay {
+            Some(relay) => Some(relay.subscriptions.lock().await),
+            None => None,
+        };
 
         self.connections.write().await.insert(conn_id, conn);
 
-        self.user_connections
-            .write()
-            .await
-            .entry(user_id)
-            .or_insert_with(Vec::new)
-            .push(conn_id);
+        let first = {
+            let mut user_connections = self.user_connections.write().await;
+            let conns = user_connections.entry(user_id).or_insert_with(Vec::new);
+            conns.push(conn_id);
+            conns.len() == 1
+        };
+
+        if let (true, Some(sink)) = (first, subscriptions.as_mut()) {
+            if let Err(e) = sink.subscribe(user_channel(&user_id)).await {
+                tracing::error!("Failed to subscribe relay for User {}: {}", user_id, e);
+            }
+        }
     }
 
     pub async fn remove_connection(&self, conn_id: &ConnectionId) {
-        if let Some(conn) = self.connections.write().await.remove(conn_id) {
-            if let Some(conns) = self.user_connections.write().await.get_mut(&conn.user_id) {
-                conns.retain(|id| id != conn_id);
+        let mut subscriptions = match &self.relay {
+            Some(relay) => Some(relay.subscriptions.lock().await),
+            None => None,
+        };
+
+        let Some(conn) = self.connections.write().await.remove(conn_id) else {
+            return;
+        };
+
+        let last = {
+            let mut user_connections = self.user_connections.write().await;
+            match user_connections.get_mut(&conn.user_id) {
+                Some(conns) => {
+                    conns.retain(|id| id != conn_id);
+                    if conns.is_empty() {
+                        user_connections.remove(&conn.user_id);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                None => false,
+            }
+        };
+
+        if let (true, Some(sink)) = (last, subscriptions.as_mut()) {
+            if let Err(e) = sink.unsubscribe(user_channel(&conn.user_id)).await {
+                tracing::error!(
+                    "Failed to unsubscribe relay for User {}: {}",
+                    conn.user_id,
+                    e
+                );
             }
         }
     }
 
-    #[allow(dead_code)]
+    /// Count a connection closed for going idle; it is removed like any other.
+    pub fn record_reaped(&self) {
+        self.reaped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection closed for overflowing its outbound queue.
+    pub fn record_overflowed(&self) {
+        self.overflowed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub async fn metrics(&self) -> ConnectionMetrics {
+        ConnectionMetrics {
+            open_connections: self.connections.read().await.len(),
+            reaped_connections: self.reaped.load(Ordering::Relaxed),
+            overflowed_connections: self.overflowed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Connections to this node only; use `send_to_user` to reach every node.
     pub async fn get_user_connections(&self, user_id: &Uuid) -> Vec<WsConnection> {
         let user_conns = self.user_connections.read().await;
         let all_conns = self.connections.read().await;
-        
+
         if let Some(conn_ids) = user_conns.get(user_id) {
-            conn_ids.iter()
+            conn_ids
+                .iter()
                 .filter_map(|id| all_conns.get(id).cloned())
                 .collect()
         } else {
@@ -63,11 +403,75 @@ impl ConnectionManager {
         }
     }
 
-    #[allow(dead_code)]
-    pub async fn get_device_connection(&self, user_id: &Uuid, device_id: i64) -> Option<WsConnection> {
+    /// A connection to this node only; use `send_to_device` to reach every node.
+    pub async fn get_device_connection(
+        &self,
+        user_id: &Uuid,
+        device_id: i64,
+    ) -> Option<WsConnection> {
         let connections = self.get_user_connections(user_id).await;
         connections.into_iter().find(|c| c.device_id == device_id)
     }
+
+    /// Write `event` to one device wherever it is connected. Returns `false` when no node
+    /// holds a connection for the user, so the device is certainly offline.
+    pub async fn send_to_device(
+        &self,
+        user_id: &Uuid,
+        device_id: i64,
+        event: &OutboundEvent,
+    ) -> bool {
+        if let Some(conn) = self.get_device_connection(user_id, device_id).await {
+            conn.send(event);
+            return true;
+        }
+        self.publish(user_id, Some(device_id), event).await
+    }
+
+    /// Write `event` to every connected device of a user, on any node. Returns `false`
+    /// when none is online.
+    pub async fn send_to_user(&self, user_id: &Uuid, event: &OutboundEvent) -> bool {
+        let local = deliver_local(
+            &self.connections,
+            &self.user_connections,
+            user_id,
+            None,
+            event,
+        )
+        .await;
+        let relayed = self.publish(user_id, None, event).await;
+        local || relayed
+    }
+
+    async fn publish(&self, user_id: &Uuid, device_id: Option<i64>, event: &OutboundEvent) -> bool {
+        let Some(relay) = &self.relay else {
+            return false;
+        };
+
+        let envelope = RelayEnvelope {
+            origin: relay.node_id,
+            user_id: *user_id,
+            device_id,
+            event,
+        };
+        match relay
+            .publisher
+            .lock()
+            .await
+            .publish(&user_channel(user_id), &envelope)
+            .await
+        {
+            // A node subscribed for its own connections does not count as a recipient
+            Ok(receivers) => {
+                let own = self.user_connections.read().await.contains_key(user_id);
+                receivers > usize::from(own)
+            }
+            Err(e) => {
+                tracing::error!("Failed to relay message for User {}: {}", user_id, e);
+                false
+            }
+        }
+    }
 }
 
 impl Default for ConnectionManager {
@@ -75,3 +479,30 @@ impl Default for ConnectionManager {
         Self::new()
     }
 }
+
+async fn deliver_local(
+    connections: &Connections,
+    user_connections: &UserConnections,
+    user_id: &Uuid,
+    device_id: Option<i64>,
+    event: &OutboundEvent,
+) -> bool {
+    let targets: Vec<WsConnection> = {
+        let user_conns = user_connections.read().await;
+        let all_conns = connections.read().await;
+        user_conns
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| all_conns.get(id))
+            .filter(|conn| device_id.map_or(true, |d| conn.device_id == d))
+            .cloned()
+            .collect()
+    };
+
+    let delivered = !targets.is_empty();
+    for conn in targets {
+        conn.send(event);
+    }
+    delivered
+}
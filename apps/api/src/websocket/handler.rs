@@ -1,19 +1,44 @@
-use super::connection::{ConnectionManager, WsConnection};
+use super::connection::{close_session, ConnectionManager, WsConnection};
+use super::messages::{
+    Encoding, OutboundEvent, ServerEvent, DEFAULT_PROTOCOL_VERSION, LATEST_PROTOCOL_VERSION,
+};
 use crate::config::Config;
+use crate::handlers::presence::{refresh_presence, update_presence};
 use actix_web::{get, web, Error, HttpRequest, HttpResponse};
-use actix_ws::Message;
+use actix_ws::{CloseCode, CloseReason, Message};
 use application::auth::dtos::Claims;
 use futures::StreamExt;
+use infrastructure::redis::RedisClient;
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::Deserialize;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct WsQuery {
     token: String,
+    /// Newest server-to-client protocol version the client understands
+    v: Option<u16>,
+    /// Wire format of server frames; JSON unless the client asks for `msgpack`
+    #[serde(default)]
+    encoding: Encoding,
 }
 
-use application::chat::{dtos::SendMessageRequest, use_cases::SendMessageUseCase};
+use application::chat::{
+    conversations::{ensure_members, NOT_A_MEMBER},
+    dtos::{
+        MultiDeviceSendOutcome, SendGroupMessageRequest, SendMessageRequest,
+        SendMultiDeviceMessageRequest,
+    },
+    sync_messages::{SyncMessagesUseCase, MAX_SYNC_PAGE},
+    use_cases::{SendGroupMessageUseCase, SendMessageUseCase, STALE_SENDER_KEY},
+};
+use application::presence::use_cases::{SubscribePresenceUseCase, UnsubscribePresenceUseCase};
+use application::sealed_sender::use_cases::{
+    SyncSealedMessagesUseCase, MAX_SEALED_MESSAGES_PAGE, SEALED_MESSAGE_TOO_LARGE,
+    SEALED_SEND_RATE_LIMITED,
+};
+use redis::aio::MultiplexedConnection;
 use sea_orm::DatabaseConnection;
 
 #[get("/ws/")]
@@ -23,6 +48,7 @@ pub async fn websocket_handler(
     manager: web::Data<ConnectionManager>,
     config: web::Data<Config>,
     db: web::Data<DatabaseConnection>,
+    redis_conn: web::Data<MultiplexedConnection>,
     query: web::Query<WsQuery>,
 ) -> Result<HttpResponse, Error> {
     // Validate JWT
@@ -43,28 +69,96 @@ pub async fn websocket_handler(
     };
     let device_id = claims.device_id;
 
+    let protocol_version = match query.v {
+        None => DEFAULT_PROTOCOL_VERSION,
+        Some(0) => return Ok(HttpResponse::BadRequest().finish()),
+        Some(v) => v.min(LATEST_PROTOCOL_VERSION),
+    };
+
     let (response, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
 
-    let conn_id = Uuid::new_v4();
-    let ws_conn = WsConnection {
+    let ws_conn = WsConnection::new(
         user_id,
         device_id,
-        conn_id,
-        session: session.clone(),
-    };
+        session.clone(),
+        protocol_version,
+        query.encoding,
+        config.ws_outbound_queue_capacity,
+    );
+    let conn_id = ws_conn.conn_id;
 
     manager.add_connection(ws_conn.clone()).await;
     tracing::info!("User {} Device {} connected (Conn ID: {})", user_id, device_id, conn_id);
 
     let db = db.get_ref().clone();
 
+    // Devices that went offline while their prekeys were being claimed learn about it now
+    crate::handlers::keys::notify_if_prekeys_low(&db, manager.get_ref(), user_id, device_id).await;
+
+    let mut redis = RedisClient::new(redis_conn.get_ref().clone());
+    update_presence(
+        &db,
+        &mut redis,
+        manager.get_ref(),
+        &config,
+        user_id,
+        device_id,
+        true,
+    )
+    .await;
+
+    let heartbeat_interval = Duration::from_secs(config.ws_heartbeat_interval_seconds);
+    let idle_timeout = Duration::from_secs(config.ws_idle_timeout_seconds);
+
     actix_web::rt::spawn(async move {
-        while let Some(Ok(msg)) = msg_stream.next().await {
+        // Queued messages are pushed without waiting for a SyncRequest, one page at a time:
+        // the client pulls the rest with the returned cursor at its own pace
+        send_sync_page(&db, &ws_conn, None, MAX_SYNC_PAGE).await;
+        send_sealed_sync_page(&db, &ws_conn, None, MAX_SEALED_MESSAGES_PAGE).await;
+
+        // Half-open sockets never error on their own, so anything silent for too long is closed
+        let mut heartbeat = tokio::time::interval_at(
+            tokio::time::Instant::now() + heartbeat_interval,
+            heartbeat_interval,
+        );
+        let mut last_heard = Instant::now();
+
+        loop {
+            let msg = tokio::select! {
+                msg = msg_stream.next() => match msg {
+                    Some(Ok(msg)) => msg,
+                    _ => break,
+                },
+                _ = heartbeat.tick() => {
+                    if last_heard.elapsed() >= idle_timeout {
+                        tracing::info!("Reaping idle connection {} (User {} Device {})", conn_id, user_id, device_id);
+                        manager.record_reaped();
+                        let reason = CloseReason { code: CloseCode::Away, description: Some("idle timeout".to_string()) };
+                        close_session(session, reason).await;
+                        break;
+                    }
+                    if session.ping(b"").await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                _ = ws_conn.overflowed() => {
+                    // The queued events it missed are still stored; it catches up on reconnect
+                    tracing::warn!("Closing connection {} that fell behind (User {} Device {})", conn_id, user_id, device_id);
+                    manager.record_overflowed();
+                    let reason = CloseReason { code: CloseCode::Again, description: Some("outbound queue overflow".to_string()) };
+                    close_session(session, reason).await;
+                    break;
+                }
+            };
+            last_heard = Instant::now();
+
             match msg {
-                Message::Text(text) => {
-                    tracing::debug!("Received text message: {}", text);
-                    // Parse message
-                    match serde_json::from_str::<super::messages::WsMessage>(&text) {
+                Message::Text(_) | Message::Binary(_) => {
+                    // Text frames are JSON and binary frames MessagePack, whatever the server writes
+                    let (encoding, frame) = Encoding::of(&msg).expect("data frame");
+                    tracing::debug!("Received {:?} frame: {} bytes", encoding, frame.len());
+                    match encoding.decode::<super::messages::WsMessage>(frame) {
                         Ok(ws_msg) => {
                             match ws_msg {
                                 super::messages::WsMessage::SignalMessage { conversation_id, client_message_id, recipient_id, recipient_device_id, content } => {
@@ -81,84 +175,160 @@ pub async fn websocket_handler(
                                         content: content.clone(),
                                     };
 
-                                    if let Err(e) = SendMessageUseCase::execute(&db, req).await {
-                                        tracing::error!("Failed to persist message: {}", e);
-                                        // TODO: Send error back to client?
-                                    }
+                                    let sent = match SendMessageUseCase::execute(&db, req).await {
+                                        Ok(sent) => {
+                                            send_ack(&ws_conn, client_message_id, sent.message_id, sent.sent_at);
+                                            sent
+                                        }
+                                        Err(e) => {
+                                            tracing::warn!("Rejected message from User {} Device {}: {}", user_id, device_id, e);
+                                            send_error(&ws_conn, error_code(&e, "send_failed"), e, Some(client_message_id));
+                                            continue;
+                                        }
+                                    };
 
                                     // Forward to specific device
-                                    if let Some(mut target_conn) = manager.get_device_connection(&recipient_id, recipient_device_id).await {
-                                        let outbound = super::messages::WsMessage::SignalMessage {
-                                            conversation_id,
-                                            client_message_id,
-                                            recipient_id, // In outbound, this might be sender_id? Or client knows context.
-                                            recipient_device_id,
-                                            content,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = target_conn.session.text(json).await;
-                                        }
-                                    } else {
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::Message {
+                                        message_id: sent.message_id,
+                                        conversation_id,
+                                        client_message_id,
+                                        content,
+                                        sent_at: sent.sent_at,
+                                    });
+                                    if !manager.send_to_device(&recipient_id, recipient_device_id, &outbound).await {
                                         tracing::warn!("Recipient {} device {} not online", recipient_id, recipient_device_id);
                                         // Message is already stored in DB, so it will be fetched on sync (Phase 4 task)
                                     }
                                 }
-                                super::messages::WsMessage::Ack { message_id } => {
-                                    tracing::info!("Received Ack for message {}", message_id);
-                                    // TODO: Update message status in DB
+                                super::messages::WsMessage::MultiDeviceSend { conversation_id, client_message_id, recipient_id, messages } => {
+                                    let req = SendMultiDeviceMessageRequest {
+                                        sender_id: user_id,
+                                        sender_device_id: device_id,
+                                        recipient_id,
+                                        conversation_id,
+                                        client_message_id,
+                                        messages,
+                                    };
+
+                                    let (sent, deliveries) = match SendMessageUseCase::execute_multi_device(&db, req).await {
+                                        Ok(MultiDeviceSendOutcome::Sent { sent, deliveries }) => {
+                                            send_ack(&ws_conn, client_message_id, sent.message_id, sent.sent_at);
+                                            (sent, deliveries)
+                                        }
+                                        Ok(MultiDeviceSendOutcome::StaleDevices(stale)) => {
+                                            let outbound = OutboundEvent::server(ServerEvent::StaleDevices {
+                                                client_message_id,
+                                                missing: stale.missing,
+                                                extra: stale.extra,
+                                            });
+                                            ws_conn.send(&outbound);
+                                            continue;
+                                        }
+                                        Err(e) => {
+                                            tracing::warn!("Rejected message from User {} Device {}: {}", user_id, device_id, e);
+                                            send_error(&ws_conn, error_code(&e, "send_failed"), e, Some(client_message_id));
+                                            continue;
+                                        }
+                                    };
+
+                                    for delivery in deliveries {
+                                        let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::Message {
+                                            message_id: sent.message_id,
+                                            conversation_id,
+                                            client_message_id,
+                                            content: delivery.content,
+                                            sent_at: sent.sent_at,
+                                        });
+                                        manager.send_to_device(&delivery.recipient_id, delivery.recipient_device_id, &outbound).await;
+                                    }
                                 }
-                                super::messages::WsMessage::SyncRequest { last_message_id } => {
-                                    tracing::info!("Received SyncRequest from User {} Device {}", user_id, device_id);
-                                    match application::chat::sync_messages::SyncMessagesUseCase::execute(&db, user_id, device_id, last_message_id).await {
-                                        Ok(messages) => {
-                                            let response = super::messages::WsMessage::SyncResponse { messages };
-                                            if let Ok(json) = serde_json::to_string(&response) {
-                                                let _ = session.text(json).await;
-                                            }
+                                super::messages::WsMessage::GroupSend { conversation_id, client_message_id, sender_key_epoch, content, distributions } => {
+                                    let req = SendGroupMessageRequest {
+                                        sender_id: user_id,
+                                        sender_device_id: device_id,
+                                        conversation_id,
+                                        client_message_id,
+                                        sender_key_epoch,
+                                        content: content.clone(),
+                                        distributions,
+                                    };
+
+                                    let fanout = match SendGroupMessageUseCase::execute(&db, req).await {
+                                        Ok(fanout) => {
+                                            send_ack(&ws_conn, client_message_id, fanout.message_id, fanout.sent_at);
+                                            fanout
                                         }
                                         Err(e) => {
-                                            tracing::error!("Failed to sync messages: {}", e);
+                                            tracing::warn!("Rejected group message from User {} Device {}: {}", user_id, device_id, e);
+                                            send_error(&ws_conn, error_code(&e, "send_failed"), e, Some(client_message_id));
+                                            continue;
                                         }
+                                    };
+
+                                    // One ciphertext for everyone; only the distribution differs per device
+                                    for delivery in fanout.deliveries {
+                                        let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::GroupMessage {
+                                            message_id: fanout.message_id,
+                                            conversation_id,
+                                            client_message_id,
+                                            content: content.clone(),
+                                            sender_key_distribution: delivery.sender_key_distribution,
+                                            sent_at: fanout.sent_at,
+                                        });
+                                        manager.send_to_device(&delivery.recipient_id, delivery.recipient_device_id, &outbound).await;
                                     }
                                 }
-                                super::messages::WsMessage::SdpOffer { recipient_id, recipient_device_id, sdp } => {
-                                    tracing::info!("Routing SdpOffer to User {} Device {}", recipient_id, recipient_device_id);
-                                    if let Some(mut target_conn) = manager.get_device_connection(&recipient_id, recipient_device_id).await {
-                                        let outbound = super::messages::WsMessage::SdpOffer {
-                                            recipient_id: user_id, // From sender
-                                            recipient_device_id: device_id,
-                                            sdp,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = target_conn.session.text(json).await;
+                                super::messages::WsMessage::SealedSend { recipient_id, recipient_device_id, access_key, content } => {
+                                    // The socket identity is deliberately ignored; only the access key authorises delivery
+                                    let req = application::sealed_sender::dtos::SendSealedMessageRequest {
+                                        recipient_id,
+                                        recipient_device_id,
+                                        access_key,
+                                        content,
+                                    };
+                                    match application::sealed_sender::use_cases::SendSealedMessageUseCase::execute(&db, &mut redis, req).await {
+                                        Ok(message) => {
+                                            crate::handlers::sealed_sender::forward_sealed_message(&manager, recipient_id, recipient_device_id, message).await;
+                                        }
+                                        Err(e) => {
+                                            send_error(&ws_conn, error_code(&e, "sealed_send_failed"), e, None);
                                         }
                                     }
                                 }
+                                super::messages::WsMessage::SealedAck { delivery_ids } => {
+                                    if let Err(e) = application::sealed_sender::use_cases::AckSealedMessagesUseCase::execute(&db, device_id, delivery_ids).await {
+                                        tracing::error!("Failed to ack sealed messages: {}", e);
+                                    }
+                                }
+                                super::messages::WsMessage::Ack { message_id } => {
+                                    tracing::debug!("Received Ack for message {} from Device {}", message_id, device_id);
+                                    if let Err(e) = application::chat::update_status::AckMessageUseCase::execute(&db, device_id, message_id).await {
+                                        tracing::error!("Failed to ack message {}: {}", message_id, e);
+                                    }
+                                }
+                                super::messages::WsMessage::SyncRequest { cursor, limit } => {
+                                    tracing::info!("Received SyncRequest from User {} Device {}", user_id, device_id);
+                                    let limit = limit.unwrap_or(MAX_SYNC_PAGE);
+                                    send_sync_page(&db, &ws_conn, cursor.as_deref(), limit).await;
+                                }
+                                super::messages::WsMessage::SealedSyncRequest { after_delivery_id, limit } => {
+                                    let limit = limit.unwrap_or(MAX_SEALED_MESSAGES_PAGE);
+                                    send_sealed_sync_page(&db, &ws_conn, after_delivery_id, limit).await;
+                                }
+                                super::messages::WsMessage::SdpOffer { recipient_id, recipient_device_id, sdp } => {
+                                    tracing::info!("Routing SdpOffer to User {} Device {}", recipient_id, recipient_device_id);
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::SdpOffer { sdp });
+                                    manager.send_to_device(&recipient_id, recipient_device_id, &outbound).await;
+                                }
                                 super::messages::WsMessage::SdpAnswer { recipient_id, recipient_device_id, sdp } => {
                                     tracing::info!("Routing SdpAnswer to User {} Device {}", recipient_id, recipient_device_id);
-                                    if let Some(mut target_conn) = manager.get_device_connection(&recipient_id, recipient_device_id).await {
-                                        let outbound = super::messages::WsMessage::SdpAnswer {
-                                            recipient_id: user_id,
-                                            recipient_device_id: device_id,
-                                            sdp,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = target_conn.session.text(json).await;
-                                        }
-                                    }
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::SdpAnswer { sdp });
+                                    manager.send_to_device(&recipient_id, recipient_device_id, &outbound).await;
                                 }
                                 super::messages::WsMessage::IceCandidate { recipient_id, recipient_device_id, candidate } => {
                                     tracing::info!("Routing IceCandidate to User {} Device {}", recipient_id, recipient_device_id);
-                                    if let Some(mut target_conn) = manager.get_device_connection(&recipient_id, recipient_device_id).await {
-                                        let outbound = super::messages::WsMessage::IceCandidate {
-                                            recipient_id: user_id,
-                                            recipient_device_id: device_id,
-                                            candidate,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = target_conn.session.text(json).await;
-                                        }
-                                    }
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::IceCandidate { candidate });
+                                    manager.send_to_device(&recipient_id, recipient_device_id, &outbound).await;
                                 }
                                 super::messages::WsMessage::DeliveryStatus { message_id, conversation_id, sender_id, status } => {
                                     tracing::info!("Received DeliveryStatus for msg {} from User {} Device {}", message_id, user_id, device_id);
@@ -170,73 +340,80 @@ pub async fn websocket_handler(
                                         super::messages::DeliveryStatusType::Read => application::chat::dtos::DeliveryStatusType::Read,
                                     };
 
-                                    if let Err(e) = application::chat::update_status::UpdateDeliveryStatusUseCase::execute(&db, message_id, device_id, app_status).await {
-                                        tracing::error!("Failed to update delivery status: {}", e);
+                                    if let Err(e) = application::chat::update_status::UpdateDeliveryStatusUseCase::execute(&db, user_id, device_id, conversation_id, sender_id, message_id, app_status).await {
+                                        tracing::warn!("Rejected delivery status from User {} Device {}: {}", user_id, device_id, e);
+                                        send_error(&ws_conn, error_code(&e, "receipt_failed"), e, None);
+                                        continue;
                                     }
 
-                                    // 2. Forward to original sender (if online)
-                                    // We need to find all connections of the sender_id
-                                    let sender_conns = manager.get_user_connections(&sender_id).await;
-                                    for mut conn in sender_conns {
-                                        let outbound = super::messages::WsMessage::DeliveryStatus {
-                                            message_id,
-                                            conversation_id,
-                                            sender_id, // Echo back? Or maybe recipient_id? The client needs to know WHO read it.
-                                            // Actually, the message structure might need 'recipient_id' (who read it) for the sender to know.
-                                            // But 'user_id' (from context) IS the one who read it.
-                                            // Let's assume the client uses the context of who sent this status update.
-                                            // But wait, WsMessage::DeliveryStatus definition:
-                                            // sender_id: Uuid, // The original sender who should receive this update
-                                            // We should probably include 'updated_by' or similar if it's a group, but for 1-on-1, the sender knows it's the other person.
-                                            status,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = conn.session.text(json).await;
-                                        }
-                                    }
+                                    // 2. Forward to original sender (if online); the envelope names the reader
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::DeliveryStatus {
+                                        message_id,
+                                        conversation_id,
+                                        status,
+                                    });
+                                    manager.send_to_user(&sender_id, &outbound).await;
                                 }
                                 super::messages::WsMessage::Typing { conversation_id, recipient_id, is_typing } => {
-                                    // Forward to recipient(s)
-                                    // For 1-on-1, find recipient connections
-                                    let recipient_conns = manager.get_user_connections(&recipient_id).await;
-                                    for mut conn in recipient_conns {
-                                        let outbound = super::messages::WsMessage::Typing {
-                                            conversation_id,
-                                            recipient_id: user_id, // From the perspective of the receiver, the 'recipient' of the typing event is the one TYPING.
-                                            // Wait, the struct field is 'recipient_id'. 
-                                            // In the outbound message, we should probably put the 'typer_id'.
-                                            // Let's reuse the field but interpret it as 'who is typing' when receiving?
-                                            // Or better, change the struct to have 'user_id' or 'sender_id'.
-                                            // For now, let's assume the client handles it. 
-                                            // Let's send the typer's ID in the 'recipient_id' slot? No that's confusing.
-                                            // Let's just forward it as is, but the client needs to know WHO is typing.
-                                            // The 'recipient_id' in the struct is the TARGET.
-                                            // We should probably add 'sender_id' to the Typing struct or rely on the client knowing the peer.
-                                            // Let's modify the struct in the next step if needed, but for now let's assume 1-on-1 context.
-                                            // Actually, let's just forward it. The client might need to know who sent it.
-                                            // Let's hack it: Put the sender's ID in 'recipient_id' for the outbound message?
-                                            // No, let's just send it. The client receiving it knows it came from the peer in that conversation.
-                                            is_typing,
-                                        };
-                                        if let Ok(json) = serde_json::to_string(&outbound) {
-                                            let _ = conn.session.text(json).await;
+                                    if let Err(e) = ensure_members(&db, conversation_id, &[user_id, recipient_id]).await {
+                                        send_error(&ws_conn, error_code(&e, "typing_failed"), e, None);
+                                        continue;
+                                    }
+
+                                    // Every device of the recipient; the envelope names who is typing
+                                    let outbound = OutboundEvent::from_device(user_id, device_id, ServerEvent::Typing { conversation_id, is_typing });
+                                    manager.send_to_user(&recipient_id, &outbound).await;
+                                }
+                                super::messages::WsMessage::PresenceSubscribe { user_ids } => {
+                                    match SubscribePresenceUseCase::execute(&db, &mut redis, user_id, user_ids).await {
+                                        Ok(presences) => {
+                                            for presence in presences {
+                                                ws_conn.send(&OutboundEvent::server(ServerEvent::Presence(presence)));
+                                            }
                                         }
+                                        Err(e) => {
+                                            send_error(&ws_conn, error_code(&e, "presence_failed"), e, None);
+                                        }
+                                    }
+                                }
+                                super::messages::WsMessage::PresenceUnsubscribe { user_ids } => {
+                                    if let Err(e) = UnsubscribePresenceUseCase::execute(&mut redis, user_id, user_ids).await {
+                                        send_error(&ws_conn, error_code(&e, "presence_failed"), e, None);
                                     }
                                 }
                                 _ => {}
                             }
                         }
                         Err(e) => {
-                            tracing::error!("Failed to parse message: {}", e);
+                            tracing::warn!("Rejected malformed frame from User {} Device {}: {}", user_id, device_id, e);
+                            send_error(&ws_conn, "invalid_message", e, encoding.client_message_id(frame));
                         }
                     }
                 }
-                Message::Binary(bin) => {
-                    tracing::debug!("Received binary message: {} bytes", bin.len());
-                    // TODO: Support binary protocol (e.g. MessagePack)
-                }
                 Message::Ping(bytes) => {
                     let _ = session.pong(&bytes).await;
+                    // Client pings double as presence heartbeats
+                    refresh_presence(
+                        &db,
+                        &mut redis,
+                        manager.get_ref(),
+                        &config,
+                        user_id,
+                        device_id,
+                    )
+                    .await;
+                }
+                Message::Pong(_) => {
+                    // Answers to our heartbeat keep presence fresh for clients that never ping
+                    refresh_presence(
+                        &db,
+                        &mut redis,
+                        manager.get_ref(),
+                        &config,
+                        user_id,
+                        device_id,
+                    )
+                    .await;
                 }
                 Message::Close(reason) => {
                     tracing::info!("WebSocket closed: {:?}", reason);
@@ -247,8 +424,94 @@ pub async fn websocket_handler(
         }
 
         manager.remove_connection(&conn_id).await;
+        update_presence(
+            &db,
+            &mut redis,
+            manager.get_ref(),
+            &config,
+            user_id,
+            device_id,
+            false,
+        )
+        .await;
         tracing::info!("Connection {} closed", conn_id);
     });
 
     Ok(response)
 }
+
+async fn send_sync_page(
+    db: &DatabaseConnection,
+    conn: &WsConnection,
+    cursor: Option<&str>,
+    limit: u64,
+) {
+    match SyncMessagesUseCase::execute(db, conn.user_id, conn.device_id, cursor, limit).await {
+        Ok(page) => {
+            let response = OutboundEvent::server(ServerEvent::SyncResponse {
+                messages: page.messages,
+                cursor: page.cursor,
+                has_more: page.has_more,
+            });
+            conn.send(&response);
+        }
+        Err(e) => {
+            tracing::error!("Failed to sync messages: {}", e);
+        }
+    }
+}
+
+async fn send_sealed_sync_page(
+    db: &DatabaseConnection,
+    conn: &WsConnection,
+    after_delivery_id: Option<i64>,
+    limit: u64,
+) {
+    match SyncSealedMessagesUseCase::execute(db, conn.device_id, after_delivery_id, limit).await
+    {
+        Ok(page) => {
+            let response = OutboundEvent::server(ServerEvent::SealedSyncResponse {
+                messages: page.messages,
+                has_more: page.has_more,
+            });
+            conn.send(&response);
+        }
+        Err(e) => {
+            tracing::error!("Failed to sync sealed messages: {}", e);
+        }
+    }
+}
+
+/// Stable error code clients can match on; anything unexpected falls back to `fallback`.
+fn error_code(error: &str, fallback: &'static str) -> &'static str {
+    match error {
+        NOT_A_MEMBER => "not_a_member",
+        STALE_SENDER_KEY => "sender_key_stale",
+        SEALED_MESSAGE_TOO_LARGE => "message_too_large",
+        SEALED_SEND_RATE_LIMITED => "rate_limited",
+        _ => fallback,
+    }
+}
+
+fn send_ack(conn: &WsConnection, client_message_id: Uuid, message_id: i64, sent_at: i64) {
+    let ack = OutboundEvent::server(ServerEvent::SendAck {
+        client_message_id,
+        message_id,
+        sent_at,
+    });
+    conn.send(&ack);
+}
+
+fn send_error(
+    conn: &WsConnection,
+    code: &str,
+    message: String,
+    client_message_id: Option<Uuid>,
+) {
+    let error = OutboundEvent::server(ServerEvent::Error {
+        code: code.to_string(),
+        message,
+        client_message_id,
+    });
+    conn.send(&error);
+}
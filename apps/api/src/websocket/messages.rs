@@ -1,7 +1,86 @@
-use application::chat::dtos::SyncMessageDto;
+use actix_ws::Message;
+use application::chat::dtos::{
+    DeviceAddressDto, DeviceMessageDto, MemberRole, MembershipAction, SenderKeyDistributionDto,
+    SyncMessageDto,
+};
+use application::presence::dtos::PresenceDto;
+use application::sealed_sender::dtos::SealedMessageDto;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Server-to-client schema version used when a client does not ask for one.
+pub const DEFAULT_PROTOCOL_VERSION: u16 = 1;
+
+/// Newest server-to-client schema. Clients pass the newest version they understand as
+/// `?v=` when connecting and get the highest one both sides support.
+///
+/// - 1: bare `WsMessage` frames, where outbound frames reuse the inbound shapes
+/// - 2: `ServerEvent`s wrapped in an `EventEnvelope`
+pub const LATEST_PROTOCOL_VERSION: u16 = 2;
+
+/// Wire format of server frames, chosen with `?encoding=` when connecting. Clients may send
+/// either: text frames are read as JSON and binary frames as MessagePack.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    /// Text frames; byte strings are arrays of numbers
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    /// Binary frames holding the same shapes as JSON, as maps keyed by field name, except
+    /// that byte strings and UUIDs are `bin` values
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    /// The encoding and contents of a client data frame; `None` for control frames.
+    pub fn of(frame: &Message) -> Option<(Self, &[u8])> {
+        match frame {
+            Message::Text(text) => Some((Self::Json, text.as_bytes())),
+            Message::Binary(bytes) => Some((Self::MessagePack, bytes)),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, frame: &T) -> Result<Message, String> {
+        match self {
+            Self::Json => serde_json::to_string(frame)
+                .map(|text| Message::Text(text.into()))
+                .map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::to_vec_named(frame)
+                .map(|bytes| Message::Binary(bytes.into()))
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, frame: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(frame).map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(frame).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// The `client_message_id` of a frame that failed to decode, if it carries a readable
+    /// one, so the rejection can be matched to the client's send.
+    pub fn client_message_id(self, frame: &[u8]) -> Option<Uuid> {
+        #[derive(Deserialize)]
+        struct Payload {
+            client_message_id: Uuid,
+        }
+        #[derive(Deserialize)]
+        struct Frame {
+            payload: Payload,
+        }
+
+        self.decode::<Frame>(frame)
+            .ok()
+            .map(|frame| frame.payload.client_message_id)
+    }
+}
+
+/// Client-to-server frames, and server-to-client frames for protocol version 1.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum WsMessage {
@@ -11,19 +90,102 @@ pub enum WsMessage {
         client_message_id: Uuid,
         recipient_id: Uuid,
         recipient_device_id: i64,
+        #[serde(with = "serde_bytes")]
         content: Vec<u8>, // Encrypted blob
     },
-    /// Acknowledge receipt of a message
+    /// Send one message to every device of the recipient and the sender's other devices;
+    /// stored atomically, or rejected with `StaleDevices`
+    MultiDeviceSend {
+        conversation_id: Uuid,
+        client_message_id: Uuid,
+        recipient_id: Uuid,
+        messages: Vec<DeviceMessageDto>,
+    },
+    /// A `MultiDeviceSend` did not match the current device lists; re-fetch and resend
+    StaleDevices {
+        client_message_id: Uuid,
+        missing: Vec<DeviceAddressDto>,
+        extra: Vec<DeviceAddressDto>,
+    },
+    /// Send one Sender Key ciphertext to a group, with Sender Key distributions for any
+    /// devices that do not yet hold the current key
+    GroupSend {
+        conversation_id: Uuid,
+        client_message_id: Uuid,
+        sender_key_epoch: i64,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        distributions: Vec<SenderKeyDistributionDto>,
+    },
+    /// A group message for this device, with the sender's distribution if it carried one
+    GroupMessage {
+        message_id: i64,
+        conversation_id: Uuid,
+        client_message_id: Uuid,
+        sender_id: Uuid,
+        sender_device_id: i64,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        sender_key_distribution: Option<Vec<u8>>,
+        sent_at: i64,
+    },
+    /// Send a sealed-sender envelope; authorised by the recipient's access key,
+    /// not by the identity of this socket
+    SealedSend {
+        recipient_id: Uuid,
+        recipient_device_id: i64,
+        #[serde(with = "serde_bytes")]
+        access_key: Option<Vec<u8>>,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+    },
+    /// A sealed-sender envelope for this device; the sender is only known to the client
+    SealedMessage {
+        delivery_id: i64,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        received_at: i64,
+    },
+    /// Acknowledge sealed messages so the server can drop them
+    SealedAck {
+        delivery_ids: Vec<i64>,
+    },
+    /// Request a page of queued sealed messages; pass the last `delivery_id` of the previous
+    /// page, or omit it to start from the oldest sealed message not yet acked
+    SealedSyncRequest {
+        after_delivery_id: Option<i64>,
+        limit: Option<u64>,
+    },
+    /// A page of queued sealed messages, oldest first; ask for the next one while
+    /// `has_more`. The first page is pushed unprompted when the device connects
+    SealedSyncResponse {
+        messages: Vec<SealedMessageDto>,
+        has_more: bool,
+    },
+    /// The server stored a message; the sender can mark it sent. Retries with the same
+    /// `client_message_id` are acknowledged with the original `message_id`
+    SendAck {
+        client_message_id: Uuid,
+        message_id: i64,
+        server_timestamp: i64,
+    },
+    /// Acknowledge receipt of a message; removes it from this device's offline queue
     Ack {
-        message_id: String,
+        message_id: i64,
     },
-    /// Request to sync offline messages
+    /// Request a page of offline messages; omit `cursor` to resume from the server-side
+    /// checkpoint
     SyncRequest {
-        last_message_id: Option<i64>,
+        cursor: Option<String>,
+        limit: Option<u64>,
     },
-    /// Response with offline messages
+    /// A page of offline messages, oldest first; send `cursor` back while `has_more`.
+    /// The first page is pushed unprompted when the device connects
     SyncResponse {
         messages: Vec<SyncMessageDto>,
+        cursor: Option<String>,
+        has_more: bool,
     },
     /// WebRTC Signaling: SDP Offer
     SdpOffer {
@@ -56,10 +218,50 @@ pub enum WsMessage {
         recipient_id: Uuid,
         is_typing: bool,
     },
-    /// Error message from server
+    /// Receive `Presence` updates for these users, starting with their current presence.
+    /// Users whose privacy settings hide it from the caller are silently left out.
+    PresenceSubscribe {
+        user_ids: Vec<Uuid>,
+    },
+    PresenceUnsubscribe {
+        user_ids: Vec<Uuid>,
+    },
+    /// A watched user came online or went offline
+    Presence {
+        user_id: Uuid,
+        online: bool,
+        last_seen_at: Option<i64>,
+    },
+    /// One-time prekey stock for this device is low; the client should upload more
+    PreKeysLow {
+        remaining: u64,
+    },
+    /// A contact's identity key changed; their safety number must be re-verified
+    IdentityChanged {
+        change_id: i64,
+        user_id: Uuid,
+        changed_at: i64,
+    },
+    /// Members of a conversation were added, removed, left, joined or had their role changed
+    MembershipChanged {
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        action: MembershipAction,
+        user_ids: Vec<Uuid>,
+        role: Option<MemberRole>,
+    },
+    /// A group was renamed or its avatar changed
+    GroupUpdated {
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        name: Option<String>,
+        avatar: Option<String>,
+    },
+    /// Error message from server; `client_message_id` names the send that failed, if any
     Error {
         code: String,
         message: String,
+        client_message_id: Option<Uuid>,
     },
 }
 
@@ -68,3 +270,330 @@ pub enum DeliveryStatusType {
     Delivered,
     Read,
 }
+
+/// Server-to-client events for protocol version 2. The device whose frame caused an event
+/// is named by the envelope rather than by the event.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum ServerEvent {
+    /// A pairwise message for this device
+    Message {
+        message_id: i64,
+        conversation_id: Uuid,
+        client_message_id: Uuid,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        sent_at: i64,
+    },
+    /// A group message for this device, with the sender's distribution if it carried one
+    GroupMessage {
+        message_id: i64,
+        conversation_id: Uuid,
+        client_message_id: Uuid,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        sender_key_distribution: Option<Vec<u8>>,
+        sent_at: i64,
+    },
+    /// A sealed-sender envelope; it never names a sender
+    SealedMessage {
+        delivery_id: i64,
+        #[serde(with = "serde_bytes")]
+        content: Vec<u8>,
+        received_at: i64,
+    },
+    /// The server stored a message sent from this device
+    SendAck {
+        client_message_id: Uuid,
+        message_id: i64,
+        sent_at: i64,
+    },
+    /// A `MultiDeviceSend` did not match the current device lists; re-fetch and resend
+    StaleDevices {
+        client_message_id: Uuid,
+        missing: Vec<DeviceAddressDto>,
+        extra: Vec<DeviceAddressDto>,
+    },
+    /// A page of offline messages, oldest first; send `cursor` back while `has_more`
+    SyncResponse {
+        messages: Vec<SyncMessageDto>,
+        cursor: Option<String>,
+        has_more: bool,
+    },
+    /// A page of queued sealed messages, oldest first; ask for the next one while `has_more`
+    SealedSyncResponse {
+        messages: Vec<SealedMessageDto>,
+        has_more: bool,
+    },
+    SdpOffer {
+        sdp: String,
+    },
+    SdpAnswer {
+        sdp: String,
+    },
+    IceCandidate {
+        candidate: String,
+    },
+    /// The sending device delivered or read one of this user's messages
+    DeliveryStatus {
+        message_id: i64,
+        conversation_id: Uuid,
+        status: DeliveryStatusType,
+    },
+    Typing {
+        conversation_id: Uuid,
+        is_typing: bool,
+    },
+    /// A watched user came online or went offline
+    Presence(PresenceDto),
+    /// One-time prekey stock for this device is low; the client should upload more
+    PreKeysLow {
+        remaining: u64,
+    },
+    /// A contact's identity key changed; their safety number must be re-verified
+    IdentityChanged {
+        change_id: i64,
+        user_id: Uuid,
+        changed_at: i64,
+    },
+    MembershipChanged {
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        action: MembershipAction,
+        user_ids: Vec<Uuid>,
+        role: Option<MemberRole>,
+    },
+    GroupUpdated {
+        conversation_id: Uuid,
+        actor_id: Uuid,
+        name: Option<String>,
+        avatar: Option<String>,
+    },
+    /// `client_message_id` names the send that failed, if any
+    Error {
+        code: String,
+        message: String,
+        client_message_id: Option<Uuid>,
+    },
+}
+
+/// A protocol version 2 frame.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventEnvelope<E = ServerEvent> {
+    pub v: u16,
+    /// Starts at 1 on every connection and goes up by one per frame, so gaps are visible
+    pub seq: u64,
+    /// The user and device whose action caused the event; both `None` when the server
+    /// raised it itself or the sender is sealed
+    pub from_user_id: Option<Uuid>,
+    pub from_device_id: Option<i64>,
+    pub server_timestamp: i64,
+    pub event: E,
+}
+
+/// An event on its way to one or more connections, before it is encoded for the version
+/// each of them speaks.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboundEvent {
+    pub from_user_id: Option<Uuid>,
+    pub from_device_id: Option<i64>,
+    pub server_timestamp: i64,
+    pub event: ServerEvent,
+}
+
+impl OutboundEvent {
+    /// An event raised by the server rather than by another device.
+    pub fn server(event: ServerEvent) -> Self {
+        Self {
+            from_user_id: None,
+            from_device_id: None,
+            server_timestamp: Utc::now().timestamp(),
+            event,
+        }
+    }
+
+    /// An event caused by a frame from `user_id`'s `device_id`.
+    pub fn from_device(user_id: Uuid, device_id: i64, event: ServerEvent) -> Self {
+        Self {
+            from_user_id: Some(user_id),
+            from_device_id: Some(device_id),
+            ..Self::server(event)
+        }
+    }
+
+    pub fn envelope(&self, v: u16, seq: u64) -> EventEnvelope<&ServerEvent> {
+        EventEnvelope {
+            v,
+            seq,
+            from_user_id: self.from_user_id,
+            from_device_id: self.from_device_id,
+            server_timestamp: self.server_timestamp,
+            event: &self.event,
+        }
+    }
+
+    /// The frame a protocol version 1 client expects on the connection of `user_id`'s
+    /// `device_id`, in the shapes version 1 has always used.
+    pub fn to_v1(&self, user_id: Uuid, device_id: i64) -> WsMessage {
+        let from_user_id = self.from_user_id.unwrap_or_default();
+        let from_device_id = self.from_device_id.unwrap_or_default();
+
+        match &self.event {
+            ServerEvent::Message {
+                conversation_id,
+                client_message_id,
+                content,
+                ..
+            } => WsMessage::SignalMessage {
+                conversation_id: *conversation_id,
+                client_message_id: *client_message_id,
+                recipient_id: user_id,
+                recipient_device_id: device_id,
+                content: content.clone(),
+            },
+            ServerEvent::GroupMessage {
+                message_id,
+                conversation_id,
+                client_message_id,
+                content,
+                sender_key_distribution,
+                sent_at,
+            } => WsMessage::GroupMessage {
+                message_id: *message_id,
+                conversation_id: *conversation_id,
+                client_message_id: *client_message_id,
+                sender_id: from_user_id,
+                sender_device_id: from_device_id,
+                content: content.clone(),
+                sender_key_distribution: sender_key_distribution.clone(),
+                sent_at: *sent_at,
+            },
+            ServerEvent::SealedMessage {
+                delivery_id,
+                content,
+                received_at,
+            } => WsMessage::SealedMessage {
+                delivery_id: *delivery_id,
+                content: content.clone(),
+                received_at: *received_at,
+            },
+            ServerEvent::SendAck {
+                client_message_id,
+                message_id,
+                sent_at,
+            } => WsMessage::SendAck {
+                client_message_id: *client_message_id,
+                message_id: *message_id,
+                server_timestamp: *sent_at,
+            },
+            ServerEvent::StaleDevices {
+                client_message_id,
+                missing,
+                extra,
+            } => WsMessage::StaleDevices {
+                client_message_id: *client_message_id,
+                missing: missing.clone(),
+                extra: extra.clone(),
+            },
+            ServerEvent::SyncResponse {
+                messages,
+                cursor,
+                has_more,
+            } => WsMessage::SyncResponse {
+                messages: messages.clone(),
+                cursor: cursor.clone(),
+                has_more: *has_more,
+            },
+            ServerEvent::SealedSyncResponse { messages, has_more } => {
+                WsMessage::SealedSyncResponse {
+                    messages: messages.clone(),
+                    has_more: *has_more,
+                }
+            }
+            ServerEvent::SdpOffer { sdp } => WsMessage::SdpOffer {
+                recipient_id: from_user_id,
+                recipient_device_id: from_device_id,
+                sdp: sdp.clone(),
+            },
+            ServerEvent::SdpAnswer { sdp } => WsMessage::SdpAnswer {
+                recipient_id: from_user_id,
+                recipient_device_id: from_device_id,
+                sdp: sdp.clone(),
+            },
+            ServerEvent::IceCandidate { candidate } => WsMessage::IceCandidate {
+                recipient_id: from_user_id,
+                recipient_device_id: from_device_id,
+                candidate: candidate.clone(),
+            },
+            ServerEvent::DeliveryStatus {
+                message_id,
+                conversation_id,
+                status,
+            } => WsMessage::DeliveryStatus {
+                message_id: *message_id,
+                conversation_id: *conversation_id,
+                sender_id: user_id,
+                status: *status,
+            },
+            ServerEvent::Typing {
+                conversation_id,
+                is_typing,
+            } => WsMessage::Typing {
+                conversation_id: *conversation_id,
+                recipient_id: from_user_id,
+                is_typing: *is_typing,
+            },
+            ServerEvent::Presence(presence) => WsMessage::Presence {
+                user_id: presence.user_id,
+                online: presence.online,
+                last_seen_at: presence.last_seen_at,
+            },
+            ServerEvent::PreKeysLow { remaining } => WsMessage::PreKeysLow {
+                remaining: *remaining,
+            },
+            ServerEvent::IdentityChanged {
+                change_id,
+                user_id,
+                changed_at,
+            } => WsMessage::IdentityChanged {
+                change_id: *change_id,
+                user_id: *user_id,
+                changed_at: *changed_at,
+            },
+            ServerEvent::MembershipChanged {
+                conversation_id,
+                actor_id,
+                action,
+                user_ids,
+                role,
+            } => WsMessage::MembershipChanged {
+                conversation_id: *conversation_id,
+                actor_id: *actor_id,
+                action: *action,
+                user_ids: user_ids.clone(),
+                role: *role,
+            },
+            ServerEvent::GroupUpdated {
+                conversation_id,
+                actor_id,
+                name,
+                avatar,
+            } => WsMessage::GroupUpdated {
+                conversation_id: *conversation_id,
+                actor_id: *actor_id,
+                name: name.clone(),
+                avatar: avatar.clone(),
+            },
+            ServerEvent::Error {
+                code,
+                message,
+                client_message_id,
+            } => WsMessage::Error {
+                code: code.clone(),
+                message: message.clone(),
+                client_message_id: *client_message_id,
+            },
+        }
+    }
+}
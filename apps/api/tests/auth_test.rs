@@ -1,19 +1,45 @@
 use actix_web::{test, web, App};
-use api::handlers::auth::{
-    request_otp, verify_otp, RequestOtpRequest, VerifyOtpRequest, VerifyOtpResponse,
-};
+use api::handlers::auth::{request_otp, verify_otp};
+use api::websocket::connection::ConnectionManager;
+use application::auth::dtos::{RequestOtpRequest, VerifyOtpRequest, VerifyOtpResponse};
+use application::keys::dtos::{DeviceKeysDto, PreKeyDto, SignedPreKeyDto};
+use infrastructure::crypto::signal;
 use infrastructure::database;
 use redis::AsyncCommands;
 use sea_orm::{ColumnTrait, DeleteResult, EntityTrait, QueryFilter};
 use uuid::Uuid;
 
 // Import core crate entities
-// Note: Using explicit module path to avoid linter confusion with std::core
-use core::entities::devices;
-use core::entities::one_time_prekeys;
-use core::entities::users;
+use chat_core::entities::devices;
+use chat_core::entities::one_time_prekeys;
+use chat_core::entities::users;
+
+/// Key bundle as a client would build it: private halves stay on the device.
+fn client_device_keys() -> DeviceKeysDto {
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let signed_prekey = signal::generate_signed_prekey(&identity, 1).unwrap();
+    let prekeys = signal::generate_prekeys(1, 100).unwrap();
+
+    DeviceKeysDto {
+        identity_key: identity.public_key,
+        registration_id: signal::generate_registration_id().max(1) as i32,
+        signed_prekey: SignedPreKeyDto {
+            id: signed_prekey.id as i32,
+            key: signed_prekey.public_key,
+            signature: signed_prekey.signature,
+        },
+        one_time_prekeys: prekeys
+            .into_iter()
+            .map(|pk| PreKeyDto {
+                id: pk.id as i32,
+                key: pk.public_key,
+            })
+            .collect(),
+    }
+}
 
 #[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
 async fn test_otp_flow_and_signal_keys() {
     // 1. Setup Test Environment
     dotenvy::from_filename(".env").ok();
@@ -43,6 +69,7 @@ async fn test_otp_flow_and_signal_keys() {
             .app_data(web::Data::new(db.clone()))
             .app_data(web::Data::new(redis_conn.clone()))
             .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(ConnectionManager::new()))
             .service(request_otp)
             .service(verify_otp),
     )
@@ -70,8 +97,28 @@ async fn test_otp_flow_and_signal_keys() {
         .expect("Failed to get OTP");
     assert_eq!(otp.len(), 6);
 
-    // 5. Test Verify OTP
+    // 5. Keys with a forged signed-prekey signature are rejected without consuming the OTP
     let device_uuid = Uuid::new_v4();
+    let keys = client_device_keys();
+    let mut forged_keys = keys.clone();
+    forged_keys.signed_prekey.signature[0] ^= 0xff;
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/auth/verify-otp")
+        .set_json(VerifyOtpRequest {
+            phone_number: phone_number.to_string(),
+            otp: otp.clone(),
+            device_uuid,
+            device_name: Some("Test Device".to_string()),
+            platform: Some(1),
+            keys: forged_keys,
+        })
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+    // 6. Test Verify OTP
     let req = test::TestRequest::post()
         .uri("/api/v1/auth/verify-otp")
         .set_json(VerifyOtpRequest {
@@ -79,6 +126,8 @@ async fn test_otp_flow_and_signal_keys() {
             otp: otp.clone(),
             device_uuid,
             device_name: Some("Test Device".to_string()),
+            platform: Some(1),
+            keys: keys.clone(),
         })
         .to_request();
 
@@ -90,7 +139,7 @@ async fn test_otp_flow_and_signal_keys() {
     assert!(!body.refresh_token.is_empty());
     assert!(body.is_new_user);
 
-    // 6. Verify Database State (Signal Keys)
+    // 7. Verify Database State (Signal Keys)
 
     // Check User
     let user = users::Entity::find_by_id(body.user_id)
@@ -107,9 +156,10 @@ async fn test_otp_flow_and_signal_keys() {
         .expect("DB Error")
         .expect("Device not found");
     assert_eq!(device.user_id, user.user_id);
-    assert!(!device.identity_key_public.is_empty());
-    assert!(!device.signed_prekey_public.is_empty());
-    assert!(!device.signed_prekey_signature.is_empty());
+    assert_eq!(device.identity_key_public, keys.identity_key);
+    assert_eq!(device.registration_id, keys.registration_id);
+    assert_eq!(device.signed_prekey_public, keys.signed_prekey.key);
+    assert_eq!(device.signed_prekey_signature, keys.signed_prekey.signature);
 
     // Check One-Time Prekeys (Should be 100)
     let prekeys = one_time_prekeys::Entity::find()
@@ -121,8 +171,8 @@ async fn test_otp_flow_and_signal_keys() {
     assert_eq!(
         prekeys.len(),
         100,
-        "Should generate exactly 100 one-time prekeys"
+        "Should store exactly the 100 uploaded one-time prekeys"
     );
 
-    println!("✅ Test Passed: OTP Flow + Signal Key Generation Complete!");
+    println!("✅ Test Passed: OTP Flow + Signal Key Upload Complete!");
 }
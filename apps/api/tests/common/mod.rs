@@ -0,0 +1,389 @@
+#![allow(dead_code)]
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{CloseReason, Codec, Frame, Message};
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, Service, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::{test, web, App};
+use api::config::Config;
+use api::handlers::health::websocket_metrics;
+use api::websocket::connection::{ConnectionManager, ConnectionMetrics};
+use api::websocket::handler::websocket_handler;
+use api::websocket::messages::{Encoding, EventEnvelope, ServerEvent, WsMessage};
+use application::auth::dtos::Claims;
+use application::chat::conversations::CreateConversationUseCase;
+use application::chat::dtos::{ConversationType, CreateConversationRequest};
+use application::keys::dtos::{DeviceKeysDto, PreKeyDto, SignedPreKeyDto};
+use bytes::{Bytes, BytesMut};
+use chat_core::entities::{conversations, devices, messages, users};
+use chrono::Utc;
+use futures::channel::mpsc;
+use infrastructure::crypto::signal;
+use infrastructure::database;
+use infrastructure::redis::RedisClient;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub async fn setup() -> (DatabaseConnection, Config) {
+    dotenvy::from_filename(".env").ok();
+    let config = Config::from_env().expect("Failed to load config");
+    let db = database::init_database(&config.database_url)
+        .await
+        .expect("Failed to connect DB");
+    (db, config)
+}
+
+/// One API node serving the WebSocket endpoint, relaying through the shared Redis.
+pub async fn node(
+    db: &DatabaseConnection,
+    config: &Config,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let publisher = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let pubsub = database::init_redis_pubsub(&config.redis_url)
+        .await
+        .expect("Failed to subscribe to Redis");
+    let manager = ConnectionManager::with_relay(RedisClient::new(publisher.clone()), pubsub);
+
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(publisher))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(manager))
+            .service(websocket_metrics)
+            .service(websocket_handler),
+    )
+    .await
+}
+
+pub async fn metrics(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+) -> ConnectionMetrics {
+    let req = test::TestRequest::get()
+        .uri("/metrics/websocket")
+        .to_request();
+    test::call_and_read_body_json(app, req).await
+}
+
+/// A WebSocket client driven directly against the service, without a listening socket.
+pub struct WsClient {
+    outgoing: mpsc::UnboundedSender<Result<Bytes, PayloadError>>,
+    incoming: BoxBody,
+    codec: Codec,
+    buf: BytesMut,
+    encoding: Encoding,
+    last_seq: u64,
+    /// Set to stop answering pings
+    silent: bool,
+    pings: usize,
+}
+
+impl WsClient {
+    /// `query` is appended to the URL after the token, e.g. `&v=2`. Frames are sent and
+    /// expected in MessagePack if it asks for `encoding=msgpack`, otherwise in JSON.
+    pub async fn connect(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+        token: &str,
+        query: &str,
+    ) -> Self {
+        let mut req = test::TestRequest::get()
+            .uri(&format!("/ws/?token={token}{query}"))
+            .insert_header(("upgrade", "websocket"))
+            .insert_header(("connection", "upgrade"))
+            .insert_header(("sec-websocket-version", "13"))
+            .insert_header(("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request();
+        let (outgoing, payload) = mpsc::unbounded();
+        *req.payload() = Payload::Stream {
+            payload: Box::pin(payload),
+        };
+
+        let resp = test::call_service(app, req).await;
+        assert_eq!(resp.status(), 101, "WebSocket upgrade was refused");
+
+        Self {
+            outgoing,
+            incoming: resp.into_body(),
+            codec: Codec::new().client_mode(),
+            buf: BytesMut::new(),
+            encoding: if query.contains("encoding=msgpack") {
+                Encoding::MessagePack
+            } else {
+                Encoding::Json
+            },
+            last_seq: 0,
+            silent: false,
+            pings: 0,
+        }
+    }
+
+    pub fn send(&mut self, message: &WsMessage) {
+        self.send_frame(self.encoding.encode(message).unwrap());
+    }
+
+    /// Send a text frame as is, whether or not it is a valid message.
+    pub fn send_text(&mut self, text: &str) {
+        self.send_frame(Message::Text(text.to_string().into()));
+    }
+
+    /// Stop answering pings, like a client whose network vanished without a close.
+    pub fn go_silent(&mut self) {
+        self.silent = true;
+    }
+
+    /// Pings received from the server so far.
+    pub fn pings(&self) -> usize {
+        self.pings
+    }
+
+    /// Keep reading, and answering pings unless silent, for `duration`; the server must not
+    /// close the connection meanwhile.
+    pub async fn idle_for(&mut self, duration: Duration) {
+        let closed = tokio::time::timeout(duration, async {
+            loop {
+                if let None | Some(Frame::Close(_)) = self.next_raw().await {
+                    return;
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_err(), "WebSocket closed while idling");
+    }
+
+    /// Read until the server closes the connection, returning its close reason.
+    pub async fn recv_close(&mut self) -> Option<CloseReason> {
+        loop {
+            match self.next_raw().await {
+                Some(Frame::Close(reason)) => return reason,
+                Some(_) => {}
+                None => panic!("WebSocket ended without a close frame"),
+            }
+        }
+    }
+
+    /// Next protocol version 1 frame matching `wanted`, skipping anything else the server
+    /// pushes first.
+    pub async fn recv(&mut self, wanted: impl Fn(&WsMessage) -> bool) -> WsMessage {
+        loop {
+            let message = self.encoding.decode(&self.next_frame().await).unwrap();
+            if wanted(&message) {
+                return message;
+            }
+        }
+    }
+
+    /// Next protocol version 2 envelope whose event matches `wanted`. Every envelope read,
+    /// skipped or not, must carry the next sequence number.
+    pub async fn recv_event(&mut self, wanted: impl Fn(&ServerEvent) -> bool) -> EventEnvelope {
+        loop {
+            let envelope: EventEnvelope = self.encoding.decode(&self.next_frame().await).unwrap();
+            assert_eq!(envelope.seq, self.last_seq + 1, "sequence numbers skipped");
+            self.last_seq = envelope.seq;
+            if wanted(&envelope.event) {
+                return envelope;
+            }
+        }
+    }
+
+    /// Read whatever the server still sends until the connection ends, with or without a
+    /// close frame; returns the number of data frames read.
+    pub async fn drain(&mut self) -> usize {
+        let mut frames = 0;
+        loop {
+            match self.next_raw().await {
+                Some(Frame::Text(_) | Frame::Binary(_)) => frames += 1,
+                Some(Frame::Close(_)) | None => return frames,
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Contents of the next data frame, which must be in the negotiated encoding.
+    async fn next_frame(&mut self) -> Bytes {
+        loop {
+            match (self.next_raw().await, self.encoding) {
+                (Some(Frame::Text(text)), Encoding::Json) => return text,
+                (Some(Frame::Binary(bytes)), Encoding::MessagePack) => return bytes,
+                (Some(Frame::Text(_) | Frame::Binary(_)), encoding) => {
+                    panic!("Expected a {encoding:?} frame")
+                }
+                (Some(Frame::Close(_)) | None, _) => panic!("WebSocket closed"),
+                _ => {}
+            }
+        }
+    }
+
+    /// The next frame other than a ping, or `None` once the response body ends. Pings are
+    /// counted and answered here.
+    async fn next_raw(&mut self) -> Option<Frame> {
+        loop {
+            while let Some(frame) = self.codec.decode(&mut self.buf).unwrap() {
+                match frame {
+                    Frame::Ping(payload) => {
+                        self.pings += 1;
+                        if !self.silent {
+                            self.send_frame(Message::Pong(payload));
+                        }
+                    }
+                    frame => return Some(frame),
+                }
+            }
+
+            let incoming = &mut self.incoming;
+            let chunk = tokio::time::timeout(
+                Duration::from_secs(5),
+                std::future::poll_fn(|cx| std::pin::Pin::new(&mut *incoming).poll_next(cx)),
+            )
+            .await
+            .expect("Timed out waiting for a WebSocket frame")?
+            .unwrap();
+            self.buf.extend_from_slice(&chunk);
+        }
+    }
+
+    fn send_frame(&mut self, message: Message) {
+        let mut frame = BytesMut::new();
+        self.codec.encode(message, &mut frame).unwrap();
+        // The server may already have dropped its end
+        let _ = self.outgoing.unbounded_send(Ok(frame.freeze()));
+    }
+}
+
+/// Key bundle as a client would build it: private halves stay on the device.
+pub fn client_device_keys() -> DeviceKeysDto {
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let signed_prekey = signal::generate_signed_prekey(&identity, 1).unwrap();
+
+    DeviceKeysDto {
+        identity_key: identity.public_key,
+        registration_id: signal::generate_registration_id().max(1) as i32,
+        signed_prekey: SignedPreKeyDto {
+            id: signed_prekey.id as i32,
+            key: signed_prekey.public_key,
+            signature: signed_prekey.signature,
+        },
+        one_time_prekeys: signal::generate_prekeys(1, 10)
+            .unwrap()
+            .into_iter()
+            .map(|pk| PreKeyDto {
+                id: pk.id as i32,
+                key: pk.public_key,
+            })
+            .collect(),
+    }
+}
+
+pub fn access_token(config: &Config, user_id: Uuid, device_id: i64) -> String {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        device_id,
+        exp: now + 300,
+        iat: now,
+        token_type: "access".to_string(),
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
+    )
+    .unwrap()
+}
+
+pub async fn seed_user_with_device(db: &DatabaseConnection) -> (Uuid, i64) {
+    let user_id = Uuid::new_v4();
+    users::ActiveModel {
+        user_id: Set(user_id),
+        phone_number: Set(format!("+991{:012}", user_id.as_u128() % 1_000_000_000_000)),
+        phone_number_hash: Set(user_id.as_bytes().to_vec()),
+        username: Set(None),
+        display_name: Set(None),
+        bio: Set(None),
+        profile_picture: Set(None),
+        last_seen_at: Set(None),
+        is_online: Set(false),
+        last_seen_visibility: Set(0),
+        is_deleted: Set(false),
+        deleted_at: Set(None),
+        created_at: Set(Utc::now().into()),
+        updated_at: Set(Utc::now().into()),
+        pin_hash: Set(None),
+        registration_lock: Set(false),
+        registration_lock_expires_at: Set(None),
+        pin_set_at: Set(None),
+    }
+    .insert(db)
+    .await
+    .expect("Failed to create user");
+
+    let device = devices::ActiveModel {
+        user_id: Set(user_id),
+        device_uuid: Set(Uuid::new_v4()),
+        device_name: Set(Some("Test Device".to_string())),
+        platform: Set(1),
+        identity_key_public: Set(vec![1; 32]),
+        registration_id: Set(1),
+        signed_prekey_id: Set(1),
+        signed_prekey_public: Set(vec![2; 32]),
+        signed_prekey_signature: Set(vec![3; 64]),
+        last_seen_at: Set(Utc::now().into()),
+        created_at: Set(Utc::now().into()),
+        device_type: Set(1),
+        is_active: Set(true),
+        linked_at: Set(None),
+        linked_by_device_id: Set(None),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .expect("Failed to create device");
+
+    (user_id, device.device_id)
+}
+
+pub async fn seed_direct_conversation(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    peer_id: Uuid,
+) -> Uuid {
+    CreateConversationUseCase::execute(
+        db,
+        user_id,
+        CreateConversationRequest {
+            conv_type: ConversationType::Direct,
+            name: None,
+            avatar: None,
+            member_ids: vec![peer_id],
+        },
+    )
+    .await
+    .unwrap()
+    .conversation_id
+}
+
+pub async fn cleanup(db: &DatabaseConnection, conv_id: Uuid, user_ids: &[Uuid]) {
+    // Messages do not cascade with their conversation
+    messages::Entity::delete_many()
+        .filter(messages::Column::ConvId.eq(conv_id))
+        .exec(db)
+        .await
+        .expect("Failed to clean up messages");
+    conversations::Entity::delete_by_id(conv_id)
+        .exec(db)
+        .await
+        .expect("Failed to clean up conversation");
+    cleanup_users(db, user_ids).await;
+}
+
+pub async fn cleanup_users(db: &DatabaseConnection, user_ids: &[Uuid]) {
+    users::Entity::delete_many()
+        .filter(users::Column::UserId.is_in(user_ids.iter().copied()))
+        .exec(db)
+        .await
+        .expect("Failed to clean up users");
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App};
+use api::config::Config;
+use api::handlers::auth::{
+    approve_linking, complete_linking, create_linking_session, unlink_device, verify_otp,
+};
+use api::middleware::auth::AuthMiddleware;
+use api::websocket::connection::ConnectionManager;
+use api::websocket::handler::websocket_handler;
+use api::websocket::messages::ServerEvent;
+use application::auth::dtos::{
+    ApproveLinkingRequest, ApproveLinkingResponse, CompleteLinkingRequest,
+    CreateLinkingSessionResponse, UnlinkDeviceResponse, VerifyOtpRequest, VerifyOtpResponse,
+};
+use application::keys::dtos::DeviceKeysDto;
+use chat_core::entities::{identity_key_changes, users};
+use common::WsClient;
+use infrastructure::database;
+use redis::AsyncCommands;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+
+async fn app(
+    db: &DatabaseConnection,
+    config: &Config,
+) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    let redis = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+
+    test::init_service(
+        App::new()
+            .wrap(AuthMiddleware)
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(ConnectionManager::new()))
+            .service(verify_otp)
+            .service(create_linking_session)
+            .service(complete_linking)
+            .service(approve_linking)
+            .service(unlink_device)
+            .service(websocket_handler),
+    )
+    .await
+}
+
+async fn register(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    config: &Config,
+    phone_number: &str,
+    keys: DeviceKeysDto,
+) -> VerifyOtpResponse {
+    let mut redis = database::init_redis(&config.redis_url).await.unwrap();
+    redis
+        .set_ex::<_, _, ()>(format!("otp:{}", phone_number), "123456", 60)
+        .await
+        .unwrap();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/auth/verify-otp")
+        .set_json(VerifyOtpRequest {
+            phone_number: phone_number.to_string(),
+            otp: "123456".to_string(),
+            device_uuid: Uuid::new_v4(),
+            device_name: None,
+            platform: Some(1),
+            keys,
+        })
+        .to_request();
+    test::call_and_read_body_json(app, req).await
+}
+
+/// The identity change recorded last for `user_id`.
+async fn latest_change(db: &DatabaseConnection, user_id: Uuid) -> identity_key_changes::Model {
+    identity_key_changes::Entity::find()
+        .filter(identity_key_changes::Column::UserId.eq(user_id))
+        .order_by_desc(identity_key_changes::Column::ChangeId)
+        .one(db)
+        .await
+        .unwrap()
+        .expect("No identity change recorded")
+}
+
+/// The next `IdentityChanged` pushed to `ws`, as `(change_id, user_id)`.
+async fn next_identity_change(ws: &mut WsClient) -> (i64, Uuid) {
+    match ws
+        .recv_event(|e| matches!(e, ServerEvent::IdentityChanged { .. }))
+        .await
+        .event
+    {
+        ServerEvent::IdentityChanged {
+            change_id, user_id, ..
+        } => (change_id, user_id),
+        _ => unreachable!(),
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn identity_changes_are_pushed_to_conversation_members() {
+    let (db, config) = common::setup().await;
+    let app = app(&db, &config).await;
+    let (alice, _) = common::seed_user_with_device(&db).await;
+    let phone_number = users::Entity::find_by_id(alice)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap()
+        .phone_number;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&app, &token, "&v=2").await;
+
+    // Re-registering on a new phone
+    let reinstalled = register(&app, &config, &phone_number, common::client_device_keys()).await;
+    assert!(reinstalled.identity_changed);
+    let change = latest_change(&db, alice).await;
+    assert_eq!(change.device_id, reinstalled.device_id);
+    assert_eq!(
+        next_identity_change(&mut bob_ws).await,
+        (change.change_id, alice)
+    );
+
+    // Linking a desktop
+    let primary_token = format!(
+        "Bearer {}",
+        common::access_token(&config, alice, reinstalled.device_id)
+    );
+    let req = test::TestRequest::post()
+        .uri("/api/v1/devices/link/create")
+        .insert_header(("Authorization", primary_token.as_str()))
+        .to_request();
+    let session: CreateLinkingSessionResponse = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::post()
+        .uri("/api/v1/devices/link/complete")
+        .set_json(CompleteLinkingRequest {
+            qr_code_token: session.qr_code_token,
+            device_uuid: Uuid::new_v4(),
+            device_name: None,
+            platform: Some(4),
+            keys: common::client_device_keys(),
+        })
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    let req = test::TestRequest::post()
+        .uri("/api/v1/devices/link/approve")
+        .insert_header(("Authorization", primary_token.as_str()))
+        .set_json(ApproveLinkingRequest {
+            session_id: session.session_id,
+            approve: true,
+        })
+        .to_request();
+    let approved: ApproveLinkingResponse = test::call_and_read_body_json(&app, req).await;
+    assert!(approved.identity_changed);
+    let linked_device = approved.new_device_id.unwrap();
+    let change = latest_change(&db, alice).await;
+    assert_eq!(change.device_id, linked_device);
+    assert_eq!(
+        next_identity_change(&mut bob_ws).await,
+        (change.change_id, alice)
+    );
+
+    // Unlinking it again
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/v1/devices/{}", linked_device))
+        .insert_header(("Authorization", primary_token.as_str()))
+        .to_request();
+    let unlinked: UnlinkDeviceResponse = test::call_and_read_body_json(&app, req).await;
+    assert!(unlinked.identity_changed);
+    let change = latest_change(&db, alice).await;
+    assert_eq!(change.device_id, linked_device);
+    assert_eq!(
+        next_identity_change(&mut bob_ws).await,
+        (change.change_id, alice)
+    );
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
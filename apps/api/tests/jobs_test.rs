@@ -0,0 +1,149 @@
+mod common;
+
+use actix_web::{test, web, App};
+use api::jobs::{spawn_presence_sweeper, spawn_signed_prekey_pruner};
+use api::websocket::connection::ConnectionManager;
+use api::websocket::handler::websocket_handler;
+use api::websocket::messages::{ServerEvent, WsMessage};
+use application::presence::use_cases::MarkDeviceOnlineUseCase;
+use chat_core::entities::{previous_signed_prekeys, users};
+use chrono::Utc;
+use common::WsClient;
+use infrastructure::database;
+use infrastructure::redis::RedisClient;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use std::time::Duration;
+
+#[actix_web::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn signed_prekey_pruner_deletes_expired_keys_on_each_tick() {
+    let (db, _) = common::setup().await;
+    let (user_id, device_id) = common::seed_user_with_device(&db).await;
+
+    let expiring_soon = Utc::now() + chrono::Duration::milliseconds(300);
+    for (signed_prekey_id, expires_at) in [
+        (7, expiring_soon),
+        (8, Utc::now() + chrono::Duration::days(1)),
+    ] {
+        previous_signed_prekeys::ActiveModel {
+            device_id: Set(device_id),
+            signed_prekey_id: Set(signed_prekey_id),
+            public_key: Set(vec![2; 32]),
+            signature: Set(vec![3; 64]),
+            replaced_at: Set(Utc::now().into()),
+            expires_at: Set(expires_at.into()),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+    }
+
+    spawn_signed_prekey_pruner(db.clone(), Duration::from_millis(100));
+
+    // Key 7 outlives the first ticks and is pruned by a later one
+    let remaining = || async {
+        previous_signed_prekeys::Entity::find()
+            .filter(previous_signed_prekeys::Column::DeviceId.eq(device_id))
+            .all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.signed_prekey_id)
+            .collect::<Vec<_>>()
+    };
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(remaining().await.contains(&7));
+
+    let mut pruned = false;
+    for _ in 0..30 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if remaining().await == vec![8] {
+            pruned = true;
+            break;
+        }
+    }
+    assert!(pruned, "expired signed prekey was not pruned");
+
+    common::cleanup_users(&db, &[user_id]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn presence_sweeper_announces_devices_that_stopped_sending_heartbeats() {
+    let (db, config) = common::setup().await;
+    let mut redis_conn = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let manager = web::Data::new(ConnectionManager::new());
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis_conn.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(manager.clone())
+            .service(websocket_handler),
+    )
+    .await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    // Alice's device was online on a node that died, so it never disconnects
+    let mut redis = RedisClient::new(redis_conn.clone());
+    MarkDeviceOnlineUseCase::execute(
+        &db,
+        &mut redis,
+        alice,
+        alice_device,
+        config.presence_ttl_seconds(),
+    )
+    .await
+    .unwrap();
+
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&app, &token, "&v=2").await;
+    bob_ws.send(&WsMessage::PresenceSubscribe {
+        user_ids: vec![alice],
+    });
+    bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::Presence(p) if p.user_id == alice && p.online))
+        .await;
+
+    redis::cmd("ZADD")
+        .arg(format!("user:{}:online", alice))
+        .arg(Utc::now().timestamp() - 1)
+        .arg(alice_device)
+        .query_async::<()>(&mut redis_conn)
+        .await
+        .unwrap();
+    spawn_presence_sweeper(
+        db.clone(),
+        RedisClient::new(redis_conn.clone()),
+        manager.clone(),
+        Duration::from_millis(100),
+    );
+
+    let offline = bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::Presence(p) if p.user_id == alice))
+        .await;
+    let ServerEvent::Presence(presence) = offline.event else {
+        unreachable!()
+    };
+    assert!(!presence.online);
+    let stored = users::Entity::find_by_id(alice)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!stored.is_online);
+    assert_eq!(
+        stored.last_seen_at.map(|t| t.timestamp()),
+        presence.last_seen_at
+    );
+
+    bob_ws.send(&WsMessage::PresenceUnsubscribe {
+        user_ids: vec![alice],
+    });
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
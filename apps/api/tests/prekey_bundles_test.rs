@@ -0,0 +1,57 @@
+mod common;
+
+use actix_web::{test, web, App};
+use api::handlers::keys::{get_all_prekey_bundles, get_prekey_bundle};
+use api::middleware::auth::AuthMiddleware;
+use api::websocket::connection::ConnectionManager;
+use application::keys::dtos::{PreKeyBundleResponse, UserPreKeyBundlesResponse};
+use infrastructure::database;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn bundle_routes_reject_anonymous_requests() {
+    let (db, config) = common::setup().await;
+    let redis = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let app = test::init_service(
+        App::new()
+            .wrap(AuthMiddleware)
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(ConnectionManager::new()))
+            .service(get_all_prekey_bundles)
+            .service(get_prekey_bundle),
+    )
+    .await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let single = format!("/api/v1/keys/{}/devices/{}", bob, bob_device);
+    let all = format!("/api/v1/keys/{}/devices/*", bob);
+
+    for uri in [&single, &all] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+
+    let token = format!(
+        "Bearer {}",
+        common::access_token(&config, alice, alice_device)
+    );
+    let req = test::TestRequest::get()
+        .uri(&single)
+        .insert_header(("Authorization", token.as_str()))
+        .to_request();
+    let bundle: PreKeyBundleResponse = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(bundle.device_id, bob_device);
+    let req = test::TestRequest::get()
+        .uri(&all)
+        .insert_header(("Authorization", token.as_str()))
+        .to_request();
+    let bundles: UserPreKeyBundlesResponse = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(bundles.devices.len(), 1);
+
+    common::cleanup_users(&db, &[alice, bob]).await;
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use actix_web::{test, web, App};
+use api::handlers::keys::get_prekey_bundle;
+use api::middleware::auth::AuthMiddleware;
+use api::websocket::connection::ConnectionManager;
+use api::websocket::handler::websocket_handler;
+use api::websocket::messages::ServerEvent;
+use application::keys::dtos::PreKeyDto;
+use application::keys::use_cases::{UploadPreKeysUseCase, PREKEY_LOW_THRESHOLD};
+use common::WsClient;
+use infrastructure::database;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn claiming_below_the_threshold_pushes_prekeys_low() {
+    let (db, config) = common::setup().await;
+    let redis = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let app = test::init_service(
+        App::new()
+            .wrap(AuthMiddleware)
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(ConnectionManager::new()))
+            .service(get_prekey_bundle)
+            .service(websocket_handler),
+    )
+    .await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let stock = PREKEY_LOW_THRESHOLD as i32;
+    UploadPreKeysUseCase::execute(
+        &db,
+        alice_device,
+        (1..=stock)
+            .map(|id| PreKeyDto {
+                id,
+                key: vec![id as u8; 32],
+            })
+            .collect(),
+    )
+    .await
+    .unwrap();
+
+    // A full stock is not reported on connect
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&app, &token, "&v=2").await;
+    let first = alice_ws.recv_event(|_| true).await;
+    assert!(matches!(first.event, ServerEvent::SyncResponse { .. }));
+    let sealed = alice_ws.recv_event(|_| true).await;
+    assert!(matches!(
+        sealed.event,
+        ServerEvent::SealedSyncResponse { .. }
+    ));
+
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let bob_token = format!("Bearer {}", common::access_token(&config, bob, bob_device));
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/v1/keys/{}/devices/{}", alice, alice_device))
+        .insert_header(("Authorization", bob_token.as_str()))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let low = alice_ws.recv_event(|_| true).await;
+    assert!(matches!(
+        low.event,
+        ServerEvent::PreKeysLow { remaining } if remaining == PREKEY_LOW_THRESHOLD - 1
+    ));
+
+    common::cleanup_users(&db, &[alice, bob]).await;
+}
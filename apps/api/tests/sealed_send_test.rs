@@ -0,0 +1,79 @@
+mod common;
+
+use actix_web::{test, web, App};
+use api::handlers::sealed_sender::{get_sender_certificate, send_sealed_message};
+use api::middleware::auth::AuthMiddleware;
+use api::websocket::connection::ConnectionManager;
+use application::sealed_sender::dtos::SendSealedMessageRequest;
+use application::sealed_sender::use_cases::{SetUnidentifiedAccessUseCase, MAX_SEALED_MESSAGE_LEN};
+use infrastructure::database;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn rejected_sealed_sends_map_to_client_errors() {
+    let (db, config) = common::setup().await;
+    let redis = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis))
+            .app_data(web::Data::new(ConnectionManager::new()))
+            .service(send_sealed_message),
+    )
+    .await;
+
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], false)
+        .await
+        .unwrap();
+    let sealed = |access_key: Vec<u8>, len: usize| SendSealedMessageRequest {
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        access_key: Some(access_key),
+        content: vec![0; len],
+    };
+
+    for (request, status) in [
+        (sealed(vec![2; 16], 16), 401),
+        (sealed(vec![1; 16], MAX_SEALED_MESSAGE_LEN + 1), 413),
+        (sealed(vec![1; 16], 16), 200),
+    ] {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/messages/sealed")
+            .set_json(&request)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+    }
+
+    common::cleanup_users(&db, &[bob]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn certificates_are_refused_without_a_configured_secret() {
+    let (db, mut config) = common::setup().await;
+    config.sender_certificate_secret = None;
+    let app = test::init_service(
+        App::new()
+            .wrap(AuthMiddleware)
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .service(get_sender_certificate),
+    )
+    .await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let token = format!(
+        "Bearer {}",
+        common::access_token(&config, alice, alice_device)
+    );
+    let req = test::TestRequest::get()
+        .uri("/api/v1/certificate/delivery")
+        .insert_header(("Authorization", token.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 503);
+
+    common::cleanup_users(&db, &[alice]).await;
+}
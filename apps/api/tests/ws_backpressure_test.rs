@@ -0,0 +1,193 @@
+mod common;
+
+use api::websocket::messages::{ServerEvent, WsMessage};
+use common::WsClient;
+use std::cell::Cell;
+use std::time::Duration;
+use uuid::Uuid;
+
+const QUEUE_CAPACITY: usize = 8;
+const BURST: usize = 200;
+/// More than the queue plus the 32 frames actix-ws buffers per session, but within what
+/// may be deferred
+const SHORT_BURST: usize = 7 * QUEUE_CAPACITY;
+
+fn signal_message(
+    conversation_id: Uuid,
+    recipient_id: Uuid,
+    recipient_device_id: i64,
+) -> WsMessage {
+    WsMessage::SignalMessage {
+        conversation_id,
+        client_message_id: Uuid::new_v4(),
+        recipient_id,
+        recipient_device_id,
+        content: b"ciphertext".to_vec(),
+    }
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn slow_readers_do_not_block_other_users() {
+    let (db, mut config) = common::setup().await;
+    config.ws_outbound_queue_capacity = QUEUE_CAPACITY;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let (carol, carol_device) = common::seed_user_with_device(&db).await;
+    let alice_conv = common::seed_direct_conversation(&db, bob, alice).await;
+    let carol_conv = common::seed_direct_conversation(&db, bob, carol).await;
+
+    // Alice connects and never reads
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "").await;
+    let token = common::access_token(&config, carol, carol_device);
+    let mut carol_ws = WsClient::connect(&node, &token, "").await;
+
+    // Each send is acknowledged promptly, long after Alice's buffers are full
+    for _ in 0..BURST {
+        bob_ws.send(&signal_message(alice_conv, alice, alice_device));
+        bob_ws
+            .recv(|m| matches!(m, WsMessage::SendAck { .. }))
+            .await;
+    }
+    bob_ws.send(&signal_message(carol_conv, carol, carol_device));
+    carol_ws
+        .recv(|m| matches!(m, WsMessage::SignalMessage { .. }))
+        .await;
+
+    // Alice was disconnected rather than left holding an ever-growing backlog
+    let mut metrics = common::metrics(&node).await;
+    for _ in 0..50 {
+        if metrics.open_connections == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        metrics = common::metrics(&node).await;
+    }
+    assert_eq!(metrics.overflowed_connections, 1);
+    assert_eq!(metrics.open_connections, 2);
+    let delivered = alice_ws.drain().await;
+    assert!(delivered < BURST, "all {delivered} frames reached Alice");
+
+    // Nothing is lost: the messages she missed are waiting for her on reconnect
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "").await;
+    let WsMessage::SyncResponse { messages, .. } = alice_ws
+        .recv(|m| matches!(m, WsMessage::SyncResponse { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert!(!messages.is_empty());
+
+    common::cleanup(&db, carol_conv, &[]).await;
+    common::cleanup(&db, alice_conv, &[alice, bob, carol]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn typing_is_shed_before_slow_readers_are_disconnected() {
+    let (db, mut config) = common::setup().await;
+    config.ws_outbound_queue_capacity = QUEUE_CAPACITY;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, bob, alice).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "").await;
+
+    for _ in 0..BURST {
+        bob_ws.send(&WsMessage::Typing {
+            conversation_id: conv_id,
+            recipient_id: alice,
+            is_typing: true,
+        });
+    }
+    // The ack means Bob's frames were all handled, typing included
+    bob_ws.send(&signal_message(conv_id, alice, alice_device));
+    bob_ws
+        .recv(|m| matches!(m, WsMessage::SendAck { .. }))
+        .await;
+
+    let metrics = common::metrics(&node).await;
+    assert_eq!(metrics.overflowed_connections, 0);
+    assert_eq!(metrics.open_connections, 2);
+
+    // The message still made it past the typing indicators that did fit
+    let typing = Cell::new(0);
+    alice_ws
+        .recv(|m| {
+            if matches!(m, WsMessage::Typing { .. }) {
+                typing.set(typing.get() + 1);
+            }
+            matches!(m, WsMessage::SignalMessage { .. })
+        })
+        .await;
+    assert!(typing.get() < BURST, "no typing events were dropped");
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn short_bursts_above_capacity_are_deferred_not_disconnected() {
+    let (db, mut config) = common::setup().await;
+    config.ws_outbound_queue_capacity = QUEUE_CAPACITY;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, bob, alice).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "&v=2").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "").await;
+
+    // Alice reads nothing while the burst arrives
+    let mut sent = Vec::new();
+    for _ in 0..SHORT_BURST {
+        let message = signal_message(conv_id, alice, alice_device);
+        let WsMessage::SignalMessage {
+            client_message_id, ..
+        } = &message
+        else {
+            unreachable!()
+        };
+        sent.push(*client_message_id);
+        bob_ws.send(&message);
+        bob_ws
+            .recv(|m| matches!(m, WsMessage::SendAck { .. }))
+            .await;
+    }
+
+    // ...then catches up well within the grace period and gets all of it, in order
+    let mut received = Vec::new();
+    while received.len() < SHORT_BURST {
+        let envelope = alice_ws
+            .recv_event(|e| matches!(e, ServerEvent::Message { .. }))
+            .await;
+        let ServerEvent::Message {
+            client_message_id, ..
+        } = envelope.event
+        else {
+            unreachable!()
+        };
+        received.push(client_message_id);
+    }
+    assert_eq!(received, sent);
+
+    let metrics = common::metrics(&node).await;
+    assert_eq!(metrics.overflowed_connections, 0);
+    assert_eq!(metrics.open_connections, 2);
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
@@ -0,0 +1,212 @@
+use actix_ws::Message;
+use api::websocket::messages::{
+    Encoding, EventEnvelope, OutboundEvent, ServerEvent, WsMessage, LATEST_PROTOCOL_VERSION,
+};
+use application::chat::dtos::{DeviceMessageDto, SyncMessageDto};
+use application::sealed_sender::dtos::SealedMessageDto;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use uuid::Uuid;
+
+/// A ciphertext the size of a typical message with its Signal Protocol overhead
+fn ciphertext() -> Vec<u8> {
+    (0..1024u32).map(|i| (i * 151 % 256) as u8).collect()
+}
+
+fn frame_bytes(frame: Message) -> Vec<u8> {
+    match frame {
+        Message::Text(text) => text.as_bytes().to_vec(),
+        Message::Binary(bytes) => bytes.to_vec(),
+        other => panic!("unexpected frame {other:?}"),
+    }
+}
+
+fn round_trip<T: Serialize + DeserializeOwned + Debug>(encoding: Encoding, value: &T) -> Vec<u8> {
+    let bytes = frame_bytes(encoding.encode(value).unwrap());
+    let decoded: T = encoding.decode(&bytes).unwrap();
+    assert_eq!(format!("{decoded:?}"), format!("{value:?}"));
+    bytes
+}
+
+fn signal_message() -> WsMessage {
+    WsMessage::SignalMessage {
+        conversation_id: Uuid::new_v4(),
+        client_message_id: Uuid::new_v4(),
+        recipient_id: Uuid::new_v4(),
+        recipient_device_id: 2,
+        content: ciphertext(),
+    }
+}
+
+fn client_frames() -> Vec<WsMessage> {
+    let conversation_id = Uuid::new_v4();
+    vec![
+        signal_message(),
+        WsMessage::MultiDeviceSend {
+            conversation_id,
+            client_message_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            messages: vec![DeviceMessageDto {
+                recipient_id: Uuid::new_v4(),
+                recipient_device_id: 1,
+                content: ciphertext(),
+            }],
+        },
+        WsMessage::SealedSend {
+            recipient_id: Uuid::new_v4(),
+            recipient_device_id: 1,
+            access_key: Some(vec![7; 16]),
+            content: ciphertext(),
+        },
+        WsMessage::SyncRequest {
+            cursor: None,
+            limit: Some(50),
+        },
+        WsMessage::SealedSyncRequest {
+            after_delivery_id: Some(7),
+            limit: None,
+        },
+        WsMessage::Ack { message_id: 42 },
+    ]
+}
+
+fn server_events() -> Vec<OutboundEvent> {
+    let conversation_id = Uuid::new_v4();
+    vec![
+        OutboundEvent::from_device(
+            Uuid::new_v4(),
+            3,
+            ServerEvent::GroupMessage {
+                message_id: 9,
+                conversation_id,
+                client_message_id: Uuid::new_v4(),
+                content: ciphertext(),
+                sender_key_distribution: Some(vec![1; 64]),
+                sent_at: 1_700_000_000,
+            },
+        ),
+        OutboundEvent::server(ServerEvent::SyncResponse {
+            messages: vec![SyncMessageDto {
+                message_id: 10,
+                conversation_id,
+                client_message_id: None,
+                sender_id: Uuid::new_v4(),
+                sender_device_id: 1,
+                content: ciphertext(),
+                sender_key_distribution: None,
+                sent_at: 1_700_000_001,
+            }],
+            cursor: Some("opaque".to_string()),
+            has_more: false,
+        }),
+        OutboundEvent::server(ServerEvent::SealedSyncResponse {
+            messages: vec![SealedMessageDto {
+                delivery_id: 11,
+                content: ciphertext(),
+                received_at: 1_700_000_002,
+            }],
+            has_more: true,
+        }),
+    ]
+}
+
+#[test]
+fn client_frames_round_trip_in_both_encodings() {
+    for frame in client_frames() {
+        round_trip(Encoding::Json, &frame);
+        round_trip(Encoding::MessagePack, &frame);
+    }
+}
+
+#[test]
+fn server_frames_round_trip_in_both_encodings() {
+    for event in server_events() {
+        let envelope: EventEnvelope<&ServerEvent> = event.envelope(LATEST_PROTOCOL_VERSION, 1);
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            let bytes = frame_bytes(encoding.encode(&envelope).unwrap());
+            let decoded: EventEnvelope = encoding.decode(&bytes).unwrap();
+            assert_eq!(decoded.seq, 1);
+            assert_eq!(decoded.from_user_id, event.from_user_id);
+            assert_eq!(format!("{:?}", decoded.event), format!("{:?}", event.event));
+        }
+
+        let legacy = event.to_v1(Uuid::new_v4(), 1);
+        round_trip(Encoding::Json, &legacy);
+        round_trip(Encoding::MessagePack, &legacy);
+    }
+}
+
+#[test]
+fn json_frames_are_text_and_msgpack_frames_binary() {
+    let frame = WsMessage::Ack { message_id: 1 };
+    assert!(matches!(
+        Encoding::Json.encode(&frame).unwrap(),
+        Message::Text(_)
+    ));
+    assert!(matches!(
+        Encoding::MessagePack.encode(&frame).unwrap(),
+        Message::Binary(_)
+    ));
+}
+
+#[test]
+fn json_keeps_byte_strings_as_number_arrays() {
+    let frame = WsMessage::SignalMessage {
+        conversation_id: Uuid::nil(),
+        client_message_id: Uuid::nil(),
+        recipient_id: Uuid::nil(),
+        recipient_device_id: 1,
+        content: vec![1, 2, 255],
+    };
+    let json: serde_json::Value =
+        serde_json::from_slice(&frame_bytes(Encoding::Json.encode(&frame).unwrap())).unwrap();
+    assert_eq!(json["payload"]["content"], serde_json::json!([1, 2, 255]));
+}
+
+#[test]
+fn msgpack_frames_are_a_fraction_of_json() {
+    let content_len = ciphertext().len();
+    let frame = signal_message();
+    let json = round_trip(Encoding::Json, &frame).len();
+    let msgpack = round_trip(Encoding::MessagePack, &frame).len();
+    println!(
+        "{content_len} byte ciphertext: JSON frame {json} bytes, MessagePack frame {msgpack} bytes"
+    );
+
+    // JSON spells each byte as up to three digits and a comma; MessagePack stores it raw
+    assert!(json > content_len * 3, "JSON frame was {json} bytes");
+    assert!(
+        msgpack < content_len + 200,
+        "MessagePack frame was {msgpack} bytes"
+    );
+}
+
+#[test]
+fn correlation_ids_are_recovered_from_undecodable_frames() {
+    #[derive(Serialize)]
+    struct Payload {
+        client_message_id: Uuid,
+        recipient_device_id: &'static str,
+    }
+    #[derive(Serialize)]
+    struct Frame {
+        #[serde(rename = "type")]
+        kind: &'static str,
+        payload: Payload,
+    }
+
+    let client_message_id = Uuid::new_v4();
+    let frame = Frame {
+        kind: "SignalMessage",
+        payload: Payload {
+            client_message_id,
+            recipient_device_id: "one",
+        },
+    };
+    for encoding in [Encoding::Json, Encoding::MessagePack] {
+        let bytes = frame_bytes(encoding.encode(&frame).unwrap());
+        assert!(encoding.decode::<WsMessage>(&bytes).is_err());
+        assert_eq!(encoding.client_message_id(&bytes), Some(client_message_id));
+        assert_eq!(encoding.client_message_id(b"\xc1"), None);
+    }
+}
@@ -0,0 +1,253 @@
+mod common;
+
+use api::websocket::messages::{
+    DeliveryStatusType, ServerEvent, WsMessage, LATEST_PROTOCOL_VERSION,
+};
+use common::WsClient;
+use uuid::Uuid;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn version_2_clients_receive_sequenced_envelopes() {
+    let (db, config) = common::setup().await;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "&v=2").await;
+    // Versions newer than the server's are negotiated down
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "&v=99").await;
+
+    // The seeded device has no one-time prekeys, so the server warns about that before
+    // pushing the first sync page; neither event has a sender
+    let first = alice_ws.recv_event(|_| true).await;
+    assert_eq!(first.seq, 1);
+    assert_eq!(first.v, 2);
+    assert!(matches!(first.event, ServerEvent::PreKeysLow { .. }));
+    assert_eq!(first.from_user_id, None);
+    assert_eq!(first.from_device_id, None);
+    let sync = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::SyncResponse { .. }))
+        .await;
+    assert_eq!(sync.from_user_id, None);
+
+    let sync = bob_ws.recv_event(|_| true).await;
+    assert_eq!(sync.v, LATEST_PROTOCOL_VERSION);
+
+    let client_message_id = Uuid::new_v4();
+    alice_ws.send(&WsMessage::SignalMessage {
+        conversation_id: conv_id,
+        client_message_id,
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        content: b"ciphertext".to_vec(),
+    });
+    let ack = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::SendAck { .. }))
+        .await;
+    let ServerEvent::SendAck {
+        message_id,
+        sent_at,
+        ..
+    } = ack.event
+    else {
+        unreachable!()
+    };
+
+    let message = bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::Message { .. }))
+        .await;
+    assert_eq!(message.from_user_id, Some(alice));
+    assert_eq!(message.from_device_id, Some(alice_device));
+    let ServerEvent::Message {
+        message_id: received_id,
+        client_message_id: received_client_id,
+        content,
+        sent_at: received_sent_at,
+        ..
+    } = message.event
+    else {
+        unreachable!()
+    };
+    assert_eq!(received_id, message_id);
+    assert_eq!(received_client_id, client_message_id);
+    assert_eq!(received_sent_at, sent_at);
+    assert_eq!(content, b"ciphertext");
+
+    // Receipts and typing name the acting device in the envelope, not the payload
+    bob_ws.send(&WsMessage::DeliveryStatus {
+        message_id,
+        conversation_id: conv_id,
+        sender_id: alice,
+        status: DeliveryStatusType::Read,
+    });
+    let receipt = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::DeliveryStatus { .. }))
+        .await;
+    assert_eq!(receipt.from_user_id, Some(bob));
+    assert_eq!(receipt.from_device_id, Some(bob_device));
+    assert!(matches!(
+        receipt.event,
+        ServerEvent::DeliveryStatus {
+            status: DeliveryStatusType::Read,
+            ..
+        }
+    ));
+
+    alice_ws.send(&WsMessage::Typing {
+        conversation_id: conv_id,
+        recipient_id: bob,
+        is_typing: true,
+    });
+    let typing = bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::Typing { .. }))
+        .await;
+    assert_eq!(typing.from_user_id, Some(alice));
+    assert_eq!(typing.from_device_id, Some(alice_device));
+    assert!(typing.server_timestamp > 0);
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn version_1_clients_keep_the_legacy_frames() {
+    let (db, config) = common::setup().await;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "&v=2").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "&v=1").await;
+
+    alice_ws.send(&WsMessage::SignalMessage {
+        conversation_id: conv_id,
+        client_message_id: Uuid::new_v4(),
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        content: b"ciphertext".to_vec(),
+    });
+    let WsMessage::SignalMessage {
+        recipient_id,
+        recipient_device_id,
+        content,
+        ..
+    } = bob_ws
+        .recv(|m| matches!(m, WsMessage::SignalMessage { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(recipient_id, bob);
+    assert_eq!(recipient_device_id, bob_device);
+    assert_eq!(content, b"ciphertext");
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn msgpack_clients_exchange_binary_frames_with_json_clients() {
+    let (db, config) = common::setup().await;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "&v=2&encoding=msgpack").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node, &token, "&v=2").await;
+
+    // Alice writes binary frames and Bob reads the same message as JSON
+    alice_ws.send(&WsMessage::SignalMessage {
+        conversation_id: conv_id,
+        client_message_id: Uuid::new_v4(),
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        content: vec![0, 1, 254, 255],
+    });
+    let ack = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::SendAck { .. }))
+        .await;
+    let ServerEvent::SendAck { message_id, .. } = ack.event else {
+        unreachable!()
+    };
+    let message = bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::Message { .. }))
+        .await;
+    let ServerEvent::Message { content, .. } = message.event else {
+        unreachable!()
+    };
+    assert_eq!(content, [0, 1, 254, 255]);
+
+    // And the other way round
+    bob_ws.send(&WsMessage::DeliveryStatus {
+        message_id,
+        conversation_id: conv_id,
+        sender_id: alice,
+        status: DeliveryStatusType::Delivered,
+    });
+    let receipt = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::DeliveryStatus { .. }))
+        .await;
+    assert_eq!(receipt.from_user_id, Some(bob));
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn malformed_frames_are_answered_with_an_error() {
+    let (db, config) = common::setup().await;
+    let node = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node, &token, "&v=2").await;
+
+    // The send it belongs to is named when the correlation id is still readable
+    let client_message_id = Uuid::new_v4();
+    alice_ws.send_text(&format!(
+        r#"{{"type":"SignalMessage","payload":{{"client_message_id":"{client_message_id}","recipient_device_id":"one"}}}}"#
+    ));
+    let error = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::Error { .. }))
+        .await;
+    let ServerEvent::Error {
+        code,
+        client_message_id: failed,
+        ..
+    } = error.event
+    else {
+        unreachable!()
+    };
+    assert_eq!(code, "invalid_message");
+    assert_eq!(failed, Some(client_message_id));
+
+    alice_ws.send_text("not json");
+    let error = alice_ws
+        .recv_event(|e| matches!(e, ServerEvent::Error { .. }))
+        .await;
+    let ServerEvent::Error {
+        code,
+        client_message_id: failed,
+        ..
+    } = error.event
+    else {
+        unreachable!()
+    };
+    assert_eq!(code, "invalid_message");
+    assert_eq!(failed, None);
+
+    common::cleanup_users(&db, &[alice]).await;
+}
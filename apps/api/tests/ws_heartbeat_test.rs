@@ -0,0 +1,106 @@
+mod common;
+
+use actix_http::ws::CloseCode;
+use api::websocket::messages::WsMessage;
+use common::WsClient;
+use infrastructure::database;
+use std::time::Duration;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn silent_connections_are_reaped() {
+    let (db, mut config) = common::setup().await;
+    config.ws_heartbeat_interval_seconds = 1;
+    config.ws_idle_timeout_seconds = 2;
+    let node = common::node(&db, &config).await;
+
+    let (user, device) = common::seed_user_with_device(&db).await;
+    let token = common::access_token(&config, user, device);
+    let mut ws = WsClient::connect(&node, &token, "").await;
+    ws.go_silent();
+
+    let reason = tokio::time::timeout(Duration::from_secs(10), ws.recv_close())
+        .await
+        .expect("the silent connection was never closed")
+        .expect("close frame without a reason");
+    assert_eq!(reason.code, CloseCode::Away);
+    assert!(ws.pings() >= 1, "the server never pinged");
+
+    // The handler removes the connection right after closing it
+    let mut metrics = common::metrics(&node).await;
+    for _ in 0..50 {
+        if metrics.open_connections == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        metrics = common::metrics(&node).await;
+    }
+    assert_eq!(metrics.open_connections, 0);
+    assert_eq!(metrics.reaped_connections, 1);
+
+    common::cleanup_users(&db, &[user]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn connections_answering_pings_stay_open() {
+    let (db, mut config) = common::setup().await;
+    config.ws_heartbeat_interval_seconds = 1;
+    config.ws_idle_timeout_seconds = 2;
+    let node = common::node(&db, &config).await;
+
+    let (user, device) = common::seed_user_with_device(&db).await;
+    let token = common::access_token(&config, user, device);
+    let mut ws = WsClient::connect(&node, &token, "").await;
+
+    // Pongs are the only frames the client sends
+    ws.idle_for(Duration::from_secs(4)).await;
+    assert!(ws.pings() >= 3, "only {} pings", ws.pings());
+
+    ws.send(&WsMessage::SyncRequest {
+        cursor: None,
+        limit: None,
+    });
+    ws.recv(|m| matches!(m, WsMessage::SyncResponse { .. }))
+        .await;
+
+    let metrics = common::metrics(&node).await;
+    assert_eq!(metrics.open_connections, 1);
+    assert_eq!(metrics.reaped_connections, 0);
+
+    common::cleanup_users(&db, &[user]).await;
+}
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn presence_outlives_the_configured_heartbeat_interval() {
+    let (db, mut config) = common::setup().await;
+    config.ws_heartbeat_interval_seconds = 120;
+    config.ws_idle_timeout_seconds = 360;
+    let node = common::node(&db, &config).await;
+    let mut redis = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+
+    let (user, device) = common::seed_user_with_device(&db).await;
+    let token = common::access_token(&config, user, device);
+    let before = chrono::Utc::now().timestamp();
+    let mut ws = WsClient::connect(&node, &token, "").await;
+    ws.send(&WsMessage::SyncRequest {
+        cursor: None,
+        limit: None,
+    });
+    ws.recv(|m| matches!(m, WsMessage::SyncResponse { .. }))
+        .await;
+
+    // Slow heartbeats must not let the device lapse between two of them
+    let expires_at: f64 = redis::cmd("ZSCORE")
+        .arg(format!("user:{}:online", user))
+        .arg(device)
+        .query_async(&mut redis)
+        .await
+        .unwrap();
+    assert!(expires_at as i64 >= before + 3 * 120);
+
+    common::cleanup_users(&db, &[user]).await;
+}
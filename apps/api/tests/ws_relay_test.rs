@@ -0,0 +1,112 @@
+mod common;
+
+use api::websocket::messages::{DeliveryStatusType, WsMessage};
+use common::WsClient;
+use uuid::Uuid;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn frames_reach_devices_connected_to_another_node() {
+    let (db, config) = common::setup().await;
+    // Two API nodes sharing the database and Redis, as behind a load balancer
+    let node_a = common::node(&db, &config).await;
+    let node_b = common::node(&db, &config).await;
+
+    let (alice, alice_device) = common::seed_user_with_device(&db).await;
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    let conv_id = common::seed_direct_conversation(&db, alice, bob).await;
+
+    let token = common::access_token(&config, alice, alice_device);
+    let mut alice_ws = WsClient::connect(&node_a, &token, "").await;
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&node_b, &token, "").await;
+
+    alice_ws.send(&WsMessage::SignalMessage {
+        conversation_id: conv_id,
+        client_message_id: Uuid::new_v4(),
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        content: b"ciphertext".to_vec(),
+    });
+    let WsMessage::SendAck { message_id, .. } = alice_ws
+        .recv(|m| matches!(m, WsMessage::SendAck { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    let WsMessage::SignalMessage { content, .. } = bob_ws
+        .recv(|m| matches!(m, WsMessage::SignalMessage { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(content, b"ciphertext");
+
+    // Receipts travel back to the sender's node
+    bob_ws.send(&WsMessage::DeliveryStatus {
+        message_id,
+        conversation_id: conv_id,
+        sender_id: alice,
+        status: DeliveryStatusType::Delivered,
+    });
+    let WsMessage::DeliveryStatus {
+        message_id: receipt_for,
+        status,
+        ..
+    } = alice_ws
+        .recv(|m| matches!(m, WsMessage::DeliveryStatus { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(receipt_for, message_id);
+    assert_eq!(status, DeliveryStatusType::Delivered);
+
+    alice_ws.send(&WsMessage::Typing {
+        conversation_id: conv_id,
+        recipient_id: bob,
+        is_typing: true,
+    });
+    let WsMessage::Typing {
+        recipient_id: typing_user,
+        is_typing,
+        ..
+    } = bob_ws.recv(|m| matches!(m, WsMessage::Typing { .. })).await
+    else {
+        unreachable!()
+    };
+    assert_eq!(typing_user, alice);
+    assert!(is_typing);
+
+    // Presence changes on one node reach watchers on the other
+    bob_ws.send(&WsMessage::PresenceSubscribe {
+        user_ids: vec![alice],
+    });
+    let WsMessage::Presence {
+        user_id, online, ..
+    } = bob_ws
+        .recv(|m| matches!(m, WsMessage::Presence { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(user_id, alice);
+    assert!(online);
+
+    drop(alice_ws);
+    let WsMessage::Presence {
+        user_id,
+        online,
+        last_seen_at,
+    } = bob_ws
+        .recv(|m| matches!(m, WsMessage::Presence { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    assert_eq!(user_id, alice);
+    assert!(!online);
+    assert!(last_seen_at.is_some());
+
+    common::cleanup(&db, conv_id, &[alice, bob]).await;
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use actix_web::{test, web, App};
+use api::websocket::connection::ConnectionManager;
+use api::websocket::handler::websocket_handler;
+use api::websocket::messages::{ServerEvent, WsMessage};
+use application::sealed_sender::dtos::SendSealedMessageRequest;
+use application::sealed_sender::use_cases::{
+    SendSealedMessageUseCase, SetUnidentifiedAccessUseCase, MAX_SEALED_MESSAGES_PAGE,
+};
+use common::WsClient;
+use infrastructure::database;
+use infrastructure::redis::RedisClient;
+
+#[actix_web::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn sealed_messages_are_synced_in_pages() {
+    let (db, config) = common::setup().await;
+    let redis_conn = database::init_redis(&config.redis_url)
+        .await
+        .expect("Failed to connect Redis");
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(db.clone()))
+            .app_data(web::Data::new(redis_conn.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(ConnectionManager::new()))
+            .service(websocket_handler),
+    )
+    .await;
+
+    let (bob, bob_device) = common::seed_user_with_device(&db).await;
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], false)
+        .await
+        .unwrap();
+    let mut redis = RedisClient::new(redis_conn);
+    let queued = MAX_SEALED_MESSAGES_PAGE + 20;
+    let mut sent = Vec::new();
+    for _ in 0..queued {
+        let message = SendSealedMessageUseCase::execute(
+            &db,
+            &mut redis,
+            SendSealedMessageRequest {
+                recipient_id: bob,
+                recipient_device_id: bob_device,
+                access_key: Some(vec![1; 16]),
+                content: b"sealed".to_vec(),
+            },
+        )
+        .await
+        .unwrap();
+        sent.push(message.delivery_id);
+    }
+
+    // The first page is pushed on connect
+    let token = common::access_token(&config, bob, bob_device);
+    let mut bob_ws = WsClient::connect(&app, &token, "&v=2").await;
+    let first = bob_ws
+        .recv_event(|e| matches!(e, ServerEvent::SealedSyncResponse { .. }))
+        .await;
+    let ServerEvent::SealedSyncResponse { messages, has_more } = first.event else {
+        unreachable!()
+    };
+    assert!(has_more);
+    let mut paged: Vec<i64> = messages.iter().map(|m| m.delivery_id).collect();
+    assert_eq!(paged.len() as u64, MAX_SEALED_MESSAGES_PAGE);
+
+    // A regular sync no longer re-pushes queued sealed messages
+    bob_ws.send(&WsMessage::SyncRequest {
+        cursor: None,
+        limit: None,
+    });
+    bob_ws.send(&WsMessage::SealedSyncRequest {
+        after_delivery_id: paged.last().copied(),
+        limit: None,
+    });
+    loop {
+        let envelope = bob_ws.recv_event(|_| true).await;
+        match envelope.event {
+            ServerEvent::SealedSyncResponse { messages, has_more } => {
+                assert!(!has_more);
+                paged.extend(messages.iter().map(|m| m.delivery_id));
+                break;
+            }
+            ServerEvent::SealedMessage { .. } => panic!("sealed message pushed outside a page"),
+            _ => {}
+        }
+    }
+    assert_eq!(paged, sent);
+
+    common::cleanup_users(&db, &[bob]).await;
+}
@@ -1,3 +1,4 @@
+use crate::keys::dtos::DeviceKeysDto;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -33,6 +34,7 @@ pub struct VerifyOtpRequest {
     pub device_uuid: Uuid,
     pub device_name: Option<String>,
     pub platform: Option<i16>, // 1 = iOS, 2 = Android, 3 = Web, 4 = Desktop
+    pub keys: DeviceKeysDto,   // Public Signal keys generated on the device
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -44,6 +46,7 @@ pub struct VerifyOtpResponse {
     pub is_new_user: bool,
     pub requires_profile_setup: bool,
     pub requires_pin: bool, // true if registration_lock is enabled
+    pub identity_changed: bool, // true if this changed the set of the user's identity keys
 }
 
 // ============ Profile Setup ============
@@ -129,6 +132,7 @@ pub struct CompleteLinkingRequest {
     pub device_uuid: Uuid,
     pub device_name: Option<String>,
     pub platform: Option<i16>,
+    pub keys: DeviceKeysDto, // Public Signal keys generated on the new device
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -149,6 +153,7 @@ pub struct ApproveLinkingResponse {
     pub session_id: Uuid,
     pub new_device_id: Option<i64>,
     pub status: String, // "approved" or "rejected"
+    pub identity_changed: bool, // true if the new device brought a new identity key
 }
 
 // ============ Device Management ============
@@ -181,6 +186,7 @@ pub struct UnlinkDeviceRequest {
 pub struct UnlinkDeviceResponse {
     pub unlinked: bool,
     pub message: String,
+    pub identity_changed: bool, // true if the device's identity key left the user's set
 }
 
 // ============ Auth Error Types ============
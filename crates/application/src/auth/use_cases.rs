@@ -1,22 +1,23 @@
 use crate::auth::dtos::*;
+use crate::keys::{dtos::DeviceKeysDto, validation::validate_device_keys};
 use anyhow::{anyhow, Result};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use chrono::{Duration, Utc};
-use core::entities::{device_linking_sessions, devices, one_time_prekeys, users};
-use infrastructure::crypto::signal::{
-    generate_identity_keypair, generate_prekeys, generate_registration_id, generate_signed_prekey,
+use chat_core::entities::{
+    device_linking_sessions, devices, identity_key_changes, one_time_prekeys, users,
 };
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use rand::Rng;
 use redis::aio::MultiplexedConnection;
 use redis::AsyncCommands;
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
-    QueryOrder, Set, TransactionTrait,
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
 };
+use std::collections::BTreeSet;
 use uuid::Uuid;
 
 // ============ Config ============
@@ -90,6 +91,9 @@ impl VerifyOtpUseCase {
         config: &AuthConfig,
         req: VerifyOtpRequest,
     ) -> Result<VerifyOtpResponse> {
+        // Reject malformed keys before consuming the OTP so the client can retry
+        validate_device_keys(&req.keys)?;
+
         let key = format!("otp:{}", req.phone_number);
         let stored_otp: Option<String> = redis_conn.get(&key).await?;
 
@@ -109,11 +113,12 @@ impl VerifyOtpUseCase {
             .one(&txn)
             .await?;
 
-        let (user, is_new_user) = match existing_user {
+        let (user, is_new_user, old_identity_keys) = match existing_user {
             Some(u) => {
+                let old_identity_keys = active_identity_keys(&txn, u.user_id).await?;
                 // Existing user - kick old primary device if this is a new primary login
                 Self::kick_old_primary_device(&txn, u.user_id).await?;
-                (u, false)
+                (u, false, old_identity_keys)
             }
             None => {
                 // Create new user
@@ -127,6 +132,7 @@ impl VerifyOtpUseCase {
                     profile_picture: Set(None),
                     last_seen_at: Set(None),
                     is_online: Set(false),
+                    last_seen_visibility: Set(0),
                     is_deleted: Set(false),
                     deleted_at: Set(None),
                     created_at: Set(Utc::now().into()),
@@ -136,7 +142,7 @@ impl VerifyOtpUseCase {
                     registration_lock_expires_at: Set(None),
                     pin_set_at: Set(None),
                 };
-                (new_user.insert(&txn).await?, true)
+                (new_user.insert(&txn).await?, true, BTreeSet::new())
             }
         };
 
@@ -144,11 +150,7 @@ impl VerifyOtpUseCase {
         let requires_profile_setup = user.display_name.is_none();
         let requires_pin = user.registration_lock && user.pin_hash.is_some();
 
-        // Generate Signal Keys
-        let (identity_key_pair, _) = generate_identity_keypair()?;
-        let registration_id = generate_registration_id();
-        let signed_prekey = generate_signed_prekey(&identity_key_pair, 1)?;
-        let one_time_prekeys_list = generate_prekeys(1, 100)?;
+        let keys = req.keys;
 
         // Create Device (Primary for OTP login)
         let device = devices::ActiveModel {
@@ -156,11 +158,11 @@ impl VerifyOtpUseCase {
             device_uuid: Set(req.device_uuid),
             device_name: Set(req.device_name.clone()),
             platform: Set(req.platform.unwrap_or(1)),
-            identity_key_public: Set(identity_key_pair.public_key),
-            registration_id: Set(registration_id as i32),
-            signed_prekey_id: Set(signed_prekey.id as i32),
-            signed_prekey_public: Set(signed_prekey.public_key),
-            signed_prekey_signature: Set(signed_prekey.signature),
+            identity_key_public: Set(keys.identity_key),
+            registration_id: Set(keys.registration_id),
+            signed_prekey_id: Set(keys.signed_prekey.id),
+            signed_prekey_public: Set(keys.signed_prekey.key),
+            signed_prekey_signature: Set(keys.signed_prekey.signature),
             last_seen_at: Set(Utc::now().into()),
             created_at: Set(Utc::now().into()),
             device_type: Set(DEVICE_TYPE_PRIMARY),
@@ -173,15 +175,18 @@ impl VerifyOtpUseCase {
         let device = device.insert(&txn).await?;
 
         // Insert One Time Prekeys
-        for prekey in one_time_prekeys_list {
+        for prekey in keys.one_time_prekeys {
             let otpk = one_time_prekeys::ActiveModel {
                 device_id: Set(device.device_id),
-                prekey_id: Set(prekey.id as i32),
-                public_key: Set(prekey.public_key),
+                prekey_id: Set(prekey.id),
+                public_key: Set(prekey.key),
             };
             otpk.insert(&txn).await?;
         }
 
+        // Record a re-registration under a new identity key so contacts can be warned
+        let identity_changed = record_identity_change(&txn, &old_identity_keys, &device).await?;
+
         txn.commit().await?;
 
         // Generate JWT tokens
@@ -196,6 +201,7 @@ impl VerifyOtpUseCase {
             is_new_user,
             requires_profile_setup,
             requires_pin,
+            identity_changed,
         })
     }
 
@@ -502,6 +508,7 @@ impl CreateLinkingSessionUseCase {
             status: Set(1), // Pending
             new_device_uuid: Set(None),
             new_device_name: Set(None),
+            new_device_keys: Set(None),
             expires_at: Set(expires_at.into()),
             created_at: Set(Utc::now().into()),
             approved_at: Set(None),
@@ -537,6 +544,8 @@ impl CompleteLinkingUseCase {
         db: &DatabaseConnection,
         req: CompleteLinkingRequest,
     ) -> Result<CompleteLinkingResponse> {
+        validate_device_keys(&req.keys)?;
+
         // Find session by token
         let session = device_linking_sessions::Entity::find()
             .filter(device_linking_sessions::Column::QrCodeToken.eq(&req.qr_code_token))
@@ -553,6 +562,7 @@ impl CompleteLinkingUseCase {
         let mut active_session: device_linking_sessions::ActiveModel = session.clone().into();
         active_session.new_device_uuid = Set(Some(req.device_uuid));
         active_session.new_device_name = Set(req.device_name.clone());
+        active_session.new_device_keys = Set(Some(serde_json::to_value(&req.keys)?));
         active_session.update(db).await?;
 
         Ok(CompleteLinkingResponse {
@@ -603,12 +613,15 @@ impl ApproveLinkingUseCase {
                 .one(&txn)
                 .await?
                 .ok_or_else(|| anyhow!("Primary device not found"))?;
+            let old_identity_keys = active_identity_keys(&txn, primary_device.user_id).await?;
 
-            // Generate Signal Keys for new device
-            let (identity_key_pair, _) = generate_identity_keypair()?;
-            let registration_id = generate_registration_id();
-            let signed_prekey = generate_signed_prekey(&identity_key_pair, 1)?;
-            let one_time_prekeys_list = generate_prekeys(1, 100)?;
+            // Keys uploaded by the new device when it completed linking
+            let keys: DeviceKeysDto = serde_json::from_value(
+                session
+                    .new_device_keys
+                    .clone()
+                    .ok_or_else(|| anyhow!("No device keys uploaded for this session"))?,
+            )?;
 
             // Create linked device
             let new_device = devices::ActiveModel {
@@ -616,11 +629,11 @@ impl ApproveLinkingUseCase {
                 device_uuid: Set(new_device_uuid),
                 device_name: Set(session.new_device_name.clone()),
                 platform: Set(3), // Default to Web for linked devices
-                identity_key_public: Set(identity_key_pair.public_key),
-                registration_id: Set(registration_id as i32),
-                signed_prekey_id: Set(signed_prekey.id as i32),
-                signed_prekey_public: Set(signed_prekey.public_key),
-                signed_prekey_signature: Set(signed_prekey.signature),
+                identity_key_public: Set(keys.identity_key),
+                registration_id: Set(keys.registration_id),
+                signed_prekey_id: Set(keys.signed_prekey.id),
+                signed_prekey_public: Set(keys.signed_prekey.key),
+                signed_prekey_signature: Set(keys.signed_prekey.signature),
                 last_seen_at: Set(Utc::now().into()),
                 created_at: Set(Utc::now().into()),
                 device_type: Set(DEVICE_TYPE_LINKED),
@@ -633,15 +646,19 @@ impl ApproveLinkingUseCase {
             let new_device = new_device.insert(&txn).await?;
 
             // Insert One Time Prekeys
-            for prekey in one_time_prekeys_list {
+            for prekey in keys.one_time_prekeys {
                 let otpk = one_time_prekeys::ActiveModel {
                     device_id: Set(new_device.device_id),
-                    prekey_id: Set(prekey.id as i32),
-                    public_key: Set(prekey.public_key),
+                    prekey_id: Set(prekey.id),
+                    public_key: Set(prekey.key),
                 };
                 otpk.insert(&txn).await?;
             }
 
+            // A linked device under its own identity key changes the user's safety number
+            let identity_changed =
+                record_identity_change(&txn, &old_identity_keys, &new_device).await?;
+
             // Update session status
             active_session.status = Set(2); // Approved
             active_session.approved_at = Set(Some(Utc::now().into()));
@@ -653,6 +670,7 @@ impl ApproveLinkingUseCase {
                 session_id: req.session_id,
                 new_device_id: Some(new_device.device_id),
                 status: "approved".to_string(),
+                identity_changed,
             })
         } else {
             // Reject
@@ -664,6 +682,7 @@ impl ApproveLinkingUseCase {
                 session_id: req.session_id,
                 new_device_id: None,
                 status: "rejected".to_string(),
+                identity_changed: false,
             })
         }
     }
@@ -718,9 +737,11 @@ impl UnlinkDeviceUseCase {
         current_device_id: i64,
         target_device_id: i64,
     ) -> Result<UnlinkDeviceResponse> {
+        let txn = db.begin().await?;
+
         // Find target device
         let device = devices::Entity::find_by_id(target_device_id)
-            .one(db)
+            .one(&txn)
             .await?
             .ok_or_else(|| anyhow!("Device not found"))?;
 
@@ -734,14 +755,67 @@ impl UnlinkDeviceUseCase {
             return Err(anyhow!("Cannot unlink current device"));
         }
 
+        let old_identity_keys = active_identity_keys(&txn, user_id).await?;
+
         // Deactivate device
         let mut active_device: devices::ActiveModel = device.into();
         active_device.is_active = Set(false);
-        active_device.update(db).await?;
+        let device = active_device.update(&txn).await?;
+
+        // Dropping a device's identity key changes the safety number just like adding one
+        let identity_changed = record_identity_change(&txn, &old_identity_keys, &device).await?;
+
+        txn.commit().await?;
 
         Ok(UnlinkDeviceResponse {
             unlinked: true,
             message: "Device unlinked successfully".to_string(),
+            identity_changed,
         })
     }
 }
+
+// ============ Identity Changes ============
+
+/// Identity keys of the user's active devices, which contacts' safety numbers cover.
+async fn active_identity_keys(
+    txn: &sea_orm::DatabaseTransaction,
+    user_id: Uuid,
+) -> Result<BTreeSet<Vec<u8>>> {
+    let keys: Vec<Vec<u8>> = devices::Entity::find()
+        .select_only()
+        .column(devices::Column::IdentityKeyPublic)
+        .filter(devices::Column::UserId.eq(user_id))
+        .filter(devices::Column::IsActive.eq(true))
+        .into_tuple()
+        .all(txn)
+        .await?;
+    Ok(keys.into_iter().collect())
+}
+
+/// Record `device`, which was just added or deactivated, as an identity change if the
+/// user's set of active identity keys is no longer `old_identity_keys`. A user's first
+/// device is not a change: nobody can have verified them yet.
+async fn record_identity_change(
+    txn: &sea_orm::DatabaseTransaction,
+    old_identity_keys: &BTreeSet<Vec<u8>>,
+    device: &devices::Model,
+) -> Result<bool> {
+    if old_identity_keys.is_empty()
+        || active_identity_keys(txn, device.user_id).await? == *old_identity_keys
+    {
+        return Ok(false);
+    }
+
+    identity_key_changes::ActiveModel {
+        user_id: Set(device.user_id),
+        device_id: Set(device.device_id),
+        identity_key: Set(device.identity_key_public.clone()),
+        changed_at: Set(Utc::now().into()),
+        ..Default::default()
+    }
+    .insert(txn)
+    .await?;
+
+    Ok(true)
+}
@@ -0,0 +1,429 @@
+use super::dtos::{
+    ConversationDto, ConversationMemberDto, ConversationSummaryDto, ConversationType,
+    CreateConversationRequest, LastMessageDto, MemberRole, MembershipAction, MembershipChange,
+};
+use super::groups::{
+    active_members, bump_sender_key_epoch, membership_change, MAX_CONVERSATION_NAME_LENGTH,
+};
+use chat_core::entities::{conv_members, conversations, messages, users};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+pub const MAX_GROUP_MEMBERS: usize = 1000;
+
+/// Returned whenever a sender or recipient is not (or no longer) in the conversation.
+pub const NOT_A_MEMBER: &str = "Not a member of this conversation";
+
+pub struct CreateConversationUseCase;
+
+impl CreateConversationUseCase {
+    /// Create a conversation with the caller as a member. Creating a direct conversation
+    /// with someone the caller already has one with returns the existing conversation.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        creator_id: Uuid,
+        req: CreateConversationRequest,
+    ) -> Result<ConversationDto, String> {
+        let others: BTreeSet<Uuid> = req
+            .member_ids
+            .into_iter()
+            .filter(|id| *id != creator_id)
+            .collect();
+
+        match req.conv_type {
+            ConversationType::Direct => {
+                if others.len() != 1 {
+                    return Err(
+                        "Invalid members: a direct conversation has exactly one other member"
+                            .to_string(),
+                    );
+                }
+            }
+            ConversationType::Group => {
+                if others.is_empty() || others.len() >= MAX_GROUP_MEMBERS {
+                    return Err(format!(
+                        "Invalid members: a group has between 2 and {} members",
+                        MAX_GROUP_MEMBERS
+                    ));
+                }
+                let name = req.name.as_deref().map(str::trim).unwrap_or_default();
+                if name.is_empty() || name.chars().count() > MAX_CONVERSATION_NAME_LENGTH {
+                    return Err(format!(
+                        "Invalid name: a group name has between 1 and {} characters",
+                        MAX_CONVERSATION_NAME_LENGTH
+                    ));
+                }
+            }
+        }
+
+        let existing_users = users::Entity::find()
+            .filter(users::Column::UserId.is_in(others.iter().copied()))
+            .filter(users::Column::IsDeleted.eq(false))
+            .count(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        if existing_users != others.len() as u64 {
+            return Err("User not found".to_string());
+        }
+
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        if req.conv_type == ConversationType::Direct {
+            let peer_id = *others.first().expect("checked above");
+            if let Some(conv_id) = find_direct_conversation(&txn, creator_id, peer_id).await? {
+                // Rejoin if the caller had left; the peer's membership is left untouched
+                let membership = conv_members::Entity::find_by_id((conv_id, creator_id))
+                    .one(&txn)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .ok_or("Conversation not found")?;
+                if membership.left_at.is_some() {
+                    let mut membership: conv_members::ActiveModel = membership.into();
+                    membership.left_at = Set(None);
+                    membership.joined_at = Set(Utc::now().into());
+                    membership.update(&txn).await.map_err(|e| e.to_string())?;
+                }
+                txn.commit().await.map_err(|e| e.to_string())?;
+                return load_conversation(db, conv_id).await;
+            }
+        }
+
+        let conv_id = Uuid::new_v4();
+        let now = Utc::now();
+        let name = match req.conv_type {
+            ConversationType::Direct => None,
+            ConversationType::Group => req.name.map(|n| n.trim().to_string()),
+        };
+        conversations::ActiveModel {
+            conv_id: Set(conv_id),
+            conv_type: Set(req.conv_type.as_i16()),
+            name: Set(name),
+            avatar: Set(req.avatar),
+            created_at: Set(now.into()),
+            creator_id: Set(Some(creator_id)),
+            metadata: Set(serde_json::json!({})),
+            sender_key_epoch: Set(0),
+        }
+        .insert(&txn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        // The creator owns a group; direct conversations have no admins
+        let creator_role = match req.conv_type {
+            ConversationType::Direct => MemberRole::Member,
+            ConversationType::Group => MemberRole::Owner,
+        };
+        let members = std::iter::once((creator_id, creator_role))
+            .chain(others.into_iter().map(|id| (id, MemberRole::Member)));
+        for (user_id, role) in members {
+            conv_members::ActiveModel {
+                conv_id: Set(conv_id),
+                user_id: Set(user_id),
+                role: Set(role.as_i16()),
+                joined_at: Set(now.into()),
+                left_at: Set(None),
+            }
+            .insert(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        load_conversation(db, conv_id).await
+    }
+}
+
+pub struct ListConversationsUseCase;
+
+impl ListConversationsUseCase {
+    /// Conversations the caller is a member of, most recently active first.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+    ) -> Result<Vec<ConversationSummaryDto>, String> {
+        let memberships = conv_members::Entity::find()
+            .filter(conv_members::Column::UserId.eq(user_id))
+            .filter(conv_members::Column::LeftAt.is_null())
+            .find_also_related(conversations::Entity)
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let conv_ids: Vec<Uuid> = memberships.iter().map(|(m, _)| m.conv_id).collect();
+        let mut last_messages = last_messages(db, &conv_ids).await?;
+
+        let mut summaries: Vec<ConversationSummaryDto> = memberships
+            .into_iter()
+            .filter_map(|(membership, conversation)| {
+                let conversation = conversation?;
+                Some(ConversationSummaryDto {
+                    conversation_id: conversation.conv_id,
+                    conv_type: ConversationType::from_i16(conversation.conv_type)?,
+                    name: conversation.name,
+                    avatar: conversation.avatar,
+                    joined_at: membership.joined_at.timestamp(),
+                    last_message: last_messages.remove(&conversation.conv_id),
+                })
+            })
+            .collect();
+
+        summaries.sort_by_key(|s| {
+            std::cmp::Reverse(s.last_message.as_ref().map_or(s.joined_at, |m| m.sent_at))
+        });
+
+        Ok(summaries)
+    }
+}
+
+pub struct GetConversationUseCase;
+
+impl GetConversationUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        conversation_id: Uuid,
+    ) -> Result<ConversationDto, String> {
+        if !is_active_member(db, conversation_id, user_id).await? {
+            return Err("Conversation not found".to_string());
+        }
+
+        load_conversation(db, conversation_id).await
+    }
+}
+
+pub struct LeaveConversationUseCase;
+
+impl LeaveConversationUseCase {
+    /// Leave a conversation. If the owner of a group leaves, ownership passes to the
+    /// longest-standing admin, or failing that the longest-standing member.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        conversation_id: Uuid,
+    ) -> Result<Vec<MembershipChange>, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        let membership = conv_members::Entity::find_by_id((conversation_id, user_id))
+            .filter(conv_members::Column::LeftAt.is_null())
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Conversation not found")?;
+        let was_owner = MemberRole::from_i16(membership.role) == MemberRole::Owner;
+        let is_group = conversations::Entity::find_by_id(conversation_id)
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .is_some_and(|c| {
+                ConversationType::from_i16(c.conv_type) == Some(ConversationType::Group)
+            });
+
+        let mut membership: conv_members::ActiveModel = membership.into();
+        membership.left_at = Set(Some(Utc::now().into()));
+        membership.update(&txn).await.map_err(|e| e.to_string())?;
+
+        if is_group {
+            bump_sender_key_epoch(&txn, conversation_id).await?;
+        }
+
+        let mut left = membership_change(
+            &txn,
+            conversation_id,
+            user_id,
+            MembershipAction::Left,
+            vec![user_id],
+            None,
+        )
+        .await?;
+        left.recipients.push(user_id);
+        let mut changes = vec![left];
+
+        if was_owner {
+            let remaining = active_members(&txn, conversation_id).await?;
+            let successor = remaining
+                .iter()
+                .find(|m| MemberRole::from_i16(m.role) == MemberRole::Admin)
+                .or_else(|| remaining.first())
+                .cloned();
+            if let Some(successor) = successor {
+                let successor_id = successor.user_id;
+                let mut successor: conv_members::ActiveModel = successor.into();
+                successor.role = Set(MemberRole::Owner.as_i16());
+                successor.update(&txn).await.map_err(|e| e.to_string())?;
+
+                changes.push(
+                    membership_change(
+                        &txn,
+                        conversation_id,
+                        user_id,
+                        MembershipAction::RoleChanged,
+                        vec![successor_id],
+                        Some(MemberRole::Owner),
+                    )
+                    .await?,
+                );
+            }
+        }
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(changes)
+    }
+}
+
+/// Whether `user_id` currently belongs to `conversation_id`.
+pub async fn is_active_member<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, String> {
+    let count = conv_members::Entity::find()
+        .filter(conv_members::Column::ConvId.eq(conversation_id))
+        .filter(conv_members::Column::UserId.eq(user_id))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .count(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(count > 0)
+}
+
+/// Fail with [`NOT_A_MEMBER`] unless every user in `user_ids` currently belongs to
+/// `conversation_id`.
+pub async fn ensure_members<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    user_ids: &[Uuid],
+) -> Result<(), String> {
+    let user_ids: BTreeSet<Uuid> = user_ids.iter().copied().collect();
+    let count = conv_members::Entity::find()
+        .filter(conv_members::Column::ConvId.eq(conversation_id))
+        .filter(conv_members::Column::UserId.is_in(user_ids.iter().copied()))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .count(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if count != user_ids.len() as u64 {
+        return Err(NOT_A_MEMBER.to_string());
+    }
+    Ok(())
+}
+
+async fn find_direct_conversation<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+    peer_id: Uuid,
+) -> Result<Option<Uuid>, String> {
+    let candidates: Vec<Uuid> = conv_members::Entity::find()
+        .select_only()
+        .column(conv_members::Column::ConvId)
+        .inner_join(conversations::Entity)
+        .filter(conversations::Column::ConvType.eq(ConversationType::Direct.as_i16()))
+        .filter(conv_members::Column::UserId.eq(user_id))
+        .into_tuple()
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    conv_members::Entity::find()
+        .select_only()
+        .column(conv_members::Column::ConvId)
+        .filter(conv_members::Column::ConvId.is_in(candidates))
+        .filter(conv_members::Column::UserId.eq(peer_id))
+        .into_tuple()
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub(super) async fn load_conversation(
+    db: &DatabaseConnection,
+    conversation_id: Uuid,
+) -> Result<ConversationDto, String> {
+    let conversation = conversations::Entity::find_by_id(conversation_id)
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Conversation not found")?;
+
+    let members = conv_members::Entity::find()
+        .filter(conv_members::Column::ConvId.eq(conversation_id))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .order_by_asc(conv_members::Column::JoinedAt)
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(ConversationDto {
+        conversation_id: conversation.conv_id,
+        conv_type: ConversationType::from_i16(conversation.conv_type)
+            .ok_or("Unknown conversation type")?,
+        name: conversation.name,
+        avatar: conversation.avatar,
+        creator_id: conversation.creator_id,
+        created_at: conversation.created_at.timestamp(),
+        sender_key_epoch: conversation.sender_key_epoch,
+        members: members
+            .into_iter()
+            .map(|m| ConversationMemberDto {
+                user_id: m.user_id,
+                role: MemberRole::from_i16(m.role),
+                joined_at: m.joined_at.timestamp(),
+            })
+            .collect(),
+    })
+}
+
+/// Newest non-deleted message of each conversation.
+async fn last_messages(
+    db: &DatabaseConnection,
+    conv_ids: &[Uuid],
+) -> Result<HashMap<Uuid, LastMessageDto>, String> {
+    if conv_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let latest_ids: Vec<i64> = messages::Entity::find()
+        .select_only()
+        .column_as(messages::Column::MessageId.max(), "message_id")
+        .filter(messages::Column::ConvId.is_in(conv_ids.iter().copied()))
+        .filter(messages::Column::DeletedAt.is_null())
+        .group_by(messages::Column::ConvId)
+        .into_tuple()
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let latest = messages::Entity::find()
+        .filter(messages::Column::MessageId.is_in(latest_ids))
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(latest
+        .into_iter()
+        .map(|m| {
+            (
+                m.conv_id,
+                LastMessageDto {
+                    message_id: m.message_id,
+                    sender_id: m.sender_user_id,
+                    sender_device_id: m.sender_device_id,
+                    message_type: m.message_type,
+                    sent_at: m.sent_at.timestamp(),
+                },
+            )
+        })
+        .collect())
+}
@@ -12,19 +12,310 @@ pub struct SendMessageRequest {
     pub content: Vec<u8>,
 }
 
+/// The server's record of a stored message, returned to the sender as its acknowledgement.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SentMessageDto {
+    pub message_id: i64,
+    pub sent_at: i64,
+}
+
+/// One device's copy of a message, encrypted over the sender's session with that device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceMessageDto {
+    pub recipient_id: Uuid,
+    pub recipient_device_id: i64,
+    #[serde(with = "serde_bytes")]
+    pub content: Vec<u8>,
+}
+
+/// A single send covering every active device of the recipient and the sender's other
+/// active devices.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendMultiDeviceMessageRequest {
+    pub sender_id: Uuid,
+    pub sender_device_id: i64,
+    pub recipient_id: Uuid,
+    pub conversation_id: Uuid,
+    pub client_message_id: Uuid,
+    pub messages: Vec<DeviceMessageDto>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DeviceAddressDto {
+    pub user_id: Uuid,
+    pub device_id: i64,
+}
+
+/// The client's device list disagreed with the server's; nothing was stored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StaleDevicesDto {
+    /// Active devices the client did not encrypt for
+    pub missing: Vec<DeviceAddressDto>,
+    /// Devices the client encrypted for that are unknown, inactive or not addressable
+    pub extra: Vec<DeviceAddressDto>,
+}
+
+#[derive(Debug)]
+pub enum MultiDeviceSendOutcome {
+    /// Stored; each copy should be pushed to its device. Empty when the send was a retry.
+    Sent {
+        sent: SentMessageDto,
+        deliveries: Vec<DeviceMessageDto>,
+    },
+    StaleDevices(StaleDevicesDto),
+}
+
+/// A Sender Key distribution message for one device, encrypted over the sender's
+/// pairwise session with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SenderKeyDistributionDto {
+    pub recipient_id: Uuid,
+    pub recipient_device_id: i64,
+    #[serde(with = "serde_bytes")]
+    pub content: Vec<u8>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct SendGroupMessageRequest {
+    pub sender_id: Uuid,
+    pub sender_device_id: i64,
+    pub conversation_id: Uuid,
+    pub client_message_id: Uuid,
+    pub sender_key_epoch: i64,
+    /// Sender Key ciphertext, stored once and delivered to every member device.
+    pub content: Vec<u8>,
+    pub distributions: Vec<SenderKeyDistributionDto>,
+}
+
+#[derive(Debug)]
+pub struct GroupDelivery {
+    pub recipient_id: Uuid,
+    pub recipient_device_id: i64,
+    pub sender_key_distribution: Option<Vec<u8>>,
+}
+
+/// A stored group message and the devices it must be pushed to.
+#[derive(Debug)]
+pub struct GroupMessageFanout {
+    pub message_id: i64,
+    pub sent_at: i64,
+    pub deliveries: Vec<GroupDelivery>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncMessageDto {
     pub message_id: i64,
     pub conversation_id: Uuid,
     pub client_message_id: Option<Uuid>,
     pub sender_id: Uuid,
     pub sender_device_id: i64,
+    #[serde(with = "serde_bytes")]
     pub content: Vec<u8>,
+    /// For group messages, the sender's Sender Key distribution for this device, if the
+    /// message carried one (encrypted over the pairwise session).
+    #[serde(with = "serde_bytes")]
+    pub sender_key_distribution: Option<Vec<u8>>,
     pub sent_at: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncMessagesQuery {
+    /// Cursor from the previous page; without one, sync resumes from the device's
+    /// server-side checkpoint.
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SyncMessagesResponse {
+    /// Oldest first.
+    pub messages: Vec<SyncMessageDto>,
+    /// Pass back to fetch the next page. Opaque to clients.
+    pub cursor: Option<String>,
+    pub has_more: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum DeliveryStatusType {
     Delivered,
     Read,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConversationType {
+    Direct,
+    Group,
+}
+
+impl ConversationType {
+    /// Value stored in `conversations.conv_type`.
+    pub fn as_i16(self) -> i16 {
+        match self {
+            ConversationType::Direct => 1,
+            ConversationType::Group => 2,
+        }
+    }
+
+    pub fn from_i16(value: i16) -> Option<Self> {
+        match value {
+            1 => Some(ConversationType::Direct),
+            2 => Some(ConversationType::Group),
+            _ => None,
+        }
+    }
+}
+
+/// Ordered so that a higher role can do everything a lower one can.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum MemberRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl MemberRole {
+    /// Value stored in `conv_members.role`.
+    pub fn as_i16(self) -> i16 {
+        match self {
+            MemberRole::Member => 0,
+            MemberRole::Admin => 1,
+            MemberRole::Owner => 2,
+        }
+    }
+
+    /// Unknown values are treated as the least privileged role.
+    pub fn from_i16(value: i16) -> Self {
+        match value {
+            2 => MemberRole::Owner,
+            1 => MemberRole::Admin,
+            _ => MemberRole::Member,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateConversationRequest {
+    pub conv_type: ConversationType,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    /// Other members; the caller is always added.
+    pub member_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationMemberDto {
+    pub user_id: Uuid,
+    pub role: MemberRole,
+    pub joined_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationDto {
+    pub conversation_id: Uuid,
+    pub conv_type: ConversationType,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub creator_id: Option<Uuid>,
+    pub created_at: i64,
+    /// Group messages must be sent with this epoch; it changes when a member leaves.
+    pub sender_key_epoch: i64,
+    pub members: Vec<ConversationMemberDto>,
+}
+
+/// Metadata of the newest message; the content is end-to-end encrypted per device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastMessageDto {
+    pub message_id: i64,
+    pub sender_id: Uuid,
+    pub sender_device_id: i64,
+    pub message_type: i16,
+    pub sent_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationSummaryDto {
+    pub conversation_id: Uuid,
+    pub conv_type: ConversationType,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub joined_at: i64,
+    pub last_message: Option<LastMessageDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationsResponse {
+    pub conversations: Vec<ConversationSummaryDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddMembersRequest {
+    pub user_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetMemberRoleRequest {
+    /// `admin` or `member`; ownership cannot be assigned directly.
+    pub role: MemberRole,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateGroupRequest {
+    pub name: Option<String>,
+    /// An empty string removes the avatar.
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateInviteRequest {
+    pub expires_in_seconds: Option<i64>,
+    /// Maximum number of users who may join through the link.
+    pub member_cap: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteDto {
+    pub token: String,
+    pub conversation_id: Uuid,
+    pub created_by: Uuid,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub member_cap: Option<i32>,
+    pub use_count: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitesResponse {
+    pub invites: Vec<InviteDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipAction {
+    Added,
+    Removed,
+    Left,
+    Joined,
+    RoleChanged,
+}
+
+/// A change to a conversation's members, and everyone who should be told about it:
+/// the remaining members plus anyone who was removed or left.
+#[derive(Debug, Clone)]
+pub struct MembershipChange {
+    pub conversation_id: Uuid,
+    pub actor_id: Uuid,
+    pub action: MembershipAction,
+    pub user_ids: Vec<Uuid>,
+    /// The new role, for `RoleChanged`.
+    pub role: Option<MemberRole>,
+    pub recipients: Vec<Uuid>,
+}
+
+/// Result of following an invite link. `change` is `None` if the user was already a member.
+#[derive(Debug)]
+pub struct JoinedConversation {
+    pub conversation: ConversationDto,
+    pub change: Option<MembershipChange>,
+}
@@ -0,0 +1,346 @@
+use super::conversations::{load_conversation, MAX_GROUP_MEMBERS, NOT_A_MEMBER};
+use super::dtos::{
+    ConversationDto, ConversationType, MemberRole, MembershipAction, MembershipChange,
+    UpdateGroupRequest,
+};
+use chat_core::entities::{conv_members, conversations, users};
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    PaginatorTrait, QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+pub const PERMISSION_DENIED: &str = "Permission denied";
+pub(super) const MAX_CONVERSATION_NAME_LENGTH: usize = 128;
+
+pub struct AddMembersUseCase;
+
+impl AddMembersUseCase {
+    /// Add users to a group, or bring back users who had left. Requires admin.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        user_ids: Vec<Uuid>,
+    ) -> Result<MembershipChange, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+        require_group(&txn, conversation_id).await?;
+        require_role(&txn, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let members = active_members(&txn, conversation_id).await?;
+        let new_ids: BTreeSet<Uuid> = user_ids
+            .into_iter()
+            .filter(|id| !members.iter().any(|m| m.user_id == *id))
+            .collect();
+        if new_ids.is_empty() {
+            return Err("Invalid members: everyone is already in the group".to_string());
+        }
+        if members.len() + new_ids.len() > MAX_GROUP_MEMBERS {
+            return Err(format!(
+                "Invalid members: a group has at most {} members",
+                MAX_GROUP_MEMBERS
+            ));
+        }
+
+        let existing_users = users::Entity::find()
+            .filter(users::Column::UserId.is_in(new_ids.iter().copied()))
+            .filter(users::Column::IsDeleted.eq(false))
+            .count(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+        if existing_users != new_ids.len() as u64 {
+            return Err("User not found".to_string());
+        }
+
+        for user_id in &new_ids {
+            join(&txn, conversation_id, *user_id).await?;
+        }
+
+        let change = membership_change(
+            &txn,
+            conversation_id,
+            actor_id,
+            MembershipAction::Added,
+            new_ids.into_iter().collect(),
+            None,
+        )
+        .await?;
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(change)
+    }
+}
+
+pub struct RemoveMemberUseCase;
+
+impl RemoveMemberUseCase {
+    /// Remove a member from a group. Admins can remove members; only the owner can
+    /// remove admins, and the owner cannot be removed.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<MembershipChange, String> {
+        if actor_id == user_id {
+            return Err("Invalid member: leave the conversation instead".to_string());
+        }
+
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+        require_group(&txn, conversation_id).await?;
+        let actor_role = require_role(&txn, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let target = active_membership(&txn, conversation_id, user_id)
+            .await?
+            .ok_or("Member not found")?;
+        if MemberRole::from_i16(target.role) >= actor_role {
+            return Err(PERMISSION_DENIED.to_string());
+        }
+
+        let mut target: conv_members::ActiveModel = target.into();
+        target.left_at = Set(Some(Utc::now().into()));
+        target.update(&txn).await.map_err(|e| e.to_string())?;
+        bump_sender_key_epoch(&txn, conversation_id).await?;
+
+        let mut change = membership_change(
+            &txn,
+            conversation_id,
+            actor_id,
+            MembershipAction::Removed,
+            vec![user_id],
+            None,
+        )
+        .await?;
+        change.recipients.push(user_id);
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(change)
+    }
+}
+
+pub struct SetMemberRoleUseCase;
+
+impl SetMemberRoleUseCase {
+    /// Promote a member to admin (any admin may) or demote an admin (owner only).
+    /// Returns `None` when the member already has `role`, as nothing changed.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        user_id: Uuid,
+        role: MemberRole,
+    ) -> Result<Option<MembershipChange>, String> {
+        if role == MemberRole::Owner {
+            return Err("Invalid role: ownership cannot be assigned".to_string());
+        }
+
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+        require_group(&txn, conversation_id).await?;
+        let actor_role = require_role(&txn, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let target = active_membership(&txn, conversation_id, user_id)
+            .await?
+            .ok_or("Member not found")?;
+        let current = MemberRole::from_i16(target.role);
+        if current == MemberRole::Owner
+            || (current == MemberRole::Admin && actor_role != MemberRole::Owner)
+        {
+            return Err(PERMISSION_DENIED.to_string());
+        }
+
+        if current == role {
+            return Ok(None);
+        }
+
+        let mut target: conv_members::ActiveModel = target.into();
+        target.role = Set(role.as_i16());
+        target.update(&txn).await.map_err(|e| e.to_string())?;
+
+        let change = membership_change(
+            &txn,
+            conversation_id,
+            actor_id,
+            MembershipAction::RoleChanged,
+            vec![user_id],
+            Some(role),
+        )
+        .await?;
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(Some(change))
+    }
+}
+
+pub struct UpdateGroupUseCase;
+
+impl UpdateGroupUseCase {
+    /// Rename a group or change its avatar. Requires admin.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        req: UpdateGroupRequest,
+    ) -> Result<ConversationDto, String> {
+        let conversation = require_group(db, conversation_id).await?;
+        require_role(db, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let mut conversation: conversations::ActiveModel = conversation.into();
+        if let Some(name) = req.name {
+            let name = name.trim();
+            if name.is_empty() || name.chars().count() > MAX_CONVERSATION_NAME_LENGTH {
+                return Err(format!(
+                    "Invalid name: a group name has between 1 and {} characters",
+                    MAX_CONVERSATION_NAME_LENGTH
+                ));
+            }
+            conversation.name = Set(Some(name.to_string()));
+        }
+        if let Some(avatar) = req.avatar {
+            conversation.avatar = Set((!avatar.is_empty()).then_some(avatar));
+        }
+        conversation.update(db).await.map_err(|e| e.to_string())?;
+
+        load_conversation(db, conversation_id).await
+    }
+}
+
+/// The conversation, provided it is a group.
+pub(super) async fn require_group<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+) -> Result<conversations::Model, String> {
+    let conversation = conversations::Entity::find_by_id(conversation_id)
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("Conversation not found")?;
+
+    if ConversationType::from_i16(conversation.conv_type) != Some(ConversationType::Group) {
+        return Err("Invalid conversation: not a group".to_string());
+    }
+    Ok(conversation)
+}
+
+/// The caller's role, failing unless they are an active member with at least `minimum`.
+pub(super) async fn require_role<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    user_id: Uuid,
+    minimum: MemberRole,
+) -> Result<MemberRole, String> {
+    let membership = active_membership(db, conversation_id, user_id)
+        .await?
+        .ok_or(NOT_A_MEMBER)?;
+
+    let role = MemberRole::from_i16(membership.role);
+    if role < minimum {
+        return Err(PERMISSION_DENIED.to_string());
+    }
+    Ok(role)
+}
+
+pub(super) async fn active_membership<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<conv_members::Model>, String> {
+    conv_members::Entity::find_by_id((conversation_id, user_id))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Current members, longest-standing first.
+pub(super) async fn active_members<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+) -> Result<Vec<conv_members::Model>, String> {
+    conv_members::Entity::find()
+        .filter(conv_members::Column::ConvId.eq(conversation_id))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .order_by_asc(conv_members::Column::JoinedAt)
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Add `user_id` as a plain member, reviving their old membership row if they had left.
+pub(super) async fn join<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), String> {
+    let previous = conv_members::Entity::find_by_id((conversation_id, user_id))
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match previous {
+        Some(previous) => {
+            let mut membership: conv_members::ActiveModel = previous.into();
+            membership.role = Set(MemberRole::Member.as_i16());
+            membership.joined_at = Set(Utc::now().into());
+            membership.left_at = Set(None);
+            membership.update(db).await.map_err(|e| e.to_string())?;
+        }
+        None => {
+            conv_members::ActiveModel {
+                conv_id: Set(conversation_id),
+                user_id: Set(user_id),
+                role: Set(MemberRole::Member.as_i16()),
+                joined_at: Set(Utc::now().into()),
+                left_at: Set(None),
+            }
+            .insert(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Force every sender to rotate its Sender Key, so departed members cannot read on.
+pub(super) async fn bump_sender_key_epoch<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+) -> Result<(), String> {
+    conversations::Entity::update_many()
+        .col_expr(
+            conversations::Column::SenderKeyEpoch,
+            Expr::col(conversations::Column::SenderKeyEpoch).add(1),
+        )
+        .filter(conversations::Column::ConvId.eq(conversation_id))
+        .exec(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Build a change addressed to everyone currently in the conversation.
+pub(super) async fn membership_change<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    actor_id: Uuid,
+    action: MembershipAction,
+    user_ids: Vec<Uuid>,
+    role: Option<MemberRole>,
+) -> Result<MembershipChange, String> {
+    let recipients = active_members(db, conversation_id)
+        .await?
+        .into_iter()
+        .map(|m| m.user_id)
+        .collect();
+
+    Ok(MembershipChange {
+        conversation_id,
+        actor_id,
+        action,
+        user_ids,
+        role,
+        recipients,
+    })
+}
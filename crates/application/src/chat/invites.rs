@@ -0,0 +1,204 @@
+use super::conversations::{load_conversation, MAX_GROUP_MEMBERS};
+use super::dtos::{
+    CreateInviteRequest, InviteDto, JoinedConversation, MemberRole, MembershipAction,
+};
+use super::groups::{active_members, join, membership_change, require_group, require_role};
+use base64::Engine;
+use chat_core::entities::conversation_invites;
+use chrono::{Duration, Utc};
+use rand::Rng;
+use sea_orm::sea_query::{Condition, Expr};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
+};
+use uuid::Uuid;
+
+/// Returned for revoked, expired and exhausted links alike.
+pub const INVITE_INVALID: &str = "Invite is no longer valid";
+
+pub struct CreateInviteUseCase;
+
+impl CreateInviteUseCase {
+    /// Create a link to join a group. Requires admin.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        req: CreateInviteRequest,
+    ) -> Result<InviteDto, String> {
+        require_group(db, conversation_id).await?;
+        require_role(db, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        if req.expires_in_seconds.is_some_and(|s| s <= 0) {
+            return Err("Invalid expiry: must be in the future".to_string());
+        }
+        if req.member_cap.is_some_and(|cap| cap <= 0) {
+            return Err("Invalid member cap: must be positive".to_string());
+        }
+
+        let now = Utc::now();
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(rand::thread_rng().gen::<[u8; 16]>());
+
+        let invite = conversation_invites::ActiveModel {
+            token: Set(token),
+            conv_id: Set(conversation_id),
+            created_by: Set(actor_id),
+            created_at: Set(now.into()),
+            expires_at: Set(req
+                .expires_in_seconds
+                .map(|s| (now + Duration::seconds(s)).into())),
+            member_cap: Set(req.member_cap),
+            use_count: Set(0),
+            revoked_at: Set(None),
+        }
+        .insert(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(invite.into())
+    }
+}
+
+pub struct ListInvitesUseCase;
+
+impl ListInvitesUseCase {
+    /// Unrevoked links of a group, newest first. Requires admin.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+    ) -> Result<Vec<InviteDto>, String> {
+        require_group(db, conversation_id).await?;
+        require_role(db, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let invites = conversation_invites::Entity::find()
+            .filter(conversation_invites::Column::ConvId.eq(conversation_id))
+            .filter(conversation_invites::Column::RevokedAt.is_null())
+            .order_by_desc(conversation_invites::Column::CreatedAt)
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(invites.into_iter().map(Into::into).collect())
+    }
+}
+
+pub struct RevokeInviteUseCase;
+
+impl RevokeInviteUseCase {
+    /// Requires admin.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        actor_id: Uuid,
+        conversation_id: Uuid,
+        token: &str,
+    ) -> Result<(), String> {
+        require_role(db, conversation_id, actor_id, MemberRole::Admin).await?;
+
+        let invite = conversation_invites::Entity::find_by_id(token.to_string())
+            .filter(conversation_invites::Column::ConvId.eq(conversation_id))
+            .filter(conversation_invites::Column::RevokedAt.is_null())
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Invite not found")?;
+
+        let mut invite: conversation_invites::ActiveModel = invite.into();
+        invite.revoked_at = Set(Some(Utc::now().into()));
+        invite.update(db).await.map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+pub struct JoinViaInviteUseCase;
+
+impl JoinViaInviteUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        token: &str,
+    ) -> Result<JoinedConversation, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        let invite = conversation_invites::Entity::find_by_id(token.to_string())
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Invite not found")?;
+        let conversation_id = invite.conv_id;
+
+        let members = active_members(&txn, conversation_id).await?;
+        if members.iter().any(|m| m.user_id == user_id) {
+            txn.commit().await.map_err(|e| e.to_string())?;
+            return Ok(JoinedConversation {
+                conversation: load_conversation(db, conversation_id).await?,
+                change: None,
+            });
+        }
+        if members.len() >= MAX_GROUP_MEMBERS {
+            return Err(INVITE_INVALID.to_string());
+        }
+
+        // Claim a use atomically so concurrent joins cannot exceed the cap
+        let claimed = conversation_invites::Entity::update_many()
+            .col_expr(
+                conversation_invites::Column::UseCount,
+                Expr::col(conversation_invites::Column::UseCount).add(1),
+            )
+            .filter(conversation_invites::Column::Token.eq(token))
+            .filter(conversation_invites::Column::RevokedAt.is_null())
+            .filter(
+                Condition::any()
+                    .add(conversation_invites::Column::ExpiresAt.is_null())
+                    .add(conversation_invites::Column::ExpiresAt.gt(Utc::now())),
+            )
+            .filter(
+                Condition::any()
+                    .add(conversation_invites::Column::MemberCap.is_null())
+                    .add(
+                        Expr::col(conversation_invites::Column::UseCount)
+                            .lt(Expr::col(conversation_invites::Column::MemberCap)),
+                    ),
+            )
+            .exec(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+        if claimed.rows_affected == 0 {
+            return Err(INVITE_INVALID.to_string());
+        }
+
+        join(&txn, conversation_id, user_id).await?;
+        let change = membership_change(
+            &txn,
+            conversation_id,
+            user_id,
+            MembershipAction::Joined,
+            vec![user_id],
+            None,
+        )
+        .await?;
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(JoinedConversation {
+            conversation: load_conversation(db, conversation_id).await?,
+            change: Some(change),
+        })
+    }
+}
+
+impl From<conversation_invites::Model> for InviteDto {
+    fn from(invite: conversation_invites::Model) -> Self {
+        Self {
+            token: invite.token,
+            conversation_id: invite.conv_id,
+            created_by: invite.created_by,
+            created_at: invite.created_at.timestamp(),
+            expires_at: invite.expires_at.map(|t| t.timestamp()),
+            member_cap: invite.member_cap,
+            use_count: invite.use_count,
+        }
+    }
+}
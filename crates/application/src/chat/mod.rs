@@ -1,4 +1,7 @@
+pub mod conversations;
 pub mod dtos;
+pub mod groups;
+pub mod invites;
 pub mod use_cases;
 pub mod sync_messages;
 pub mod update_status;
@@ -1,42 +1,92 @@
-use super::dtos::SyncMessageDto;
-use core::entities::{message_deliveries, messages};
+use super::dtos::{SyncMessageDto, SyncMessagesResponse};
+use base64::Engine;
+use chat_core::entities::{devices, message_deliveries, messages, sync_checkpoints};
+use chrono::Utc;
+use sea_orm::sea_query::{Expr, OnConflict};
 use sea_orm::{
-    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
 };
 use uuid::Uuid;
 
+pub const MAX_SYNC_PAGE: u64 = 100;
+
 pub struct SyncMessagesUseCase;
 
 impl SyncMessagesUseCase {
+    /// One page of messages still queued for `device_id`, oldest first.
+    ///
+    /// Following a `cursor` confirms the device received every page before it, so the
+    /// device's checkpoint moves up to it; a request without one resumes after that
+    /// checkpoint. Sends lock their recipients' device rows before taking a message id,
+    /// so a device's messages commit in id order and none can appear behind its
+    /// checkpoint.
     pub async fn execute(
         db: &DatabaseConnection,
-        _user_id: Uuid,
+        user_id: Uuid,
         device_id: i64,
-        last_message_id: Option<i64>,
-    ) -> Result<Vec<SyncMessageDto>, String> {
-        // Query message_deliveries joined with messages
-        // Where device_id = device_id AND delivered_at IS NULL
-        // AND message_id > last_message_id (if provided)
-        
+        cursor: Option<&str>,
+        limit: u64,
+    ) -> Result<SyncMessagesResponse, String> {
+        devices::Entity::find_by_id(device_id)
+            .filter(devices::Column::UserId.eq(user_id))
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Device not found")?;
+
+        let after = match cursor {
+            Some(cursor) => {
+                let after = decode_cursor(cursor)?;
+                // Only a message queued for this device can have ended one of its pages
+                message_deliveries::Entity::find_by_id((after, device_id))
+                    .one(db)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .ok_or("Invalid cursor")?;
+                advance_checkpoint(db, device_id, after).await?;
+                Some(after)
+            }
+            None => sync_checkpoints::Entity::find_by_id(device_id)
+                .one(db)
+                .await
+                .map_err(|e| e.to_string())?
+                .map(|checkpoint| checkpoint.last_message_id),
+        };
+
+        let limit = limit.clamp(1, MAX_SYNC_PAGE);
         let mut query = message_deliveries::Entity::find()
             .filter(message_deliveries::Column::DeviceId.eq(device_id))
             .filter(message_deliveries::Column::DeliveredAt.is_null());
-
-        if let Some(last_id) = last_message_id {
-            query = query.filter(message_deliveries::Column::MessageId.gt(last_id));
+        if let Some(after) = after {
+            query = query.filter(message_deliveries::Column::MessageId.gt(after));
         }
 
-        let deliveries = query
+        let mut deliveries = query
+            .order_by_asc(message_deliveries::Column::MessageId)
+            .limit(limit + 1)
             .find_also_related(messages::Entity)
             .all(db)
             .await
             .map_err(|e| e.to_string())?;
 
+        let has_more = deliveries.len() as u64 > limit;
+        deliveries.truncate(limit as usize);
+        let next = deliveries
+            .last()
+            .map(|(delivery, _)| delivery.message_id)
+            .or(after);
+
         let mut result = Vec::new();
 
         for (delivery, message) in deliveries {
             if let Some(msg) = message {
-                if let Some(content) = delivery.content {
+                // Group messages share one Sender Key ciphertext; the delivery row only
+                // carries this device's distribution message, if any
+                let (content, sender_key_distribution) = match msg.ciphertext {
+                    Some(ciphertext) => (Some(ciphertext), delivery.content),
+                    None => (delivery.content, None),
+                };
+                if let Some(content) = content {
                     result.push(SyncMessageDto {
                         message_id: msg.message_id,
                         conversation_id: msg.conv_id,
@@ -44,12 +94,64 @@ impl SyncMessagesUseCase {
                         sender_id: msg.sender_user_id,
                         sender_device_id: msg.sender_device_id,
                         content,
+                        sender_key_distribution,
                         sent_at: msg.sent_at.timestamp(),
                     });
                 }
             }
         }
 
-        Ok(result)
+        Ok(SyncMessagesResponse {
+            messages: result,
+            cursor: next.map(encode_cursor),
+            has_more,
+        })
     }
 }
+
+fn encode_cursor(message_id: i64) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(message_id.to_be_bytes())
+}
+
+fn decode_cursor(cursor: &str) -> Result<i64, String> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+        .map(i64::from_be_bytes)
+        .ok_or_else(|| "Invalid cursor".to_string())
+}
+
+/// Checkpoints only move forward; the conditional upsert keeps concurrent or replayed
+/// cursors from rewinding one.
+async fn advance_checkpoint(
+    db: &DatabaseConnection,
+    device_id: i64,
+    last_message_id: i64,
+) -> Result<(), String> {
+    sync_checkpoints::Entity::insert(sync_checkpoints::ActiveModel {
+        device_id: Set(device_id),
+        last_message_id: Set(last_message_id),
+        updated_at: Set(Utc::now().into()),
+    })
+    .on_conflict(
+        OnConflict::column(sync_checkpoints::Column::DeviceId)
+            .update_columns([
+                sync_checkpoints::Column::LastMessageId,
+                sync_checkpoints::Column::UpdatedAt,
+            ])
+            .action_and_where(
+                Expr::col((
+                    sync_checkpoints::Entity,
+                    sync_checkpoints::Column::LastMessageId,
+                ))
+                .lt(Expr::cust("EXCLUDED.last_message_id")),
+            )
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
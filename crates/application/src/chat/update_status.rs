@@ -1,18 +1,39 @@
-use core::entities::message_deliveries;
+use super::conversations::ensure_members;
+use chat_core::entities::{message_deliveries, messages};
 use sea_orm::{
-    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, ActiveModelTrait,
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    Set, ActiveModelTrait, TransactionTrait,
 };
 use chrono::Utc;
+use uuid::Uuid;
 
 pub struct UpdateDeliveryStatusUseCase;
 
 impl UpdateDeliveryStatusUseCase {
+    /// Record a receipt from `user_id`'s device. The message must belong to
+    /// `conversation_id` and have been sent by `sender_id`, and both users must still be
+    /// members, since the receipt is forwarded to the sender. A delivered receipt removes
+    /// the message from the device's queue exactly as an ack does.
     pub async fn execute(
         db: &DatabaseConnection,
-        message_id: i64,
+        user_id: Uuid,
         device_id: i64,
+        conversation_id: Uuid,
+        sender_id: Uuid,
+        message_id: i64,
         status: super::dtos::DeliveryStatusType,
     ) -> Result<(), String> {
+        ensure_members(db, conversation_id, &[user_id, sender_id]).await?;
+
+        let message = messages::Entity::find_by_id(message_id)
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Message not found")?;
+        if message.conv_id != conversation_id || message.sender_user_id != sender_id {
+            return Err("Message not found".to_string());
+        }
+
         // Find the delivery record
         let delivery = message_deliveries::Entity::find()
             .filter(message_deliveries::Column::MessageId.eq(message_id))
@@ -22,20 +43,84 @@ impl UpdateDeliveryStatusUseCase {
             .map_err(|e| e.to_string())?;
 
         if let Some(delivery) = delivery {
-            let mut active_delivery: message_deliveries::ActiveModel = delivery.into();
-            
             match status {
                 super::dtos::DeliveryStatusType::Delivered => {
-                    active_delivery.delivered_at = Set(Some(Utc::now().into()));
+                    let txn = db.begin().await.map_err(|e| e.to_string())?;
+                    mark_delivered(&txn, delivery).await?;
+                    txn.commit().await.map_err(|e| e.to_string())?;
                 },
                 super::dtos::DeliveryStatusType::Read => {
+                    let mut active_delivery: message_deliveries::ActiveModel = delivery.into();
                     active_delivery.read_at = Set(Some(Utc::now().into()));
+                    active_delivery.update(db).await.map_err(|e| e.to_string())?;
                 },
             }
-
-            active_delivery.update(db).await.map_err(|e| e.to_string())?;
         }
 
         Ok(())
     }
 }
+
+pub struct AckMessageUseCase;
+
+impl AckMessageUseCase {
+    /// Remove a message from `device_id`'s offline queue: the delivery is marked delivered
+    /// and its ciphertext dropped. A group message's shared ciphertext is dropped once
+    /// every device has acknowledged it. Acking an unknown or already acked message is a
+    /// no-op.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+        message_id: i64,
+    ) -> Result<(), String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        let delivery = message_deliveries::Entity::find_by_id((message_id, device_id))
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+        let Some(delivery) = delivery else {
+            return Ok(());
+        };
+
+        mark_delivered(&txn, delivery).await?;
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// Mark `delivery` delivered and drop its ciphertext, along with the message's shared
+/// group ciphertext once no other device is still waiting for it.
+async fn mark_delivered<C: ConnectionTrait>(
+    db: &C,
+    delivery: message_deliveries::Model,
+) -> Result<(), String> {
+    let message_id = delivery.message_id;
+    let mut active_delivery: message_deliveries::ActiveModel = delivery.clone().into();
+    if delivery.delivered_at.is_none() {
+        active_delivery.delivered_at = Set(Some(Utc::now().into()));
+    }
+    active_delivery.content = Set(None);
+    active_delivery.update(db).await.map_err(|e| e.to_string())?;
+
+    let pending = message_deliveries::Entity::find()
+        .filter(message_deliveries::Column::MessageId.eq(message_id))
+        .filter(message_deliveries::Column::DeliveredAt.is_null())
+        .count(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    if pending == 0 {
+        let message = messages::Entity::find_by_id(message_id)
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(message) = message.filter(|m| m.ciphertext.is_some()) {
+            let mut active_message: messages::ActiveModel = message.into();
+            active_message.ciphertext = Set(None);
+            active_message.update(db).await.map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,9 +1,41 @@
-use super::dtos::SendMessageRequest;
-use core::entities::{message_deliveries, messages};
+use super::conversations::ensure_members;
+use super::dtos::{
+    ConversationType, DeviceAddressDto, DeviceMessageDto, GroupDelivery, GroupMessageFanout,
+    MultiDeviceSendOutcome, SendGroupMessageRequest, SendMessageRequest,
+    SendMultiDeviceMessageRequest, SentMessageDto, StaleDevicesDto,
+};
+use chat_core::entities::{conv_members, conversations, devices, message_deliveries, messages};
+use sea_orm::sea_query::Query;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set, TransactionTrait,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
 };
 use chrono::Utc;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use uuid::Uuid;
+
+/// Returned when a group message was encrypted under a Sender Key from before the last
+/// membership departure; the client must rotate, redistribute and resend.
+pub const STALE_SENDER_KEY: &str = "Sender key is stale";
+
+/// A message this device already sent to the conversation under `client_message_id`.
+/// Ids are only unique per sending device, so retries never match another sender's message.
+async fn find_sent<C: ConnectionTrait>(
+    db: &C,
+    conversation_id: Uuid,
+    sender_id: Uuid,
+    sender_device_id: i64,
+    client_message_id: Uuid,
+) -> Result<Option<messages::Model>, String> {
+    messages::Entity::find()
+        .filter(messages::Column::ConvId.eq(conversation_id))
+        .filter(messages::Column::SenderUserId.eq(sender_id))
+        .filter(messages::Column::SenderDeviceId.eq(sender_device_id))
+        .filter(messages::Column::ClientMessageId.eq(client_message_id))
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
 
 pub struct SendMessageUseCase;
 
@@ -11,18 +43,32 @@ impl SendMessageUseCase {
     pub async fn execute(
         db: &DatabaseConnection,
         req: SendMessageRequest,
-    ) -> Result<(), String> {
+    ) -> Result<SentMessageDto, String> {
         let txn = db.begin().await.map_err(|e| e.to_string())?;
 
-        // 1. Check if message exists (deduplication)
-        let message = messages::Entity::find()
-            .filter(messages::Column::ClientMessageId.eq(req.client_message_id))
+        // 0. Both ends must currently belong to the conversation
+        ensure_members(&txn, req.conversation_id, &[req.sender_id, req.recipient_id]).await?;
+        // Locked until commit so the recipient device's messages commit in id order
+        devices::Entity::find_by_id(req.recipient_device_id)
+            .filter(devices::Column::UserId.eq(req.recipient_id))
+            .lock_exclusive()
             .one(&txn)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| e.to_string())?
+            .ok_or("Device not found")?;
+
+        // 1. Check if message exists (deduplication)
+        let message = find_sent(
+            &txn,
+            req.conversation_id,
+            req.sender_id,
+            req.sender_device_id,
+            req.client_message_id,
+        )
+        .await?;
 
-        let message_id = if let Some(msg) = message {
-            msg.message_id
+        let message = if let Some(msg) = message {
+            msg
         } else {
             // Create new message
             let new_msg = messages::ActiveModel {
@@ -34,11 +80,12 @@ impl SendMessageUseCase {
                 content: Set("".to_string()), // Placeholder for sender's copy
                 iv: Set(Vec::new()),
                 sent_at: Set(Utc::now().into()),
+                extra: Set(serde_json::json!({})),
                 ..Default::default()
             };
-            let inserted_msg = new_msg.insert(&txn).await.map_err(|e| e.to_string())?;
-            inserted_msg.message_id
+            new_msg.insert(&txn).await.map_err(|e| e.to_string())?
         };
+        let message_id = message.message_id;
 
         // 2. Insert Delivery
         // Check if delivery already exists
@@ -62,6 +109,243 @@ impl SendMessageUseCase {
 
         txn.commit().await.map_err(|e| e.to_string())?;
 
-        Ok(())
+        Ok(SentMessageDto {
+            message_id,
+            sent_at: message.sent_at.timestamp(),
+        })
+    }
+
+    /// Store one message with a copy for every device of the recipient and every other
+    /// device of the sender, or none at all if the client's device list is stale.
+    pub async fn execute_multi_device(
+        db: &DatabaseConnection,
+        req: SendMultiDeviceMessageRequest,
+    ) -> Result<MultiDeviceSendOutcome, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        ensure_members(&txn, req.conversation_id, &[req.sender_id, req.recipient_id]).await?;
+
+        // Deduplicate retries; the original send already stored every copy
+        let existing = find_sent(
+            &txn,
+            req.conversation_id,
+            req.sender_id,
+            req.sender_device_id,
+            req.client_message_id,
+        )
+        .await?;
+        if let Some(existing) = existing {
+            txn.commit().await.map_err(|e| e.to_string())?;
+            return Ok(MultiDeviceSendOutcome::Sent {
+                sent: SentMessageDto {
+                    message_id: existing.message_id,
+                    sent_at: existing.sent_at.timestamp(),
+                },
+                deliveries: Vec::new(),
+            });
+        }
+
+        // Locked like the single-device send, in device order to avoid deadlocks
+        let expected: BTreeSet<DeviceAddressDto> = devices::Entity::find()
+            .filter(devices::Column::UserId.is_in([req.recipient_id, req.sender_id]))
+            .filter(devices::Column::IsActive.eq(true))
+            .filter(devices::Column::DeviceId.ne(req.sender_device_id))
+            .order_by_asc(devices::Column::DeviceId)
+            .lock_exclusive()
+            .all(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|d| DeviceAddressDto {
+                user_id: d.user_id,
+                device_id: d.device_id,
+            })
+            .collect();
+
+        let mut copies: BTreeMap<DeviceAddressDto, DeviceMessageDto> = BTreeMap::new();
+        let mut extra = BTreeSet::new();
+        for message in req.messages {
+            let address = DeviceAddressDto {
+                user_id: message.recipient_id,
+                device_id: message.recipient_device_id,
+            };
+            if !expected.contains(&address) {
+                extra.insert(address);
+            } else if copies.insert(address, message).is_some() {
+                return Err("Invalid messages: duplicate device".to_string());
+            }
+        }
+        let missing: Vec<DeviceAddressDto> = expected
+            .into_iter()
+            .filter(|address| !copies.contains_key(address))
+            .collect();
+        if !missing.is_empty() || !extra.is_empty() {
+            return Ok(MultiDeviceSendOutcome::StaleDevices(StaleDevicesDto {
+                missing,
+                extra: extra.into_iter().collect(),
+            }));
+        }
+
+        let message = messages::ActiveModel {
+            conv_id: Set(req.conversation_id),
+            client_message_id: Set(Some(req.client_message_id)),
+            sender_user_id: Set(req.sender_id),
+            sender_device_id: Set(req.sender_device_id),
+            message_type: Set(1), // Signal Message
+            content: Set("".to_string()),
+            iv: Set(Vec::new()),
+            sent_at: Set(Utc::now().into()),
+            extra: Set(serde_json::json!({})),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut deliveries = Vec::with_capacity(copies.len());
+        for copy in copies.into_values() {
+            message_deliveries::ActiveModel {
+                message_id: Set(message.message_id),
+                device_id: Set(copy.recipient_device_id),
+                content: Set(Some(copy.content.clone())),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+            deliveries.push(copy);
+        }
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(MultiDeviceSendOutcome::Sent {
+            sent: SentMessageDto {
+                message_id: message.message_id,
+                sent_at: message.sent_at.timestamp(),
+            },
+            deliveries,
+        })
+    }
+}
+
+pub struct SendGroupMessageUseCase;
+
+impl SendGroupMessageUseCase {
+    /// Store a Sender Key message once and queue it for every other active device of
+    /// every current member.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        req: SendGroupMessageRequest,
+    ) -> Result<GroupMessageFanout, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        let conversation = conversations::Entity::find_by_id(req.conversation_id)
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Conversation not found")?;
+        if ConversationType::from_i16(conversation.conv_type) != Some(ConversationType::Group) {
+            return Err("Invalid conversation: not a group".to_string());
+        }
+        ensure_members(&txn, req.conversation_id, &[req.sender_id]).await?;
+        if req.sender_key_epoch != conversation.sender_key_epoch {
+            return Err(STALE_SENDER_KEY.to_string());
+        }
+
+        // Deduplicate retries; the original send already queued every delivery
+        let existing = find_sent(
+            &txn,
+            req.conversation_id,
+            req.sender_id,
+            req.sender_device_id,
+            req.client_message_id,
+        )
+        .await?;
+        if let Some(existing) = existing {
+            txn.commit().await.map_err(|e| e.to_string())?;
+            return Ok(GroupMessageFanout {
+                message_id: existing.message_id,
+                sent_at: existing.sent_at.timestamp(),
+                deliveries: Vec::new(),
+            });
+        }
+
+        let members = Query::select()
+            .column(conv_members::Column::UserId)
+            .from(conv_members::Entity)
+            .and_where(conv_members::Column::ConvId.eq(req.conversation_id))
+            .and_where(conv_members::Column::LeftAt.is_null())
+            .to_owned();
+        // Every target's row stays locked until commit, as for direct sends
+        let targets = devices::Entity::find()
+            .filter(devices::Column::UserId.in_subquery(members))
+            .filter(devices::Column::IsActive.eq(true))
+            .filter(devices::Column::DeviceId.ne(req.sender_device_id))
+            .order_by_asc(devices::Column::DeviceId)
+            .lock_exclusive()
+            .all(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut distributions: HashMap<(uuid::Uuid, i64), Vec<u8>> = HashMap::new();
+        for distribution in req.distributions {
+            let known = targets.iter().any(|d| {
+                d.user_id == distribution.recipient_id
+                    && d.device_id == distribution.recipient_device_id
+            });
+            if !known {
+                return Err("Invalid distribution: device is not in the conversation".to_string());
+            }
+            distributions.insert(
+                (distribution.recipient_id, distribution.recipient_device_id),
+                distribution.content,
+            );
+        }
+
+        let message = messages::ActiveModel {
+            conv_id: Set(req.conversation_id),
+            client_message_id: Set(Some(req.client_message_id)),
+            sender_user_id: Set(req.sender_id),
+            sender_device_id: Set(req.sender_device_id),
+            message_type: Set(2), // Sender Key Message
+            content: Set("".to_string()),
+            iv: Set(Vec::new()),
+            ciphertext: Set(Some(req.content)),
+            sent_at: Set(Utc::now().into()),
+            extra: Set(serde_json::json!({})),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut deliveries = Vec::with_capacity(targets.len());
+        for device in targets {
+            let sender_key_distribution =
+                distributions.remove(&(device.user_id, device.device_id));
+            message_deliveries::ActiveModel {
+                message_id: Set(message.message_id),
+                device_id: Set(device.device_id),
+                content: Set(sender_key_distribution.clone()),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            deliveries.push(GroupDelivery {
+                recipient_id: device.user_id,
+                recipient_device_id: device.device_id,
+                sender_key_distribution,
+            });
+        }
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(GroupMessageFanout {
+            message_id: message.message_id,
+            sent_at: message.sent_at.timestamp(),
+            deliveries,
+        })
     }
 }
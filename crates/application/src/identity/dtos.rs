@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SafetyNumberResponse {
+    pub peer_user_id: Uuid,
+    /// 60 digits, usually shown as 12 groups of 5.
+    pub safety_number: String,
+    /// QR payload for the peer to scan.
+    pub scannable: Vec<u8>,
+    /// Whether the peer was verified and their identity keys have not changed since.
+    pub verified: bool,
+}
+
+/// Carries the safety number the user compared, the QR payload they scanned, or both.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyContactRequest {
+    /// The safety number the user compared out of band.
+    #[serde(default)]
+    pub safety_number: Option<String>,
+    /// The `scannable` payload read from the contact's screen.
+    #[serde(default)]
+    pub scanned: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityChangeDto {
+    pub change_id: i64,
+    pub user_id: Uuid,
+    pub identity_key: Vec<u8>,
+    pub changed_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityChangesQuery {
+    pub after_change_id: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityChangesResponse {
+    pub changes: Vec<IdentityChangeDto>,
+}
+
+/// The latest identity change of a user and everyone who shares a conversation with them.
+#[derive(Debug)]
+pub struct IdentityChangeNotice {
+    pub change: IdentityChangeDto,
+    pub recipients: Vec<Uuid>,
+}
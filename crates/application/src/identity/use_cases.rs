@@ -0,0 +1,277 @@
+use super::dtos::{
+    IdentityChangeDto, IdentityChangeNotice, SafetyNumberResponse, VerifyContactRequest,
+};
+use chat_core::entities::{conv_members, devices, identity_key_changes, verified_contacts};
+use chat_core::signal::fingerprint::{fingerprint, matches_scanned, Fingerprint};
+use chrono::Utc;
+use sea_orm::sea_query::{OnConflict, Query, SelectStatement};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use uuid::Uuid;
+
+pub struct GetSafetyNumberUseCase;
+
+impl GetSafetyNumberUseCase {
+    /// Safety number between the caller and another member of a conversation they both belong to.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        conversation_id: Uuid,
+        peer_user_id: Uuid,
+    ) -> Result<SafetyNumberResponse, String> {
+        if user_id == peer_user_id {
+            return Err("Cannot compute a safety number with yourself".to_string());
+        }
+
+        let members = conv_members::Entity::find()
+            .filter(conv_members::Column::ConvId.eq(conversation_id))
+            .filter(conv_members::Column::UserId.is_in([user_id, peer_user_id]))
+            .filter(conv_members::Column::LeftAt.is_null())
+            .count(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if members < 2 {
+            return Err("Conversation not found".to_string());
+        }
+
+        let fingerprint = compute_fingerprint(db, user_id, peer_user_id).await?;
+
+        let verified = verified_contacts::Entity::find_by_id((user_id, peer_user_id))
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .is_some_and(|v| v.identity_hash == fingerprint.remote_hash);
+
+        Ok(SafetyNumberResponse {
+            peer_user_id,
+            safety_number: fingerprint.safety_number,
+            scannable: fingerprint.scannable,
+            verified,
+        })
+    }
+}
+
+pub struct VerifyContactUseCase;
+
+impl VerifyContactUseCase {
+    /// Mark `contact_user_id`, who must share a conversation with the user, as verified,
+    /// provided the safety number compared or the code scanned is still the current one.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+        req: VerifyContactRequest,
+    ) -> Result<(), String> {
+        if user_id == contact_user_id {
+            return Err("Cannot verify yourself".to_string());
+        }
+        if req.safety_number.is_none() && req.scanned.is_none() {
+            return Err("Cannot verify without a safety number or scanned code".to_string());
+        }
+
+        let shared = conv_members::Entity::find()
+            .filter(conv_members::Column::ConvId.in_subquery(conversations_of(user_id)))
+            .filter(conv_members::Column::UserId.eq(contact_user_id))
+            .filter(conv_members::Column::LeftAt.is_null())
+            .count(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        if shared == 0 {
+            return Err("Contact not found".to_string());
+        }
+
+        let fingerprint = compute_fingerprint(db, user_id, contact_user_id).await?;
+
+        if let Some(safety_number) = &req.safety_number {
+            let submitted: String = safety_number
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            if submitted != fingerprint.safety_number {
+                return Err("Safety number does not match".to_string());
+            }
+        }
+        if let Some(scanned) = &req.scanned {
+            let matches = matches_scanned(&fingerprint.scannable, scanned)
+                .map_err(|e| format!("Invalid scanned code: {}", e))?;
+            if !matches {
+                return Err("Safety number does not match".to_string());
+            }
+        }
+
+        verified_contacts::Entity::insert(verified_contacts::ActiveModel {
+            user_id: Set(user_id),
+            contact_user_id: Set(contact_user_id),
+            identity_hash: Set(fingerprint.remote_hash.to_vec()),
+            verified_at: Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                verified_contacts::Column::UserId,
+                verified_contacts::Column::ContactUserId,
+            ])
+            .update_columns([
+                verified_contacts::Column::IdentityHash,
+                verified_contacts::Column::VerifiedAt,
+            ])
+            .to_owned(),
+        )
+        .exec(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+pub struct UnverifyContactUseCase;
+
+impl UnverifyContactUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        contact_user_id: Uuid,
+    ) -> Result<(), String> {
+        verified_contacts::Entity::delete_by_id((user_id, contact_user_id))
+            .exec(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// Identity changes are synced in pages of at most this many.
+pub const MAX_IDENTITY_CHANGES_PAGE: u64 = 100;
+
+pub struct GetIdentityChangeNoticeUseCase;
+
+impl GetIdentityChangeNoticeUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+    ) -> Result<Option<IdentityChangeNotice>, String> {
+        let change = identity_key_changes::Entity::find()
+            .filter(identity_key_changes::Column::UserId.eq(user_id))
+            .order_by_desc(identity_key_changes::Column::ChangeId)
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let Some(change) = change else {
+            return Ok(None);
+        };
+
+        let recipients = conv_members::Entity::find()
+            .select_only()
+            .column(conv_members::Column::UserId)
+            .distinct()
+            .filter(conv_members::Column::ConvId.in_subquery(conversations_of(user_id)))
+            .filter(conv_members::Column::LeftAt.is_null())
+            .filter(conv_members::Column::UserId.ne(user_id))
+            .into_tuple()
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(Some(IdentityChangeNotice {
+            change: change.into(),
+            recipients,
+        }))
+    }
+}
+
+pub struct SyncIdentityChangesUseCase;
+
+impl SyncIdentityChangesUseCase {
+    /// Identity changes of everyone the caller shares a conversation with, oldest first.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        after_change_id: Option<i64>,
+        limit: u64,
+    ) -> Result<Vec<IdentityChangeDto>, String> {
+        let contacts = Query::select()
+            .column(conv_members::Column::UserId)
+            .from(conv_members::Entity)
+            .and_where(conv_members::Column::ConvId.in_subquery(conversations_of(user_id)))
+            .and_where(conv_members::Column::LeftAt.is_null())
+            .and_where(conv_members::Column::UserId.ne(user_id))
+            .to_owned();
+
+        let mut query = identity_key_changes::Entity::find()
+            .filter(identity_key_changes::Column::UserId.in_subquery(contacts));
+
+        if let Some(after) = after_change_id {
+            query = query.filter(identity_key_changes::Column::ChangeId.gt(after));
+        }
+
+        let changes = query
+            .order_by_asc(identity_key_changes::Column::ChangeId)
+            .limit(limit.clamp(1, MAX_IDENTITY_CHANGES_PAGE))
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(changes.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<identity_key_changes::Model> for IdentityChangeDto {
+    fn from(change: identity_key_changes::Model) -> Self {
+        Self {
+            change_id: change.change_id,
+            user_id: change.user_id,
+            identity_key: change.identity_key,
+            changed_at: change.changed_at.timestamp(),
+        }
+    }
+}
+
+/// Conversations `user_id` is currently a member of.
+pub(crate) fn conversations_of(user_id: Uuid) -> SelectStatement {
+    Query::select()
+        .column(conv_members::Column::ConvId)
+        .from(conv_members::Entity)
+        .and_where(conv_members::Column::UserId.eq(user_id))
+        .and_where(conv_members::Column::LeftAt.is_null())
+        .to_owned()
+}
+
+async fn compute_fingerprint(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    peer_user_id: Uuid,
+) -> Result<Fingerprint, String> {
+    let local_keys = active_identity_keys(db, user_id).await?;
+    let peer_keys = active_identity_keys(db, peer_user_id).await?;
+    if local_keys.is_empty() || peer_keys.is_empty() {
+        return Err("Contact not found".to_string());
+    }
+
+    Ok(fingerprint(
+        user_id.as_bytes(),
+        &local_keys,
+        peer_user_id.as_bytes(),
+        &peer_keys,
+    ))
+}
+
+async fn active_identity_keys(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+) -> Result<Vec<Vec<u8>>, String> {
+    devices::Entity::find()
+        .select_only()
+        .column(devices::Column::IdentityKeyPublic)
+        .filter(devices::Column::UserId.eq(user_id))
+        .filter(devices::Column::IsActive.eq(true))
+        .order_by_asc(devices::Column::DeviceId)
+        .into_tuple()
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,14 +11,47 @@ pub struct PreKeyBundleResponse {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct UserPreKeyBundlesResponse {
+    pub user_id: uuid::Uuid,
+    pub devices: Vec<PreKeyBundleResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedPreKeyDto {
     pub id: i32,
     pub key: Vec<u8>,
     pub signature: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PreKeyDto {
     pub id: i32,
     pub key: Vec<u8>,
 }
+
+/// Public key material uploaded by a device when it registers or is linked.
+/// Private halves never leave the client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceKeysDto {
+    pub identity_key: Vec<u8>,
+    pub registration_id: i32,
+    pub signed_prekey: SignedPreKeyDto,
+    pub one_time_prekeys: Vec<PreKeyDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadPreKeysRequest {
+    pub one_time_prekeys: Vec<PreKeyDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreKeyCountResponse {
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateSignedPreKeyResponse {
+    pub signed_prekey_id: i32,
+    pub previous_signed_prekey_id: i32,
+    pub previous_expires_at: DateTime<Utc>,
+}
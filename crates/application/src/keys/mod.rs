@@ -1,2 +1,3 @@
 pub mod dtos;
 pub mod use_cases;
+pub mod validation;
@@ -1,63 +1,332 @@
-use super::dtos::{PreKeyBundleResponse, PreKeyDto, SignedPreKeyDto};
-use core::entities::{devices, one_time_prekeys};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use super::dtos::{
+    PreKeyBundleResponse, PreKeyCountResponse, PreKeyDto, RotateSignedPreKeyResponse,
+    SignedPreKeyDto,
+};
+use super::validation::{validate_prekeys, validate_signed_prekey};
+use chat_core::entities::{devices, one_time_prekeys, previous_signed_prekeys};
+use chrono::{Duration, Utc};
+use infrastructure::redis::RedisClient;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbBackend, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, Set, Statement, TransactionTrait,
+};
 use uuid::Uuid;
 
+/// Devices are told to replenish once their one-time prekey stock falls below this.
+pub const PREKEY_LOW_THRESHOLD: u64 = 20;
+
+/// Returned when an upload reuses the id of a one-time prekey that is still stored.
+pub const PREKEY_ID_EXISTS: &str = "Prekey id already exists";
+
+/// Returned when a rotation reuses the id of the current or a replaced signed prekey.
+pub const SIGNED_PREKEY_ID_EXISTS: &str = "Signed prekey id already exists";
+
+/// Returned when a caller fetches bundles faster than `BUNDLE_FETCHES_PER_CALLER` or a
+/// target is fetched faster than `BUNDLE_FETCHES_PER_TARGET`.
+pub const BUNDLE_RATE_LIMITED: &str = "Too many prekey bundle requests";
+
+/// Each fetch claims a one-time prekey from every device of the target, so fetches are
+/// limited per hour to keep anyone from draining another user's stock.
+pub const BUNDLE_FETCHES_PER_CALLER: u64 = 100;
+pub const BUNDLE_FETCHES_PER_TARGET: u64 = 200;
+const BUNDLE_RATE_WINDOW_SECONDS: i64 = 3600;
+
 pub struct GetPreKeyBundleUseCase;
 
 impl GetPreKeyBundleUseCase {
+    /// Bundle for one active device of `user_id` with a claimed one-time prekey, fetched
+    /// on behalf of `requester_id` under the same limits as `GetUserPreKeyBundlesUseCase`.
     pub async fn execute(
         db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        requester_id: Uuid,
         user_id: Uuid,
         device_id: i64,
     ) -> Result<PreKeyBundleResponse, String> {
+        check_bundle_rate_limits(redis, requester_id, user_id).await?;
+
         // 1. Fetch Device
         let device = devices::Entity::find()
             .filter(devices::Column::UserId.eq(user_id))
             .filter(devices::Column::DeviceId.eq(device_id))
+            .filter(devices::Column::IsActive.eq(true))
             .one(db)
             .await
             .map_err(|e| e.to_string())?
             .ok_or("Device not found")?;
 
-        // 2. Fetch One One-Time Prekey
-        // We need to fetch one and delete it (or mark as used).
-        // For simplicity, we just fetch one. In a real Signal implementation, 
-        // we should delete it, but that requires a transaction and might run out of keys.
-        // Signal server usually deletes it.
-        
-        let prekey = one_time_prekeys::Entity::find()
+        // 2. Claim One One-Time Prekey (removed atomically so it is never handed out twice)
+        let prekey = claim_one_time_prekey(db, device_id).await?;
+
+        Ok(build_bundle(device, prekey))
+    }
+}
+
+pub struct GetUserPreKeyBundlesUseCase;
+
+impl GetUserPreKeyBundlesUseCase {
+    /// Bundles for every active device of `user_id`, each with its own claimed one-time
+    /// prekey, fetched on behalf of `requester_id`.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        requester_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Vec<PreKeyBundleResponse>, String> {
+        check_bundle_rate_limits(redis, requester_id, user_id).await?;
+
+        let active_devices = devices::Entity::find()
+            .filter(devices::Column::UserId.eq(user_id))
+            .filter(devices::Column::IsActive.eq(true))
+            .order_by_asc(devices::Column::DeviceId)
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if active_devices.is_empty() {
+            return Err("Device not found".to_string());
+        }
+
+        let mut bundles = Vec::with_capacity(active_devices.len());
+        for device in active_devices {
+            let prekey = claim_one_time_prekey(db, device.device_id).await?;
+            bundles.push(build_bundle(device, prekey));
+        }
+
+        Ok(bundles)
+    }
+}
+
+/// Count one bundle fetch against both the requester's and the target's hourly limit.
+async fn check_bundle_rate_limits(
+    redis: &mut RedisClient,
+    requester_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), String> {
+    let caller_allowed = redis
+        .within_rate_limit(
+            &format!("prekey_bundles:caller:{}", requester_id),
+            BUNDLE_FETCHES_PER_CALLER,
+            BUNDLE_RATE_WINDOW_SECONDS,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    let target_allowed = redis
+        .within_rate_limit(
+            &format!("prekey_bundles:target:{}", user_id),
+            BUNDLE_FETCHES_PER_TARGET,
+            BUNDLE_RATE_WINDOW_SECONDS,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    if !caller_allowed || !target_allowed {
+        return Err(BUNDLE_RATE_LIMITED.to_string());
+    }
+    Ok(())
+}
+
+fn build_bundle(
+    device: devices::Model,
+    prekey: Option<one_time_prekeys::Model>,
+) -> PreKeyBundleResponse {
+    PreKeyBundleResponse {
+        device_id: device.device_id,
+        registration_id: device.registration_id,
+        identity_key: device.identity_key_public,
+        signed_prekey: SignedPreKeyDto {
+            id: device.signed_prekey_id,
+            key: device.signed_prekey_public,
+            signature: device.signed_prekey_signature,
+        },
+        one_time_prekey: prekey.map(|pk| PreKeyDto {
+            id: pk.prekey_id,
+            key: pk.public_key,
+        }),
+    }
+}
+
+/// Delete and return the device's oldest one-time prekey in a single statement.
+///
+/// `FOR UPDATE SKIP LOCKED` makes concurrent claimers pick different rows instead of
+/// blocking on (or both reading) the same one.
+async fn claim_one_time_prekey(
+    db: &DatabaseConnection,
+    device_id: i64,
+) -> Result<Option<one_time_prekeys::Model>, String> {
+    one_time_prekeys::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"DELETE FROM one_time_prekeys
+               WHERE (device_id, prekey_id) = (
+                   SELECT device_id, prekey_id FROM one_time_prekeys
+                   WHERE device_id = $1
+                   ORDER BY prekey_id
+                   LIMIT 1
+                   FOR UPDATE SKIP LOCKED
+               )
+               RETURNING device_id, prekey_id, public_key"#,
+            [device_id.into()],
+        ))
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+pub struct UploadPreKeysUseCase;
+
+impl UploadPreKeysUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+        prekeys: Vec<PreKeyDto>,
+    ) -> Result<PreKeyCountResponse, String> {
+        if prekeys.is_empty() {
+            return Err("Invalid key bundle: no prekeys to upload".to_string());
+        }
+        validate_prekeys(&prekeys).map_err(|e| e.to_string())?;
+
+        // Prekey ids must never be reused while an older key with the same id is still stored.
+        // Conflicting rows are skipped rather than failing the statement, so a concurrent
+        // upload of the same ids shows up as a short insert and the whole batch is rolled back.
+        let uploaded = prekeys.len() as u64;
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+        let inserted = one_time_prekeys::Entity::insert_many(prekeys.into_iter().map(|pk| {
+            one_time_prekeys::ActiveModel {
+                device_id: Set(device_id),
+                prekey_id: Set(pk.id),
+                public_key: Set(pk.key),
+            }
+        }))
+        .on_conflict(
+            OnConflict::columns([
+                one_time_prekeys::Column::DeviceId,
+                one_time_prekeys::Column::PrekeyId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        if inserted != uploaded {
+            return Err(PREKEY_ID_EXISTS.to_string());
+        }
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        GetPreKeyCountUseCase::execute(db, device_id).await
+    }
+}
+
+pub struct GetPreKeyCountUseCase;
+
+impl GetPreKeyCountUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+    ) -> Result<PreKeyCountResponse, String> {
+        let count = one_time_prekeys::Entity::find()
             .filter(one_time_prekeys::Column::DeviceId.eq(device_id))
-            .order_by_asc(one_time_prekeys::Column::PrekeyId) // Get oldest
-            .one(db)
+            .count(db)
             .await
             .map_err(|e| e.to_string())?;
 
-        // If we found a prekey, we should ideally delete it.
-        if let Some(ref pk) = prekey {
-             // TODO: Delete prekey in a transaction? 
-             // For now, let's just return it. 
-             // If we delete it, we need to make sure we don't run out.
-             // The client should replenish keys.
-             let _ = one_time_prekeys::Entity::delete_by_id((pk.device_id, pk.prekey_id))
-                 .exec(db)
-                 .await
-                 .map_err(|e| e.to_string())?;
+        Ok(PreKeyCountResponse { count })
+    }
+}
+
+pub struct CheckPreKeyStockUseCase;
+
+impl CheckPreKeyStockUseCase {
+    /// The device's remaining one-time prekeys, if it is running low and should be told to
+    /// upload more.
+    pub async fn execute(db: &DatabaseConnection, device_id: i64) -> Result<Option<u64>, String> {
+        let remaining = GetPreKeyCountUseCase::execute(db, device_id).await?.count;
+        Ok((remaining < PREKEY_LOW_THRESHOLD).then_some(remaining))
+    }
+}
+
+pub struct RotateSignedPreKeyUseCase;
+
+impl RotateSignedPreKeyUseCase {
+    /// Replace the device's signed prekey, keeping the old one for `grace_period`.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+        signed_prekey: SignedPreKeyDto,
+        grace_period: Duration,
+    ) -> Result<RotateSignedPreKeyResponse, String> {
+        let txn = db.begin().await.map_err(|e| e.to_string())?;
+
+        // Locked so concurrent rotations archive the key they actually replace, one at a time
+        let device = devices::Entity::find_by_id(device_id)
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Device not found")?;
+
+        validate_signed_prekey(&device.identity_key_public, &signed_prekey)
+            .map_err(|e| e.to_string())?;
+
+        // Signed prekey ids identify the key in X3DH messages, so they can't be reused
+        let id_in_history =
+            previous_signed_prekeys::Entity::find_by_id((device_id, signed_prekey.id))
+                .one(&txn)
+                .await
+                .map_err(|e| e.to_string())?
+                .is_some();
+        if signed_prekey.id == device.signed_prekey_id || id_in_history {
+            return Err(SIGNED_PREKEY_ID_EXISTS.to_string());
         }
 
-        Ok(PreKeyBundleResponse {
-            device_id: device.device_id,
-            registration_id: device.registration_id,
-            identity_key: device.identity_key_public,
-            signed_prekey: SignedPreKeyDto {
-                id: device.signed_prekey_id,
-                key: device.signed_prekey_public,
-                signature: device.signed_prekey_signature,
-            },
-            one_time_prekey: prekey.map(|pk| PreKeyDto {
-                id: pk.prekey_id,
-                key: pk.public_key,
-            }),
+        let now = Utc::now();
+        let previous_expires_at = now + grace_period;
+        let previous_signed_prekey_id = device.signed_prekey_id;
+
+        previous_signed_prekeys::ActiveModel {
+            device_id: Set(device_id),
+            signed_prekey_id: Set(device.signed_prekey_id),
+            public_key: Set(device.signed_prekey_public.clone()),
+            signature: Set(device.signed_prekey_signature.clone()),
+            replaced_at: Set(now.into()),
+            expires_at: Set(previous_expires_at.into()),
+        }
+        .insert(&txn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let mut active_device: devices::ActiveModel = device.into();
+        active_device.signed_prekey_id = Set(signed_prekey.id);
+        active_device.signed_prekey_public = Set(signed_prekey.key);
+        active_device.signed_prekey_signature = Set(signed_prekey.signature);
+        active_device
+            .update(&txn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        txn.commit().await.map_err(|e| e.to_string())?;
+
+        Ok(RotateSignedPreKeyResponse {
+            signed_prekey_id: signed_prekey.id,
+            previous_signed_prekey_id,
+            previous_expires_at,
         })
     }
 }
+
+pub struct PruneSignedPreKeysUseCase;
+
+impl PruneSignedPreKeysUseCase {
+    /// Delete replaced signed prekeys whose grace period is over. Returns how many were removed.
+    pub async fn execute(db: &DatabaseConnection) -> Result<u64, String> {
+        let result = previous_signed_prekeys::Entity::delete_many()
+            .filter(previous_signed_prekeys::Column::ExpiresAt.lte(Utc::now()))
+            .exec(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(result.rows_affected)
+    }
+}
@@ -0,0 +1,88 @@
+use super::dtos::{DeviceKeysDto, PreKeyDto, SignedPreKeyDto};
+use anyhow::{anyhow, Result};
+use infrastructure::crypto::signal::verify_signed_prekey;
+use std::collections::HashSet;
+
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+pub const MAX_PREKEYS_PER_UPLOAD: usize = 100;
+const MAX_REGISTRATION_ID: i32 = 0x3fff;
+
+/// Check a full key bundle uploaded at registration or linking time.
+pub fn validate_device_keys(keys: &DeviceKeysDto) -> Result<()> {
+    if keys.identity_key.len() != PUBLIC_KEY_LENGTH {
+        return Err(anyhow!(
+            "Invalid key bundle: identity key must be {} bytes",
+            PUBLIC_KEY_LENGTH
+        ));
+    }
+
+    if keys.registration_id < 1 || keys.registration_id > MAX_REGISTRATION_ID {
+        return Err(anyhow!(
+            "Invalid key bundle: registration id must be between 1 and {}",
+            MAX_REGISTRATION_ID
+        ));
+    }
+
+    validate_signed_prekey(&keys.identity_key, &keys.signed_prekey)?;
+
+    if keys.one_time_prekeys.is_empty() {
+        return Err(anyhow!(
+            "Invalid key bundle: at least one one-time prekey is required"
+        ));
+    }
+
+    validate_prekeys(&keys.one_time_prekeys)
+}
+
+/// Check a signed prekey and its signature against the device's identity key.
+pub fn validate_signed_prekey(identity_key: &[u8], signed_prekey: &SignedPreKeyDto) -> Result<()> {
+    if signed_prekey.id < 0 {
+        return Err(anyhow!(
+            "Invalid key bundle: signed prekey id must not be negative"
+        ));
+    }
+
+    if signed_prekey.key.len() != PUBLIC_KEY_LENGTH {
+        return Err(anyhow!(
+            "Invalid key bundle: signed prekey must be {} bytes",
+            PUBLIC_KEY_LENGTH
+        ));
+    }
+
+    verify_signed_prekey(identity_key, &signed_prekey.key, &signed_prekey.signature)
+        .map_err(|e| anyhow!("Invalid key bundle: {}", e))
+}
+
+/// Check a batch of one-time prekeys: size limit, key length and unique ids.
+pub fn validate_prekeys(prekeys: &[PreKeyDto]) -> Result<()> {
+    if prekeys.len() > MAX_PREKEYS_PER_UPLOAD {
+        return Err(anyhow!(
+            "Invalid key bundle: at most {} one-time prekeys per upload",
+            MAX_PREKEYS_PER_UPLOAD
+        ));
+    }
+
+    let mut seen = HashSet::with_capacity(prekeys.len());
+    for prekey in prekeys {
+        if prekey.id < 0 {
+            return Err(anyhow!(
+                "Invalid key bundle: prekey id must not be negative"
+            ));
+        }
+        if prekey.key.len() != PUBLIC_KEY_LENGTH {
+            return Err(anyhow!(
+                "Invalid key bundle: prekey {} must be {} bytes",
+                prekey.id,
+                PUBLIC_KEY_LENGTH
+            ));
+        }
+        if !seen.insert(prekey.id) {
+            return Err(anyhow!(
+                "Invalid key bundle: duplicate prekey id {}",
+                prekey.id
+            ));
+        }
+    }
+
+    Ok(())
+}
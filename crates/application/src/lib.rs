@@ -1,3 +1,6 @@
 pub mod auth;
 pub mod chat;
+pub mod identity;
 pub mod keys;
+pub mod presence;
+pub mod sealed_sender;
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Who may see a user's online status and last seen time. Being online gives away when the
+/// user was last seen, so the two are always shown or hidden together.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LastSeenVisibility {
+    Everyone,
+    /// Users who share a conversation with them
+    Contacts,
+    Nobody,
+}
+
+impl LastSeenVisibility {
+    /// Value stored in `users.last_seen_visibility`.
+    pub fn as_i16(self) -> i16 {
+        match self {
+            LastSeenVisibility::Everyone => 0,
+            LastSeenVisibility::Contacts => 1,
+            LastSeenVisibility::Nobody => 2,
+        }
+    }
+
+    /// Unknown values are treated as the most private setting.
+    pub fn from_i16(value: i16) -> Self {
+        match value {
+            0 => LastSeenVisibility::Everyone,
+            1 => LastSeenVisibility::Contacts,
+            _ => LastSeenVisibility::Nobody,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceDto {
+    pub user_id: Uuid,
+    pub online: bool,
+    pub last_seen_at: Option<i64>,
+}
+
+/// A user came online or went offline, and the watchers allowed to see it.
+#[derive(Debug)]
+pub struct PresenceChange {
+    pub presence: PresenceDto,
+    pub recipients: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceSettingsDto {
+    pub last_seen_visibility: LastSeenVisibility,
+}
@@ -0,0 +1,314 @@
+use super::dtos::{LastSeenVisibility, PresenceChange, PresenceDto, PresenceSettingsDto};
+use crate::identity::use_cases::conversations_of;
+use chat_core::entities::{conv_members, users};
+use chrono::Utc;
+use infrastructure::redis::RedisClient;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Presence subscriptions lapse unless renewed; clients subscribe again on every connect.
+pub const PRESENCE_WATCH_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+/// Users a single `PresenceSubscribe` may name.
+pub const MAX_PRESENCE_SUBSCRIPTIONS: usize = 500;
+
+pub struct MarkDeviceOnlineUseCase;
+
+impl MarkDeviceOnlineUseCase {
+    /// Called when a device connects; it drops offline unless a heartbeat arrives within
+    /// `ttl_seconds`. Returns the update to push to watchers if this made the user online.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        user_id: Uuid,
+        device_id: i64,
+        ttl_seconds: i64,
+    ) -> Result<Option<PresenceChange>, String> {
+        let came_online = redis
+            .set_user_online(&user_id.to_string(), device_id, ttl_seconds)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !came_online {
+            return Ok(None);
+        }
+
+        let user = set_online(db, find_user(db, user_id).await?, true).await?;
+        presence_change(db, redis, user).await.map(Some)
+    }
+}
+
+pub struct RefreshDevicePresenceUseCase;
+
+impl RefreshDevicePresenceUseCase {
+    /// Called on every heartbeat. Only extends the device's expiry to `ttl_seconds` from
+    /// now, unless it already expired and must come online again like a new connection.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        user_id: Uuid,
+        device_id: i64,
+        ttl_seconds: i64,
+    ) -> Result<Option<PresenceChange>, String> {
+        let refreshed = redis
+            .refresh_user_online(&user_id.to_string(), device_id, ttl_seconds)
+            .await
+            .map_err(|e| e.to_string())?;
+        if refreshed {
+            return Ok(None);
+        }
+
+        MarkDeviceOnlineUseCase::execute(db, redis, user_id, device_id, ttl_seconds).await
+    }
+}
+
+pub struct MarkDeviceOfflineUseCase;
+
+impl MarkDeviceOfflineUseCase {
+    /// Returns the update to push to watchers if the user has no other device online.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        user_id: Uuid,
+        device_id: i64,
+    ) -> Result<Option<PresenceChange>, String> {
+        let went_offline = redis
+            .set_user_offline(&user_id.to_string(), device_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !went_offline {
+            return Ok(None);
+        }
+
+        let user = set_online(db, find_user(db, user_id).await?, false).await?;
+        presence_change(db, redis, user).await.map(Some)
+    }
+}
+
+pub struct ExpirePresenceUseCase;
+
+impl ExpirePresenceUseCase {
+    /// Takes offline every user whose devices all stopped sending heartbeats without
+    /// disconnecting. Returns the updates to push to their watchers.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+    ) -> Result<Vec<PresenceChange>, String> {
+        let expired: Vec<Uuid> = redis
+            .expire_presence()
+            .await
+            .map_err(|e| e.to_string())?
+            .iter()
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .collect();
+
+        let mut changes = Vec::with_capacity(expired.len());
+        for user_id in expired {
+            let user = users::Entity::find_by_id(user_id)
+                .filter(users::Column::IsDeleted.eq(false))
+                .one(db)
+                .await
+                .map_err(|e| e.to_string())?;
+            // Deleted users have nobody left to tell
+            let Some(user) = user else {
+                continue;
+            };
+            let user = set_online(db, user, false).await?;
+            changes.push(presence_change(db, redis, user).await?);
+        }
+        Ok(changes)
+    }
+}
+
+pub struct SubscribePresenceUseCase;
+
+impl SubscribePresenceUseCase {
+    /// Subscribes the watcher to future changes and returns the current presence of every
+    /// user whose privacy settings let the watcher see it; the rest are left out.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        watcher_id: Uuid,
+        user_ids: Vec<Uuid>,
+    ) -> Result<Vec<PresenceDto>, String> {
+        let user_ids: Vec<Uuid> = user_ids
+            .into_iter()
+            .filter(|id| *id != watcher_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if user_ids.len() > MAX_PRESENCE_SUBSCRIPTIONS {
+            return Err(format!(
+                "Invalid user_ids: at most {} per subscription",
+                MAX_PRESENCE_SUBSCRIPTIONS
+            ));
+        }
+
+        let watcher = watcher_id.to_string();
+        for user_id in &user_ids {
+            redis
+                .add_presence_watcher(&user_id.to_string(), &watcher, PRESENCE_WATCH_TTL_SECONDS)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let users = users::Entity::find()
+            .filter(users::Column::UserId.is_in(user_ids))
+            .filter(users::Column::IsDeleted.eq(false))
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+        let contacts = contacts_of(db, watcher_id).await?;
+        let visible: Vec<users::Model> = users
+            .into_iter()
+            .filter(|user| can_see(user, contacts.contains(&user.user_id)))
+            .collect();
+
+        let keys: Vec<String> = visible.iter().map(|u| u.user_id.to_string()).collect();
+        let online = redis.users_online(&keys).await.map_err(|e| e.to_string())?;
+
+        Ok(visible
+            .into_iter()
+            .zip(online)
+            .map(|(user, online)| PresenceDto {
+                user_id: user.user_id,
+                online,
+                last_seen_at: user.last_seen_at.map(|t| t.timestamp()),
+            })
+            .collect())
+    }
+}
+
+pub struct UnsubscribePresenceUseCase;
+
+impl UnsubscribePresenceUseCase {
+    pub async fn execute(
+        redis: &mut RedisClient,
+        watcher_id: Uuid,
+        user_ids: Vec<Uuid>,
+    ) -> Result<(), String> {
+        let watcher = watcher_id.to_string();
+        for user_id in user_ids {
+            redis
+                .remove_presence_watcher(&user_id.to_string(), &watcher)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct GetPresenceSettingsUseCase;
+
+impl GetPresenceSettingsUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+    ) -> Result<PresenceSettingsDto, String> {
+        let user = find_user(db, user_id).await?;
+        Ok(PresenceSettingsDto {
+            last_seen_visibility: LastSeenVisibility::from_i16(user.last_seen_visibility),
+        })
+    }
+}
+
+pub struct UpdatePresenceSettingsUseCase;
+
+impl UpdatePresenceSettingsUseCase {
+    /// Applies to updates pushed from now on; watchers are not told about the change itself.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        req: PresenceSettingsDto,
+    ) -> Result<PresenceSettingsDto, String> {
+        let mut user: users::ActiveModel = find_user(db, user_id).await?.into();
+        user.last_seen_visibility = Set(req.last_seen_visibility.as_i16());
+        user.updated_at = Set(Utc::now().into());
+        user.update(db).await.map_err(|e| e.to_string())?;
+
+        Ok(req)
+    }
+}
+
+async fn find_user(db: &DatabaseConnection, user_id: Uuid) -> Result<users::Model, String> {
+    users::Entity::find_by_id(user_id)
+        .filter(users::Column::IsDeleted.eq(false))
+        .one(db)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "User not found".to_string())
+}
+
+/// `last_seen_at` moves on both transitions, so a device that vanished without
+/// disconnecting still leaves a usable last seen time behind.
+async fn set_online(
+    db: &DatabaseConnection,
+    user: users::Model,
+    online: bool,
+) -> Result<users::Model, String> {
+    let mut user: users::ActiveModel = user.into();
+    user.is_online = Set(online);
+    user.last_seen_at = Set(Some(Utc::now().into()));
+    user.update(db).await.map_err(|e| e.to_string())
+}
+
+async fn presence_change(
+    db: &DatabaseConnection,
+    redis: &mut RedisClient,
+    user: users::Model,
+) -> Result<PresenceChange, String> {
+    let watchers: Vec<Uuid> = redis
+        .presence_watchers(&user.user_id.to_string())
+        .await
+        .map_err(|e| e.to_string())?
+        .iter()
+        .filter_map(|id| Uuid::parse_str(id).ok())
+        .collect();
+
+    let recipients = match LastSeenVisibility::from_i16(user.last_seen_visibility) {
+        LastSeenVisibility::Everyone => watchers,
+        LastSeenVisibility::Contacts => {
+            let contacts = contacts_of(db, user.user_id).await?;
+            watchers
+                .into_iter()
+                .filter(|id| contacts.contains(id))
+                .collect()
+        }
+        LastSeenVisibility::Nobody => Vec::new(),
+    };
+
+    Ok(PresenceChange {
+        presence: PresenceDto {
+            user_id: user.user_id,
+            online: user.is_online,
+            last_seen_at: user.last_seen_at.map(|t| t.timestamp()),
+        },
+        recipients,
+    })
+}
+
+fn can_see(user: &users::Model, viewer_is_contact: bool) -> bool {
+    match LastSeenVisibility::from_i16(user.last_seen_visibility) {
+        LastSeenVisibility::Everyone => true,
+        LastSeenVisibility::Contacts => viewer_is_contact,
+        LastSeenVisibility::Nobody => false,
+    }
+}
+
+/// Everyone `user_id` currently shares a conversation with.
+async fn contacts_of(db: &DatabaseConnection, user_id: Uuid) -> Result<HashSet<Uuid>, String> {
+    let contacts: Vec<Uuid> = conv_members::Entity::find()
+        .select_only()
+        .column(conv_members::Column::UserId)
+        .distinct()
+        .filter(conv_members::Column::ConvId.in_subquery(conversations_of(user_id)))
+        .filter(conv_members::Column::LeftAt.is_null())
+        .filter(conv_members::Column::UserId.ne(user_id))
+        .into_tuple()
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(contacts.into_iter().collect())
+}
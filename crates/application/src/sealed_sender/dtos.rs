@@ -0,0 +1,61 @@
+use chat_core::signal::sealed_sender::SenderCertificate;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SenderCertificateResponse {
+    pub certificate: SenderCertificate,
+    /// Trust root clients use to verify certificates found inside sealed envelopes.
+    pub server_public_key: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetUnidentifiedAccessRequest {
+    /// 16-byte key contacts must present to send sealed messages to this user.
+    pub access_key: Vec<u8>,
+    /// Accept sealed messages from anyone, with or without the access key.
+    #[serde(default)]
+    pub allow_unrestricted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendSealedMessageRequest {
+    pub recipient_id: Uuid,
+    pub recipient_device_id: i64,
+    pub access_key: Option<Vec<u8>>,
+    /// Output of `sealed_sender::seal`; opaque to the server.
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SendSealedMessageResponse {
+    pub delivery_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedMessageDto {
+    pub delivery_id: i64,
+    #[serde(with = "serde_bytes")]
+    pub content: Vec<u8>,
+    pub received_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedMessagesQuery {
+    /// The last `delivery_id` of the previous page; omit to start from the oldest sealed
+    /// message not yet acked.
+    pub after_delivery_id: Option<i64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SealedMessagesResponse {
+    pub messages: Vec<SealedMessageDto>,
+    /// Request the next page after the last `delivery_id` while this is set.
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AckSealedMessagesRequest {
+    pub delivery_ids: Vec<i64>,
+}
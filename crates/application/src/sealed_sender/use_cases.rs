@@ -0,0 +1,244 @@
+use super::dtos::{
+    SealedMessageDto, SealedMessagesResponse, SendSealedMessageRequest, SenderCertificateResponse,
+};
+use chat_core::entities::{devices, sealed_deliveries, unidentified_access};
+use chat_core::signal::sealed_sender::{SenderCertificate, ServerKey};
+use chrono::Utc;
+use infrastructure::redis::RedisClient;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use uuid::Uuid;
+
+pub const ACCESS_KEY_LEN: usize = 16;
+
+/// Sealed messages are synced in pages of at most this many.
+pub const MAX_SEALED_MESSAGES_PAGE: u64 = 100;
+
+/// Largest sealed envelope accepted, in bytes.
+pub const MAX_SEALED_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Sealed messages accepted for one recipient per minute, whoever sends them. Senders are
+/// anonymous, so this is what stops a leaked access key from filling the recipient's queue.
+pub const SEALED_SENDS_PER_RECIPIENT: u64 = 300;
+
+const SEALED_SEND_RATE_WINDOW_SECONDS: i64 = 60;
+
+pub const SEALED_MESSAGE_TOO_LARGE: &str = "Sealed message too large";
+pub const SEALED_SEND_RATE_LIMITED: &str = "Too many sealed messages for this recipient";
+
+/// Returned for every failed access check, so unauthenticated callers cannot tell a wrong
+/// key from a user who has not enabled unidentified delivery.
+pub const SEALED_ACCESS_DENIED: &str = "Unidentified delivery not permitted";
+
+pub struct IssueSenderCertificateUseCase;
+
+impl IssueSenderCertificateUseCase {
+    /// Certify the caller's device identity key for `ttl_seconds`.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        server_key: &ServerKey,
+        user_id: Uuid,
+        device_id: i64,
+        ttl_seconds: i64,
+    ) -> Result<SenderCertificateResponse, String> {
+        let device = devices::Entity::find_by_id(device_id)
+            .filter(devices::Column::UserId.eq(user_id))
+            .filter(devices::Column::IsActive.eq(true))
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Device not found")?;
+
+        let certificate = SenderCertificate::issue(
+            &server_key.private_key,
+            user_id,
+            device_id,
+            device.identity_key_public,
+            Utc::now().timestamp() + ttl_seconds,
+        );
+
+        Ok(SenderCertificateResponse {
+            certificate,
+            server_public_key: server_key.public_key.to_vec(),
+        })
+    }
+}
+
+pub struct SetUnidentifiedAccessUseCase;
+
+impl SetUnidentifiedAccessUseCase {
+    pub async fn execute(
+        db: &DatabaseConnection,
+        user_id: Uuid,
+        access_key: Vec<u8>,
+        allow_unrestricted: bool,
+    ) -> Result<(), String> {
+        if access_key.len() != ACCESS_KEY_LEN {
+            return Err(format!("Access key must be {} bytes", ACCESS_KEY_LEN));
+        }
+
+        unidentified_access::Entity::insert(unidentified_access::ActiveModel {
+            user_id: Set(user_id),
+            access_key: Set(access_key),
+            allow_unrestricted: Set(allow_unrestricted),
+            updated_at: Set(Utc::now().into()),
+        })
+        .on_conflict(
+            OnConflict::column(unidentified_access::Column::UserId)
+                .update_columns([
+                    unidentified_access::Column::AccessKey,
+                    unidentified_access::Column::AllowUnrestricted,
+                    unidentified_access::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+pub struct SendSealedMessageUseCase;
+
+impl SendSealedMessageUseCase {
+    /// Queue a sealed envelope for one device. The caller is not authenticated; delivery is
+    /// authorised by the recipient's access key and nothing about the sender is stored.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        redis: &mut RedisClient,
+        req: SendSealedMessageRequest,
+    ) -> Result<SealedMessageDto, String> {
+        if req.content.len() > MAX_SEALED_MESSAGE_LEN {
+            return Err(SEALED_MESSAGE_TOO_LARGE.to_string());
+        }
+
+        let access = unidentified_access::Entity::find_by_id(req.recipient_id)
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or(SEALED_ACCESS_DENIED)?;
+
+        let key_matches = req
+            .access_key
+            .as_deref()
+            .is_some_and(|key| constant_time_eq(key, &access.access_key));
+        if !key_matches && !access.allow_unrestricted {
+            return Err(SEALED_ACCESS_DENIED.to_string());
+        }
+
+        // Each recipient has one access key, so this limits every holder of it together
+        let within_limit = redis
+            .within_rate_limit(
+                &format!("sealed_send:{}", req.recipient_id),
+                SEALED_SENDS_PER_RECIPIENT,
+                SEALED_SEND_RATE_WINDOW_SECONDS,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        if !within_limit {
+            return Err(SEALED_SEND_RATE_LIMITED.to_string());
+        }
+
+        devices::Entity::find_by_id(req.recipient_device_id)
+            .filter(devices::Column::UserId.eq(req.recipient_id))
+            .filter(devices::Column::IsActive.eq(true))
+            .one(db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("Device not found")?;
+
+        let delivery = sealed_deliveries::ActiveModel {
+            device_id: Set(req.recipient_device_id),
+            content: Set(req.content),
+            created_at: Set(Utc::now().into()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(delivery.into())
+    }
+}
+
+pub struct SyncSealedMessagesUseCase;
+
+impl SyncSealedMessagesUseCase {
+    /// One page of sealed messages still queued for `device_id`, oldest first.
+    ///
+    /// As with `SyncMessagesUseCase`, `after_delivery_id` only skips rows returned earlier
+    /// in the same paging session: delivery ids are assigned before the insert commits, so
+    /// a request without one starts over from everything not yet acked.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+        after_delivery_id: Option<i64>,
+        limit: u64,
+    ) -> Result<SealedMessagesResponse, String> {
+        let mut query = sealed_deliveries::Entity::find()
+            .filter(sealed_deliveries::Column::DeviceId.eq(device_id));
+
+        if let Some(after) = after_delivery_id {
+            query = query.filter(sealed_deliveries::Column::DeliveryId.gt(after));
+        }
+
+        let limit = limit.clamp(1, MAX_SEALED_MESSAGES_PAGE);
+        let mut deliveries = query
+            .order_by_asc(sealed_deliveries::Column::DeliveryId)
+            .limit(limit + 1)
+            .all(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let has_more = deliveries.len() as u64 > limit;
+        deliveries.truncate(limit as usize);
+
+        Ok(SealedMessagesResponse {
+            messages: deliveries.into_iter().map(Into::into).collect(),
+            has_more,
+        })
+    }
+}
+
+pub struct AckSealedMessagesUseCase;
+
+impl AckSealedMessagesUseCase {
+    /// Drop delivered envelopes; sealed messages are not kept once the device has them.
+    pub async fn execute(
+        db: &DatabaseConnection,
+        device_id: i64,
+        delivery_ids: Vec<i64>,
+    ) -> Result<u64, String> {
+        if delivery_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let result = sealed_deliveries::Entity::delete_many()
+            .filter(sealed_deliveries::Column::DeviceId.eq(device_id))
+            .filter(sealed_deliveries::Column::DeliveryId.is_in(delivery_ids))
+            .exec(db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(result.rows_affected)
+    }
+}
+
+impl From<sealed_deliveries::Model> for SealedMessageDto {
+    fn from(delivery: sealed_deliveries::Model) -> Self {
+        Self {
+            delivery_id: delivery.delivery_id,
+            content: delivery.content,
+            received_at: delivery.created_at.timestamp(),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
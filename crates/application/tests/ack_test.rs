@@ -0,0 +1,236 @@
+mod common;
+
+use application::chat::conversations::CreateConversationUseCase;
+use application::chat::dtos::{
+    ConversationType, CreateConversationRequest, DeliveryStatusType, SendGroupMessageRequest,
+    SendMessageRequest,
+};
+use application::chat::update_status::{AckMessageUseCase, UpdateDeliveryStatusUseCase};
+use application::chat::use_cases::{SendGroupMessageUseCase, SendMessageUseCase};
+use chat_core::entities::{message_deliveries, messages};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use uuid::Uuid;
+
+async fn stored_ciphertext(db: &DatabaseConnection, message_id: i64) -> Option<Vec<u8>> {
+    messages::Entity::find_by_id(message_id)
+        .one(db)
+        .await
+        .unwrap()
+        .unwrap()
+        .ciphertext
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn acked_messages_leave_the_offline_queue() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let sent = SendMessageUseCase::execute(
+        &db,
+        SendMessageRequest {
+            sender_id: alice,
+            sender_device_id: alice_device,
+            recipient_id: bob,
+            recipient_device_id: bob_device,
+            conversation_id: conv_id,
+            client_message_id: Uuid::new_v4(),
+            content: b"ciphertext".to_vec(),
+        },
+    )
+    .await
+    .unwrap();
+
+    // Acks from other devices do not touch this device's queue
+    AckMessageUseCase::execute(&db, alice_device, sent.message_id)
+        .await
+        .unwrap();
+    assert_eq!(common::queued_messages(&db, bob, bob_device).await.len(), 1);
+
+    AckMessageUseCase::execute(&db, bob_device, sent.message_id)
+        .await
+        .unwrap();
+    assert!(common::queued_messages(&db, bob, bob_device)
+        .await
+        .is_empty());
+    let delivery = message_deliveries::Entity::find_by_id((sent.message_id, bob_device))
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(delivery.delivered_at.is_some());
+    assert_eq!(delivery.content, None);
+
+    // Acking twice is harmless
+    AckMessageUseCase::execute(&db, bob_device, sent.message_id)
+        .await
+        .unwrap();
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn group_ciphertext_is_dropped_once_every_device_acked() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let carol_device = common::seed_device(&db, carol, vec![3; 32]).await;
+
+    let group = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        CreateConversationRequest {
+            conv_type: ConversationType::Group,
+            name: Some("Team".to_string()),
+            avatar: None,
+            member_ids: vec![bob, carol],
+        },
+    )
+    .await
+    .unwrap();
+    let conv_id = group.conversation_id;
+
+    let fanout = SendGroupMessageUseCase::execute(
+        &db,
+        SendGroupMessageRequest {
+            sender_id: alice,
+            sender_device_id: alice_device,
+            conversation_id: conv_id,
+            client_message_id: Uuid::new_v4(),
+            sender_key_epoch: group.sender_key_epoch,
+            content: b"sender key ciphertext".to_vec(),
+            distributions: Vec::new(),
+        },
+    )
+    .await
+    .unwrap();
+
+    AckMessageUseCase::execute(&db, bob_device, fanout.message_id)
+        .await
+        .unwrap();
+    assert!(stored_ciphertext(&db, fanout.message_id).await.is_some());
+    // Carol has not acked yet and still receives it
+    assert_eq!(
+        common::queued_messages(&db, carol, carol_device)
+            .await
+            .len(),
+        1
+    );
+
+    AckMessageUseCase::execute(&db, carol_device, fanout.message_id)
+        .await
+        .unwrap();
+    assert_eq!(stored_ciphertext(&db, fanout.message_id).await, None);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, carol]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn delivered_receipts_clear_the_queue_like_acks() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let carol_device = common::seed_device(&db, carol, vec![3; 32]).await;
+
+    let group = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        CreateConversationRequest {
+            conv_type: ConversationType::Group,
+            name: Some("Team".to_string()),
+            avatar: None,
+            member_ids: vec![bob, carol],
+        },
+    )
+    .await
+    .unwrap();
+    let conv_id = group.conversation_id;
+
+    let fanout = SendGroupMessageUseCase::execute(
+        &db,
+        SendGroupMessageRequest {
+            sender_id: alice,
+            sender_device_id: alice_device,
+            conversation_id: conv_id,
+            client_message_id: Uuid::new_v4(),
+            sender_key_epoch: group.sender_key_epoch,
+            content: b"sender key ciphertext".to_vec(),
+            distributions: Vec::new(),
+        },
+    )
+    .await
+    .unwrap();
+
+    UpdateDeliveryStatusUseCase::execute(
+        &db,
+        bob,
+        bob_device,
+        conv_id,
+        alice,
+        fanout.message_id,
+        DeliveryStatusType::Delivered,
+    )
+    .await
+    .unwrap();
+    assert!(common::queued_messages(&db, bob, bob_device)
+        .await
+        .is_empty());
+    let delivery = message_deliveries::Entity::find_by_id((fanout.message_id, bob_device))
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(delivery.delivered_at.is_some());
+    assert_eq!(delivery.content, None);
+    assert!(stored_ciphertext(&db, fanout.message_id).await.is_some());
+
+    // Either path completes the message once it is the last pending delivery
+    AckMessageUseCase::execute(&db, carol_device, fanout.message_id)
+        .await
+        .unwrap();
+    assert_eq!(stored_ciphertext(&db, fanout.message_id).await, None);
+
+    let second = SendGroupMessageUseCase::execute(
+        &db,
+        SendGroupMessageRequest {
+            sender_id: alice,
+            sender_device_id: alice_device,
+            conversation_id: conv_id,
+            client_message_id: Uuid::new_v4(),
+            sender_key_epoch: group.sender_key_epoch,
+            content: b"sender key ciphertext".to_vec(),
+            distributions: Vec::new(),
+        },
+    )
+    .await
+    .unwrap();
+    AckMessageUseCase::execute(&db, carol_device, second.message_id)
+        .await
+        .unwrap();
+    UpdateDeliveryStatusUseCase::execute(
+        &db,
+        bob,
+        bob_device,
+        conv_id,
+        alice,
+        second.message_id,
+        DeliveryStatusType::Delivered,
+    )
+    .await
+    .unwrap();
+    assert_eq!(stored_ciphertext(&db, second.message_id).await, None);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, carol]).await;
+}
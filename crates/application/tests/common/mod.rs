@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+
+use application::chat::dtos::SyncMessageDto;
+use application::chat::sync_messages::{SyncMessagesUseCase, MAX_SYNC_PAGE};
+use application::keys::dtos::{DeviceKeysDto, PreKeyDto, SignedPreKeyDto};
+use chat_core::entities::{conv_members, conversations, devices, messages, users};
+use chrono::Utc;
+use infrastructure::crypto::signal;
+use infrastructure::redis::RedisClient;
+use redis::aio::MultiplexedConnection;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Database, DatabaseConnection, EntityTrait, QueryFilter, Set,
+};
+use uuid::Uuid;
+
+pub async fn connect() -> DatabaseConnection {
+    dotenvy::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    Database::connect(database_url)
+        .await
+        .expect("Failed to connect DB")
+}
+
+pub async fn connect_redis() -> RedisClient {
+    RedisClient::new(connect_redis_raw().await)
+}
+
+pub async fn connect_redis_raw() -> MultiplexedConnection {
+    dotenvy::dotenv().ok();
+    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+    infrastructure::database::init_redis(&redis_url)
+        .await
+        .expect("Failed to connect Redis")
+}
+
+/// Key bundle as a client would build it: private halves stay on the device.
+pub fn client_device_keys() -> DeviceKeysDto {
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let signed_prekey = signal::generate_signed_prekey(&identity, 1).unwrap();
+
+    DeviceKeysDto {
+        identity_key: identity.public_key,
+        registration_id: signal::generate_registration_id().max(1) as i32,
+        signed_prekey: SignedPreKeyDto {
+            id: signed_prekey.id as i32,
+            key: signed_prekey.public_key,
+            signature: signed_prekey.signature,
+        },
+        one_time_prekeys: signal::generate_prekeys(1, 10)
+            .unwrap()
+            .into_iter()
+            .map(|pk| PreKeyDto {
+                id: pk.id as i32,
+                key: pk.public_key,
+            })
+            .collect(),
+    }
+}
+
+/// Create a throwaway user; deleting it cascades to everything seeded below.
+pub async fn seed_user(db: &DatabaseConnection) -> Uuid {
+    let user_id = Uuid::new_v4();
+    users::ActiveModel {
+        user_id: Set(user_id),
+        phone_number: Set(format!("+990{:012}", user_id.as_u128() % 1_000_000_000_000)),
+        phone_number_hash: Set(user_id.as_bytes().to_vec()),
+        username: Set(None),
+        display_name: Set(None),
+        bio: Set(None),
+        profile_picture: Set(None),
+        last_seen_at: Set(None),
+        is_online: Set(false),
+        last_seen_visibility: Set(0),
+        is_deleted: Set(false),
+        deleted_at: Set(None),
+        created_at: Set(Utc::now().into()),
+        updated_at: Set(Utc::now().into()),
+        pin_hash: Set(None),
+        registration_lock: Set(false),
+        registration_lock_expires_at: Set(None),
+        pin_set_at: Set(None),
+    }
+    .insert(db)
+    .await
+    .expect("Failed to create user");
+    user_id
+}
+
+pub async fn seed_device(db: &DatabaseConnection, user_id: Uuid, identity_key: Vec<u8>) -> i64 {
+    devices::ActiveModel {
+        user_id: Set(user_id),
+        device_uuid: Set(Uuid::new_v4()),
+        device_name: Set(Some("Test Device".to_string())),
+        platform: Set(1),
+        identity_key_public: Set(identity_key),
+        registration_id: Set(1),
+        signed_prekey_id: Set(1),
+        signed_prekey_public: Set(vec![2; 32]),
+        signed_prekey_signature: Set(vec![3; 64]),
+        last_seen_at: Set(Utc::now().into()),
+        created_at: Set(Utc::now().into()),
+        device_type: Set(1),
+        is_active: Set(true),
+        linked_at: Set(None),
+        linked_by_device_id: Set(None),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .expect("Failed to create device")
+    .device_id
+}
+
+pub async fn seed_conversation(db: &DatabaseConnection, members: &[Uuid]) -> Uuid {
+    let conv_id = Uuid::new_v4();
+    conversations::ActiveModel {
+        conv_id: Set(conv_id),
+        conv_type: Set(1),
+        name: Set(None),
+        avatar: Set(None),
+        created_at: Set(Utc::now().into()),
+        creator_id: Set(members.first().copied()),
+        metadata: Set(serde_json::json!({})),
+        sender_key_epoch: Set(0),
+    }
+    .insert(db)
+    .await
+    .expect("Failed to create conversation");
+
+    for user_id in members {
+        conv_members::ActiveModel {
+            conv_id: Set(conv_id),
+            user_id: Set(*user_id),
+            role: Set(0),
+            joined_at: Set(Utc::now().into()),
+            left_at: Set(None),
+        }
+        .insert(db)
+        .await
+        .expect("Failed to add member");
+    }
+    conv_id
+}
+
+/// The first page of `device_id`'s queue after its checkpoint.
+pub async fn queued_messages(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    device_id: i64,
+) -> Vec<SyncMessageDto> {
+    SyncMessagesUseCase::execute(db, user_id, device_id, None, MAX_SYNC_PAGE)
+        .await
+        .expect("Failed to sync messages")
+        .messages
+}
+
+pub async fn cleanup(db: &DatabaseConnection, conversations: &[Uuid], users: &[Uuid]) {
+    for conv_id in conversations {
+        // Messages do not cascade with their conversation
+        messages::Entity::delete_many()
+            .filter(messages::Column::ConvId.eq(*conv_id))
+            .exec(db)
+            .await
+            .expect("Failed to clean up messages");
+        conversations::Entity::delete_by_id(*conv_id)
+            .exec(db)
+            .await
+            .expect("Failed to clean up conversation");
+    }
+    for user_id in users {
+        users::Entity::delete_by_id(*user_id)
+            .exec(db)
+            .await
+            .expect("Failed to clean up user");
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use application::identity::dtos::VerifyContactRequest;
+use application::identity::use_cases::{GetSafetyNumberUseCase, VerifyContactUseCase};
+use chat_core::signal::fingerprint::fingerprint;
+
+fn by_number(safety_number: &str) -> VerifyContactRequest {
+    VerifyContactRequest {
+        safety_number: Some(safety_number.to_string()),
+        scanned: None,
+    }
+}
+
+fn by_scan(scanned: Vec<u8>) -> VerifyContactRequest {
+    VerifyContactRequest {
+        safety_number: None,
+        scanned: Some(scanned),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn only_conversation_peers_can_be_verified() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+    common::seed_device(&db, alice, vec![1; 32]).await;
+    common::seed_device(&db, bob, vec![2; 32]).await;
+    common::seed_device(&db, carol, vec![3; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    // Even with the right safety number, a stranger can't be marked verified
+    let with_carol = fingerprint(
+        alice.as_bytes(),
+        &[vec![1; 32]],
+        carol.as_bytes(),
+        &[vec![3; 32]],
+    );
+    assert_eq!(
+        VerifyContactUseCase::execute(&db, alice, carol, by_number(&with_carol.safety_number))
+            .await
+            .unwrap_err(),
+        "Contact not found"
+    );
+
+    let with_bob = GetSafetyNumberUseCase::execute(&db, alice, conv_id, bob)
+        .await
+        .unwrap();
+    assert!(!with_bob.verified);
+    assert_eq!(
+        VerifyContactUseCase::execute(&db, alice, bob, by_number(&"0".repeat(60)))
+            .await
+            .unwrap_err(),
+        "Safety number does not match"
+    );
+    VerifyContactUseCase::execute(&db, alice, bob, by_number(&with_bob.safety_number))
+        .await
+        .unwrap();
+    assert!(
+        GetSafetyNumberUseCase::execute(&db, alice, conv_id, bob)
+            .await
+            .unwrap()
+            .verified
+    );
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, carol]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn scanning_the_peers_code_verifies_them() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    common::seed_device(&db, alice, vec![1; 32]).await;
+    common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let alice_screen = GetSafetyNumberUseCase::execute(&db, alice, conv_id, bob)
+        .await
+        .unwrap();
+    let bob_screen = GetSafetyNumberUseCase::execute(&db, bob, conv_id, alice)
+        .await
+        .unwrap();
+
+    // Alice's own code is not what she would see on Bob's screen
+    assert_eq!(
+        VerifyContactUseCase::execute(&db, alice, bob, by_scan(alice_screen.scannable.clone()))
+            .await
+            .unwrap_err(),
+        "Safety number does not match"
+    );
+    assert!(
+        VerifyContactUseCase::execute(&db, alice, bob, by_scan(vec![0; 10]))
+            .await
+            .unwrap_err()
+            .starts_with("Invalid scanned code")
+    );
+    assert!(VerifyContactUseCase::execute(
+        &db,
+        alice,
+        bob,
+        VerifyContactRequest {
+            safety_number: None,
+            scanned: None,
+        },
+    )
+    .await
+    .unwrap_err()
+    .starts_with("Cannot"));
+
+    VerifyContactUseCase::execute(&db, alice, bob, by_scan(bob_screen.scannable))
+        .await
+        .unwrap();
+    assert!(
+        GetSafetyNumberUseCase::execute(&db, alice, conv_id, bob)
+            .await
+            .unwrap()
+            .verified
+    );
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use application::chat::conversations::{
+    CreateConversationUseCase, GetConversationUseCase, LeaveConversationUseCase,
+    ListConversationsUseCase,
+};
+use application::chat::dtos::{ConversationType, CreateConversationRequest};
+use uuid::Uuid;
+
+fn request(
+    conv_type: ConversationType,
+    name: Option<&str>,
+    members: &[Uuid],
+) -> CreateConversationRequest {
+    CreateConversationRequest {
+        conv_type,
+        name: name.map(str::to_string),
+        avatar: None,
+        member_ids: members.to_vec(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn conversation_lifecycle() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+
+    let direct = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        request(ConversationType::Direct, None, &[bob]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(direct.members.len(), 2);
+
+    // The same pair gets the same direct conversation back, from either side
+    let again = CreateConversationUseCase::execute(
+        &db,
+        bob,
+        request(ConversationType::Direct, None, &[alice]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(again.conversation_id, direct.conversation_id);
+
+    assert!(CreateConversationUseCase::execute(
+        &db,
+        alice,
+        request(ConversationType::Direct, None, &[bob, carol])
+    )
+    .await
+    .is_err());
+    assert!(CreateConversationUseCase::execute(
+        &db,
+        alice,
+        request(ConversationType::Group, None, &[bob, carol])
+    )
+    .await
+    .is_err());
+
+    let group = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        request(ConversationType::Group, Some("Friends"), &[bob, carol]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(group.members.len(), 3);
+    assert_eq!(group.creator_id, Some(alice));
+
+    let listed = ListConversationsUseCase::execute(&db, carol).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].conversation_id, group.conversation_id);
+    assert!(listed[0].last_message.is_none());
+
+    assert!(
+        GetConversationUseCase::execute(&db, carol, direct.conversation_id)
+            .await
+            .is_err()
+    );
+
+    LeaveConversationUseCase::execute(&db, carol, group.conversation_id)
+        .await
+        .unwrap();
+    assert!(ListConversationsUseCase::execute(&db, carol)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(
+        LeaveConversationUseCase::execute(&db, carol, group.conversation_id)
+            .await
+            .is_err()
+    );
+    let group = GetConversationUseCase::execute(&db, alice, group.conversation_id)
+        .await
+        .unwrap();
+    assert_eq!(group.members.len(), 2);
+
+    common::cleanup(
+        &db,
+        &[direct.conversation_id, group.conversation_id],
+        &[alice, bob, carol],
+    )
+    .await;
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use application::chat::conversations::{CreateConversationUseCase, LeaveConversationUseCase};
+use application::chat::dtos::{
+    ConversationType, CreateConversationRequest, SendGroupMessageRequest, SenderKeyDistributionDto,
+};
+use application::chat::groups::RemoveMemberUseCase;
+use application::chat::use_cases::{SendGroupMessageUseCase, STALE_SENDER_KEY};
+use uuid::Uuid;
+
+fn group_message(
+    sender_id: Uuid,
+    sender_device_id: i64,
+    conversation_id: Uuid,
+    sender_key_epoch: i64,
+    distributions: Vec<SenderKeyDistributionDto>,
+) -> SendGroupMessageRequest {
+    SendGroupMessageRequest {
+        sender_id,
+        sender_device_id,
+        conversation_id,
+        client_message_id: Uuid::new_v4(),
+        sender_key_epoch,
+        content: b"sender key ciphertext".to_vec(),
+        distributions,
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn group_message_is_stored_once_and_fanned_out_per_device() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+    let alice_phone = common::seed_device(&db, alice, vec![1; 32]).await;
+    let alice_laptop = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let carol_device = common::seed_device(&db, carol, vec![3; 32]).await;
+
+    let group = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        CreateConversationRequest {
+            conv_type: ConversationType::Group,
+            name: Some("Team".to_string()),
+            avatar: None,
+            member_ids: vec![bob, carol],
+        },
+    )
+    .await
+    .unwrap();
+    let conv_id = group.conversation_id;
+    assert_eq!(group.sender_key_epoch, 0);
+
+    let distribution = SenderKeyDistributionDto {
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        content: b"distribution for bob".to_vec(),
+    };
+    let fanout = SendGroupMessageUseCase::execute(
+        &db,
+        group_message(alice, alice_phone, conv_id, 0, vec![distribution]),
+    )
+    .await
+    .unwrap();
+
+    // Every other device of every member, including the sender's own laptop
+    let mut targets: Vec<i64> = fanout
+        .deliveries
+        .iter()
+        .map(|d| d.recipient_device_id)
+        .collect();
+    targets.sort();
+    let mut expected = vec![alice_laptop, bob_device, carol_device];
+    expected.sort();
+    assert_eq!(targets, expected);
+
+    let synced = common::queued_messages(&db, bob, bob_device).await;
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].message_id, fanout.message_id);
+    assert_eq!(synced[0].content, b"sender key ciphertext".to_vec());
+    assert_eq!(
+        synced[0].sender_key_distribution,
+        Some(b"distribution for bob".to_vec())
+    );
+    let synced = common::queued_messages(&db, carol, carol_device).await;
+    assert_eq!(synced[0].content, b"sender key ciphertext".to_vec());
+    assert_eq!(synced[0].sender_key_distribution, None);
+
+    // Distributions may only target devices in the conversation
+    let stranger = SenderKeyDistributionDto {
+        recipient_id: Uuid::new_v4(),
+        recipient_device_id: bob_device,
+        content: vec![0],
+    };
+    let err = SendGroupMessageUseCase::execute(
+        &db,
+        group_message(alice, alice_phone, conv_id, 0, vec![stranger]),
+    )
+    .await
+    .unwrap_err();
+    assert!(err.starts_with("Invalid distribution"));
+
+    // Once a member is gone, keys they held are stale and they receive nothing further
+    RemoveMemberUseCase::execute(&db, alice, conv_id, carol)
+        .await
+        .unwrap();
+    let err = SendGroupMessageUseCase::execute(
+        &db,
+        group_message(alice, alice_phone, conv_id, 0, Vec::new()),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, STALE_SENDER_KEY);
+
+    let fanout = SendGroupMessageUseCase::execute(
+        &db,
+        group_message(alice, alice_phone, conv_id, 1, Vec::new()),
+    )
+    .await
+    .unwrap();
+    assert!(fanout
+        .deliveries
+        .iter()
+        .all(|d| d.recipient_device_id != carol_device));
+
+    LeaveConversationUseCase::execute(&db, bob, conv_id)
+        .await
+        .unwrap();
+    let err = SendGroupMessageUseCase::execute(
+        &db,
+        group_message(alice, alice_phone, conv_id, 1, Vec::new()),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, STALE_SENDER_KEY);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, carol]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn another_member_reusing_a_client_message_id_sends_a_new_message() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+
+    let group = CreateConversationUseCase::execute(
+        &db,
+        alice,
+        CreateConversationRequest {
+            conv_type: ConversationType::Group,
+            name: Some("Team".to_string()),
+            avatar: None,
+            member_ids: vec![bob],
+        },
+    )
+    .await
+    .unwrap();
+    let conv_id = group.conversation_id;
+
+    let first = group_message(alice, alice_device, conv_id, 0, Vec::new());
+    let client_message_id = first.client_message_id;
+    let sent = SendGroupMessageUseCase::execute(&db, first).await.unwrap();
+    let retried = SendGroupMessageUseCase::execute(
+        &db,
+        SendGroupMessageRequest {
+            client_message_id,
+            ..group_message(alice, alice_device, conv_id, 0, Vec::new())
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(retried.message_id, sent.message_id);
+    assert!(retried.deliveries.is_empty());
+
+    let reply = SendGroupMessageUseCase::execute(
+        &db,
+        SendGroupMessageRequest {
+            client_message_id,
+            ..group_message(bob, bob_device, conv_id, 0, Vec::new())
+        },
+    )
+    .await
+    .unwrap();
+    assert_ne!(reply.message_id, sent.message_id);
+    assert_eq!(reply.deliveries.len(), 1);
+    let synced = common::queued_messages(&db, alice, alice_device).await;
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].sender_id, bob);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
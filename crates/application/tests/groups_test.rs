@@ -0,0 +1,212 @@
+mod common;
+
+use application::chat::conversations::{
+    CreateConversationUseCase, GetConversationUseCase, LeaveConversationUseCase,
+};
+use application::chat::dtos::{
+    ConversationDto, ConversationType, CreateConversationRequest, CreateInviteRequest, MemberRole,
+    MembershipAction, UpdateGroupRequest,
+};
+use application::chat::groups::{
+    AddMembersUseCase, RemoveMemberUseCase, SetMemberRoleUseCase, UpdateGroupUseCase,
+    PERMISSION_DENIED,
+};
+use application::chat::invites::{
+    CreateInviteUseCase, JoinViaInviteUseCase, RevokeInviteUseCase, INVITE_INVALID,
+};
+use sea_orm::DatabaseConnection;
+use uuid::Uuid;
+
+async fn create_group(db: &DatabaseConnection, owner: Uuid, members: &[Uuid]) -> ConversationDto {
+    CreateConversationUseCase::execute(
+        db,
+        owner,
+        CreateConversationRequest {
+            conv_type: ConversationType::Group,
+            name: Some("Team".to_string()),
+            avatar: None,
+            member_ids: members.to_vec(),
+        },
+    )
+    .await
+    .unwrap()
+}
+
+fn role_of(conversation: &ConversationDto, user_id: Uuid) -> Option<MemberRole> {
+    conversation
+        .members
+        .iter()
+        .find(|m| m.user_id == user_id)
+        .map(|m| m.role)
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn admins_manage_members_and_owner_hands_over_on_leave() {
+    let db = common::connect().await;
+    let owner = common::seed_user(&db).await;
+    let admin = common::seed_user(&db).await;
+    let member = common::seed_user(&db).await;
+    let newcomer = common::seed_user(&db).await;
+
+    let group = create_group(&db, owner, &[admin, member]).await;
+    let conv_id = group.conversation_id;
+    assert_eq!(role_of(&group, owner), Some(MemberRole::Owner));
+
+    // Plain members can do nothing administrative
+    let err = AddMembersUseCase::execute(&db, member, conv_id, vec![newcomer])
+        .await
+        .unwrap_err();
+    assert_eq!(err, PERMISSION_DENIED);
+
+    let change = SetMemberRoleUseCase::execute(&db, owner, conv_id, admin, MemberRole::Admin)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(change.action, MembershipAction::RoleChanged);
+    assert_eq!(change.recipients.len(), 3);
+    // Granting a role the member already has changes nothing and announces nothing
+    let change = SetMemberRoleUseCase::execute(&db, owner, conv_id, admin, MemberRole::Admin)
+        .await
+        .unwrap();
+    assert!(change.is_none());
+    let change = SetMemberRoleUseCase::execute(&db, owner, conv_id, member, MemberRole::Member)
+        .await
+        .unwrap();
+    assert!(change.is_none());
+
+    let change = AddMembersUseCase::execute(&db, admin, conv_id, vec![newcomer])
+        .await
+        .unwrap();
+    assert_eq!(change.user_ids, vec![newcomer]);
+    assert!(change.recipients.contains(&newcomer));
+
+    // Admins cannot touch the owner or demote each other
+    assert_eq!(
+        RemoveMemberUseCase::execute(&db, admin, conv_id, owner)
+            .await
+            .unwrap_err(),
+        PERMISSION_DENIED
+    );
+    SetMemberRoleUseCase::execute(&db, admin, conv_id, member, MemberRole::Admin)
+        .await
+        .unwrap();
+    assert_eq!(
+        SetMemberRoleUseCase::execute(&db, admin, conv_id, member, MemberRole::Member)
+            .await
+            .unwrap_err(),
+        PERMISSION_DENIED
+    );
+
+    let change = RemoveMemberUseCase::execute(&db, admin, conv_id, newcomer)
+        .await
+        .unwrap();
+    assert_eq!(change.action, MembershipAction::Removed);
+    assert!(change.recipients.contains(&newcomer));
+
+    let renamed = UpdateGroupUseCase::execute(
+        &db,
+        admin,
+        conv_id,
+        UpdateGroupRequest {
+            name: Some("Renamed".to_string()),
+            avatar: Some("https://example.com/a.png".to_string()),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(renamed.name.as_deref(), Some("Renamed"));
+
+    let changes = LeaveConversationUseCase::execute(&db, owner, conv_id)
+        .await
+        .unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[1].user_ids, vec![admin]);
+    let group = GetConversationUseCase::execute(&db, admin, conv_id)
+        .await
+        .unwrap();
+    assert_eq!(role_of(&group, admin), Some(MemberRole::Owner));
+
+    common::cleanup(&db, &[conv_id], &[owner, admin, member, newcomer]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn invite_links_respect_cap_and_revocation() {
+    let db = common::connect().await;
+    let owner = common::seed_user(&db).await;
+    let member = common::seed_user(&db).await;
+    let first = common::seed_user(&db).await;
+    let second = common::seed_user(&db).await;
+
+    let conv_id = create_group(&db, owner, &[member]).await.conversation_id;
+
+    assert_eq!(
+        CreateInviteUseCase::execute(
+            &db,
+            member,
+            conv_id,
+            CreateInviteRequest {
+                expires_in_seconds: None,
+                member_cap: None,
+            },
+        )
+        .await
+        .unwrap_err(),
+        PERMISSION_DENIED
+    );
+
+    let invite = CreateInviteUseCase::execute(
+        &db,
+        owner,
+        conv_id,
+        CreateInviteRequest {
+            expires_in_seconds: Some(3600),
+            member_cap: Some(1),
+        },
+    )
+    .await
+    .unwrap();
+
+    let joined = JoinViaInviteUseCase::execute(&db, first, &invite.token)
+        .await
+        .unwrap();
+    assert_eq!(joined.conversation.members.len(), 3);
+    assert_eq!(joined.change.unwrap().action, MembershipAction::Joined);
+
+    // Following the link again is a no-op, and does not use up the cap
+    let again = JoinViaInviteUseCase::execute(&db, first, &invite.token)
+        .await
+        .unwrap();
+    assert!(again.change.is_none());
+
+    assert_eq!(
+        JoinViaInviteUseCase::execute(&db, second, &invite.token)
+            .await
+            .unwrap_err(),
+        INVITE_INVALID
+    );
+
+    let open = CreateInviteUseCase::execute(
+        &db,
+        owner,
+        conv_id,
+        CreateInviteRequest {
+            expires_in_seconds: None,
+            member_cap: None,
+        },
+    )
+    .await
+    .unwrap();
+    RevokeInviteUseCase::execute(&db, owner, conv_id, &open.token)
+        .await
+        .unwrap();
+    assert_eq!(
+        JoinViaInviteUseCase::execute(&db, second, &open.token)
+            .await
+            .unwrap_err(),
+        INVITE_INVALID
+    );
+
+    common::cleanup(&db, &[conv_id], &[owner, member, first, second]).await;
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use application::auth::dtos::{ApproveLinkingRequest, CompleteLinkingRequest, VerifyOtpRequest};
+use application::auth::use_cases::{
+    ApproveLinkingUseCase, AuthConfig, CompleteLinkingUseCase, CreateLinkingSessionUseCase,
+    UnlinkDeviceUseCase, VerifyOtpUseCase,
+};
+use application::identity::use_cases::{
+    GetIdentityChangeNoticeUseCase, SyncIdentityChangesUseCase,
+};
+use application::keys::dtos::DeviceKeysDto;
+use chat_core::entities::{identity_key_changes, users};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+
+fn auth_config() -> AuthConfig {
+    AuthConfig {
+        jwt_secret: "test-secret".to_string(),
+        jwt_expiration: 3600,
+        refresh_token_expiration: 2592000,
+    }
+}
+
+/// Register `phone_number` as a new primary device, as the OTP endpoint would.
+async fn register(
+    db: &DatabaseConnection,
+    redis: &mut MultiplexedConnection,
+    phone_number: &str,
+    keys: DeviceKeysDto,
+) -> application::auth::dtos::VerifyOtpResponse {
+    redis
+        .set_ex::<_, _, ()>(format!("otp:{}", phone_number), "123456", 60)
+        .await
+        .unwrap();
+    VerifyOtpUseCase::execute(
+        db,
+        redis,
+        &auth_config(),
+        VerifyOtpRequest {
+            phone_number: phone_number.to_string(),
+            otp: "123456".to_string(),
+            device_uuid: Uuid::new_v4(),
+            device_name: None,
+            platform: Some(1),
+            keys,
+        },
+    )
+    .await
+    .unwrap()
+}
+
+async fn recorded_changes(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+) -> Vec<identity_key_changes::Model> {
+    identity_key_changes::Entity::find()
+        .filter(identity_key_changes::Column::UserId.eq(user_id))
+        .order_by_asc(identity_key_changes::Column::ChangeId)
+        .all(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn identity_changes_are_recorded_whenever_active_keys_change() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis_raw().await;
+    let alice = common::seed_user(&db).await;
+    let phone_number = users::Entity::find_by_id(alice)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap()
+        .phone_number;
+
+    // A first device is not a change: nobody can have verified the user yet
+    let original_keys = common::client_device_keys();
+    let first = register(&db, &mut redis, &phone_number, original_keys.clone()).await;
+    assert!(!first.identity_changed);
+
+    let bob = common::seed_user(&db).await;
+    let stranger = common::seed_user(&db).await;
+    common::seed_device(&db, bob, vec![7; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    // Re-registering under the same identity key leaves the safety number alone
+    let same = register(&db, &mut redis, &phone_number, original_keys).await;
+    assert!(!same.identity_changed);
+    assert!(recorded_changes(&db, alice).await.is_empty());
+
+    let new_keys = common::client_device_keys();
+    let reinstalled = register(&db, &mut redis, &phone_number, new_keys.clone()).await;
+    assert!(reinstalled.identity_changed);
+    let changes = recorded_changes(&db, alice).await;
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].device_id, reinstalled.device_id);
+    assert_eq!(changes[0].identity_key, new_keys.identity_key);
+
+    // Linking a device brings its own identity key into the set
+    let session = CreateLinkingSessionUseCase::execute(&db, reinstalled.device_id)
+        .await
+        .unwrap();
+    let linked_keys = common::client_device_keys();
+    CompleteLinkingUseCase::execute(
+        &db,
+        CompleteLinkingRequest {
+            qr_code_token: session.qr_code_token,
+            device_uuid: Uuid::new_v4(),
+            device_name: None,
+            platform: Some(3),
+            keys: linked_keys.clone(),
+        },
+    )
+    .await
+    .unwrap();
+    let approved = ApproveLinkingUseCase::execute(
+        &db,
+        reinstalled.device_id,
+        ApproveLinkingRequest {
+            session_id: session.session_id,
+            approve: true,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(approved.identity_changed);
+    let linked_device = approved.new_device_id.unwrap();
+    let changes = recorded_changes(&db, alice).await;
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[1].device_id, linked_device);
+    assert_eq!(changes[1].identity_key, linked_keys.identity_key);
+
+    // ...and unlinking it takes the key out again
+    let unlinked = UnlinkDeviceUseCase::execute(&db, alice, reinstalled.device_id, linked_device)
+        .await
+        .unwrap();
+    assert!(unlinked.identity_changed);
+    let changes = recorded_changes(&db, alice).await;
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes[2].device_id, linked_device);
+
+    let notice = GetIdentityChangeNoticeUseCase::execute(&db, alice)
+        .await
+        .unwrap()
+        .expect("Alice has identity changes");
+    assert_eq!(notice.change.change_id, changes[2].change_id);
+    assert_eq!(notice.recipients, vec![bob]);
+
+    let synced = SyncIdentityChangesUseCase::execute(&db, bob, None, 100)
+        .await
+        .unwrap();
+    assert_eq!(
+        synced.iter().map(|c| c.change_id).collect::<Vec<_>>(),
+        changes.iter().map(|c| c.change_id).collect::<Vec<_>>()
+    );
+
+    let after = SyncIdentityChangesUseCase::execute(&db, bob, Some(changes[2].change_id), 100)
+        .await
+        .unwrap();
+    assert!(after.is_empty());
+
+    let unrelated = SyncIdentityChangesUseCase::execute(&db, stranger, None, 100)
+        .await
+        .unwrap();
+    assert!(unrelated.is_empty());
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, stranger]).await;
+}
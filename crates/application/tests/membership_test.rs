@@ -0,0 +1,117 @@
+mod common;
+
+use application::chat::conversations::{ensure_members, LeaveConversationUseCase, NOT_A_MEMBER};
+use application::chat::dtos::{DeliveryStatusType, SendMessageRequest};
+use application::chat::update_status::UpdateDeliveryStatusUseCase;
+use application::chat::use_cases::SendMessageUseCase;
+use chat_core::entities::messages;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use uuid::Uuid;
+
+fn message(
+    sender: (Uuid, i64),
+    recipient: (Uuid, i64),
+    conversation_id: Uuid,
+) -> SendMessageRequest {
+    SendMessageRequest {
+        sender_id: sender.0,
+        sender_device_id: sender.1,
+        recipient_id: recipient.0,
+        recipient_device_id: recipient.1,
+        conversation_id,
+        client_message_id: Uuid::new_v4(),
+        content: b"ciphertext".to_vec(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn removed_member_cannot_post_or_receive() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let first = message((alice, alice_device), (bob, bob_device), conv_id);
+    let first_id = first.client_message_id;
+    SendMessageUseCase::execute(&db, first).await.unwrap();
+
+    LeaveConversationUseCase::execute(&db, bob, conv_id)
+        .await
+        .unwrap();
+
+    let err = SendMessageUseCase::execute(
+        &db,
+        message((bob, bob_device), (alice, alice_device), conv_id),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, NOT_A_MEMBER);
+    let err = SendMessageUseCase::execute(
+        &db,
+        message((alice, alice_device), (bob, bob_device), conv_id),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, NOT_A_MEMBER);
+
+    assert_eq!(
+        ensure_members(&db, conv_id, &[alice, bob])
+            .await
+            .unwrap_err(),
+        NOT_A_MEMBER
+    );
+    assert!(ensure_members(&db, conv_id, &[alice]).await.is_ok());
+
+    let delivered = messages::Entity::find()
+        .filter(messages::Column::ClientMessageId.eq(first_id))
+        .one(&db)
+        .await
+        .unwrap()
+        .expect("first message was stored");
+    let err = UpdateDeliveryStatusUseCase::execute(
+        &db,
+        bob,
+        bob_device,
+        conv_id,
+        alice,
+        delivered.message_id,
+        DeliveryStatusType::Read,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, NOT_A_MEMBER);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn outsider_cannot_post_into_conversation() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let mallory = common::seed_user(&db).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let mallory_device = common::seed_device(&db, mallory, vec![3; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let err = SendMessageUseCase::execute(
+        &db,
+        message((mallory, mallory_device), (bob, bob_device), conv_id),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err, NOT_A_MEMBER);
+
+    // A member cannot route to a device that belongs to someone else either
+    assert!(
+        SendMessageUseCase::execute(&db, message((alice, 0), (bob, mallory_device), conv_id))
+            .await
+            .is_err()
+    );
+
+    common::cleanup(&db, &[conv_id], &[alice, bob, mallory]).await;
+}
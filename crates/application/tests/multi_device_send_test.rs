@@ -0,0 +1,141 @@
+mod common;
+
+use application::chat::dtos::{
+    DeviceAddressDto, DeviceMessageDto, MultiDeviceSendOutcome, SendMultiDeviceMessageRequest,
+};
+use application::chat::use_cases::SendMessageUseCase;
+use uuid::Uuid;
+
+fn copy_for(user_id: Uuid, device_id: i64) -> DeviceMessageDto {
+    DeviceMessageDto {
+        recipient_id: user_id,
+        recipient_device_id: device_id,
+        content: format!("for device {device_id}").into_bytes(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn multi_device_send_is_all_or_nothing() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_phone = common::seed_device(&db, alice, vec![1; 32]).await;
+    let alice_laptop = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_phone = common::seed_device(&db, bob, vec![2; 32]).await;
+    let bob_tablet = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let request = |messages: Vec<DeviceMessageDto>| SendMultiDeviceMessageRequest {
+        sender_id: alice,
+        sender_device_id: alice_phone,
+        recipient_id: bob,
+        conversation_id: conv_id,
+        client_message_id: Uuid::new_v4(),
+        messages,
+    };
+
+    // Forgetting the sender's own laptop and addressing the sending device are both reported
+    let outcome = SendMessageUseCase::execute_multi_device(
+        &db,
+        request(vec![
+            copy_for(bob, bob_phone),
+            copy_for(bob, bob_tablet),
+            copy_for(alice, alice_phone),
+        ]),
+    )
+    .await
+    .unwrap();
+    match outcome {
+        MultiDeviceSendOutcome::StaleDevices(stale) => {
+            assert_eq!(
+                stale.missing,
+                vec![DeviceAddressDto {
+                    user_id: alice,
+                    device_id: alice_laptop,
+                }]
+            );
+            assert_eq!(
+                stale.extra,
+                vec![DeviceAddressDto {
+                    user_id: alice,
+                    device_id: alice_phone,
+                }]
+            );
+        }
+        other => panic!("expected stale devices, got {other:?}"),
+    }
+    // Nothing was stored for the rejected send
+    assert!(common::queued_messages(&db, bob, bob_phone)
+        .await
+        .is_empty());
+
+    let outcome = SendMessageUseCase::execute_multi_device(
+        &db,
+        request(vec![
+            copy_for(bob, bob_phone),
+            copy_for(bob, bob_tablet),
+            copy_for(alice, alice_laptop),
+        ]),
+    )
+    .await
+    .unwrap();
+    let MultiDeviceSendOutcome::Sent { sent, deliveries } = outcome else {
+        panic!("expected the send to be stored");
+    };
+    assert_eq!(deliveries.len(), 3);
+
+    for (user_id, device_id) in [(bob, bob_phone), (bob, bob_tablet), (alice, alice_laptop)] {
+        let synced = common::queued_messages(&db, user_id, device_id).await;
+        assert_eq!(synced.len(), 1);
+        assert_eq!(synced[0].message_id, sent.message_id);
+        assert_eq!(synced[0].content, copy_for(user_id, device_id).content);
+    }
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn retries_are_deduplicated_per_sending_device() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let client_message_id = Uuid::new_v4();
+    let send = |sender_id, sender_device_id, recipient_id, recipient_device_id| {
+        SendMessageUseCase::execute_multi_device(
+            &db,
+            SendMultiDeviceMessageRequest {
+                sender_id,
+                sender_device_id,
+                recipient_id,
+                conversation_id: conv_id,
+                client_message_id,
+                messages: vec![copy_for(recipient_id, recipient_device_id)],
+            },
+        )
+    };
+    let sent_by = |outcome| match outcome {
+        MultiDeviceSendOutcome::Sent { sent, deliveries } => (sent, deliveries.len()),
+        other => panic!("expected the send to be stored, got {other:?}"),
+    };
+
+    let (sent, _) = sent_by(send(alice, alice_device, bob, bob_device).await.unwrap());
+    let (retried, redelivered) = sent_by(send(alice, alice_device, bob, bob_device).await.unwrap());
+    assert_eq!(retried, sent);
+    assert_eq!(redelivered, 0);
+
+    // Bob picking the same id is a different message, not a retry of Alice's
+    let (reply, delivered) = sent_by(send(bob, bob_device, alice, alice_device).await.unwrap());
+    assert_ne!(reply.message_id, sent.message_id);
+    assert_eq!(delivered, 1);
+    let synced = common::queued_messages(&db, alice, alice_device).await;
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].sender_id, bob);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
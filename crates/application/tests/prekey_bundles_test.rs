@@ -0,0 +1,144 @@
+mod common;
+
+use application::keys::use_cases::{
+    GetPreKeyBundleUseCase, GetPreKeyCountUseCase, GetUserPreKeyBundlesUseCase,
+    BUNDLE_FETCHES_PER_CALLER, BUNDLE_RATE_LIMITED,
+};
+use chat_core::entities::{devices, one_time_prekeys};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, IntoActiveModel, Set};
+
+const PREKEY_COUNT: i32 = 5;
+
+async fn seed_prekeys(db: &DatabaseConnection, device_id: i64) {
+    one_time_prekeys::Entity::insert_many((1..=PREKEY_COUNT).map(|id| {
+        one_time_prekeys::ActiveModel {
+            device_id: Set(device_id),
+            prekey_id: Set(id),
+            public_key: Set(vec![id as u8; 32]),
+        }
+    }))
+    .exec(db)
+    .await
+    .expect("Failed to insert prekeys");
+}
+
+async fn remaining(db: &DatabaseConnection, device_id: i64) -> u64 {
+    GetPreKeyCountUseCase::execute(db, device_id)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn bundles_cover_active_devices_with_one_claim_each() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let caller = common::seed_user(&db).await;
+    let target = common::seed_user(&db).await;
+    let phone = common::seed_device(&db, target, vec![1; 32]).await;
+    let laptop = common::seed_device(&db, target, vec![2; 32]).await;
+    let unlinked = common::seed_device(&db, target, vec![3; 32]).await;
+    for device_id in [phone, laptop, unlinked] {
+        seed_prekeys(&db, device_id).await;
+    }
+    let mut device = devices::Entity::find_by_id(unlinked)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_active_model();
+    device.is_active = Set(false);
+    device.update(&db).await.unwrap();
+
+    let bundles = GetUserPreKeyBundlesUseCase::execute(&db, &mut redis, caller, target)
+        .await
+        .unwrap();
+    assert_eq!(
+        bundles.iter().map(|b| b.device_id).collect::<Vec<_>>(),
+        vec![phone, laptop]
+    );
+    for bundle in &bundles {
+        assert_eq!(bundle.one_time_prekey.as_ref().map(|pk| pk.id), Some(1));
+    }
+    assert_eq!(remaining(&db, phone).await, PREKEY_COUNT as u64 - 1);
+    assert_eq!(remaining(&db, laptop).await, PREKEY_COUNT as u64 - 1);
+    assert_eq!(remaining(&db, unlinked).await, PREKEY_COUNT as u64);
+
+    common::cleanup(&db, &[], &[caller, target]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn bundle_fetches_are_rate_limited_per_caller() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let caller = common::seed_user(&db).await;
+    let target = common::seed_user(&db).await;
+    let other_caller = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, target, vec![1; 32]).await;
+    seed_prekeys(&db, device_id).await;
+
+    for _ in 0..BUNDLE_FETCHES_PER_CALLER {
+        GetUserPreKeyBundlesUseCase::execute(&db, &mut redis, caller, target)
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        GetUserPreKeyBundlesUseCase::execute(&db, &mut redis, caller, target)
+            .await
+            .unwrap_err(),
+        BUNDLE_RATE_LIMITED
+    );
+    // Other callers are counted separately
+    GetUserPreKeyBundlesUseCase::execute(&db, &mut redis, other_caller, target)
+        .await
+        .unwrap();
+
+    common::cleanup(&db, &[], &[caller, target, other_caller]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn single_device_fetches_skip_unlinked_devices_and_share_the_rate_limit() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let caller = common::seed_user(&db).await;
+    let target = common::seed_user(&db).await;
+    let phone = common::seed_device(&db, target, vec![1; 32]).await;
+    let unlinked = common::seed_device(&db, target, vec![2; 32]).await;
+    for device_id in [phone, unlinked] {
+        seed_prekeys(&db, device_id).await;
+    }
+    let mut device = devices::Entity::find_by_id(unlinked)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap()
+        .into_active_model();
+    device.is_active = Set(false);
+    device.update(&db).await.unwrap();
+
+    assert_eq!(
+        GetPreKeyBundleUseCase::execute(&db, &mut redis, caller, target, unlinked)
+            .await
+            .unwrap_err(),
+        "Device not found"
+    );
+    assert_eq!(remaining(&db, unlinked).await, PREKEY_COUNT as u64);
+
+    // Per-device fetches, including the refused one above, share the caller's budget
+    for _ in 1..BUNDLE_FETCHES_PER_CALLER {
+        GetUserPreKeyBundlesUseCase::execute(&db, &mut redis, caller, target)
+            .await
+            .unwrap();
+    }
+    assert_eq!(
+        GetPreKeyBundleUseCase::execute(&db, &mut redis, caller, target, phone)
+            .await
+            .unwrap_err(),
+        BUNDLE_RATE_LIMITED
+    );
+
+    common::cleanup(&db, &[], &[caller, target]).await;
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use application::keys::use_cases::GetPreKeyBundleUseCase;
+use chat_core::entities::{one_time_prekeys, users};
+use infrastructure::redis::RedisClient;
+use sea_orm::{DatabaseConnection, EntityTrait, Set};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+const PREKEY_COUNT: i32 = 20;
+const CONCURRENT_FETCHES: usize = 50;
+
+/// Create a throwaway user with one device holding `PREKEY_COUNT` one-time prekeys.
+async fn seed_device(db: &DatabaseConnection) -> (Uuid, i64) {
+    let user_id = common::seed_user(db).await;
+    let device_id = common::seed_device(db, user_id, vec![1; 32]).await;
+
+    one_time_prekeys::Entity::insert_many((1..=PREKEY_COUNT).map(|id| {
+        one_time_prekeys::ActiveModel {
+            device_id: Set(device_id),
+            prekey_id: Set(id),
+            public_key: Set(vec![id as u8; 32]),
+        }
+    }))
+    .exec(db)
+    .await
+    .expect("Failed to insert prekeys");
+
+    (user_id, device_id)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn concurrent_fetches_never_share_a_prekey() {
+    let db = common::connect().await;
+    let redis = common::connect_redis_raw().await;
+    let (user_id, device_id) = seed_device(&db).await;
+
+    let fetches: Vec<_> = (0..CONCURRENT_FETCHES)
+        .map(|_| {
+            let db = db.clone();
+            let mut redis = RedisClient::new(redis.clone());
+            tokio::spawn(async move {
+                GetPreKeyBundleUseCase::execute(&db, &mut redis, Uuid::new_v4(), user_id, device_id)
+                    .await
+            })
+        })
+        .collect();
+
+    let mut claimed = Vec::new();
+    let mut exhausted = 0;
+    for fetch in fetches {
+        let bundle = fetch.await.unwrap().expect("Bundle fetch failed");
+        match bundle.one_time_prekey {
+            Some(prekey) => claimed.push(prekey.id),
+            None => exhausted += 1,
+        }
+    }
+
+    let unique: HashSet<i32> = claimed.iter().copied().collect();
+    assert_eq!(
+        unique.len(),
+        claimed.len(),
+        "A prekey was handed out twice: {:?}",
+        claimed
+    );
+    assert_eq!(
+        claimed.len(),
+        PREKEY_COUNT as usize,
+        "Every prekey should be claimed once"
+    );
+    assert_eq!(exhausted, CONCURRENT_FETCHES - PREKEY_COUNT as usize);
+
+    users::Entity::delete_by_id(user_id)
+        .exec(&db)
+        .await
+        .expect("Failed to clean up test data");
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use application::keys::dtos::PreKeyDto;
+use application::keys::use_cases::{
+    CheckPreKeyStockUseCase, GetPreKeyBundleUseCase, GetPreKeyCountUseCase, UploadPreKeysUseCase,
+    PREKEY_ID_EXISTS, PREKEY_LOW_THRESHOLD,
+};
+use uuid::Uuid;
+
+fn prekeys(ids: std::ops::RangeInclusive<i32>) -> Vec<PreKeyDto> {
+    ids.map(|id| PreKeyDto {
+        id,
+        key: vec![id as u8; 32],
+    })
+    .collect()
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn uploads_are_counted_and_reused_ids_rejected_whole() {
+    let db = common::connect().await;
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, vec![1; 32]).await;
+
+    let uploaded = UploadPreKeysUseCase::execute(&db, device_id, prekeys(1..=30))
+        .await
+        .unwrap();
+    assert_eq!(uploaded.count, 30);
+
+    // One reused id fails the batch without storing the new ids alongside it
+    assert_eq!(
+        UploadPreKeysUseCase::execute(&db, device_id, prekeys(30..=40))
+            .await
+            .unwrap_err(),
+        PREKEY_ID_EXISTS
+    );
+    let count = GetPreKeyCountUseCase::execute(&db, device_id)
+        .await
+        .unwrap();
+    assert_eq!(count.count, 30);
+
+    assert!(UploadPreKeysUseCase::execute(&db, device_id, Vec::new())
+        .await
+        .unwrap_err()
+        .starts_with("Invalid key bundle"));
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn concurrent_uploads_of_the_same_ids_store_one_batch() {
+    let db = common::connect().await;
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, vec![1; 32]).await;
+
+    let uploads: Vec<_> = (0..8)
+        .map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                UploadPreKeysUseCase::execute(&db, device_id, prekeys(1..=50)).await
+            })
+        })
+        .collect();
+
+    let mut stored = 0;
+    for upload in uploads {
+        match upload.await.unwrap() {
+            Ok(_) => stored += 1,
+            Err(e) => assert_eq!(e, PREKEY_ID_EXISTS),
+        }
+    }
+    assert_eq!(stored, 1);
+    let count = GetPreKeyCountUseCase::execute(&db, device_id)
+        .await
+        .unwrap();
+    assert_eq!(count.count, 50);
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn stock_is_low_once_claims_drop_it_below_the_threshold() {
+    let db = common::connect().await;
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, vec![1; 32]).await;
+
+    let stock = PREKEY_LOW_THRESHOLD as i32;
+    UploadPreKeysUseCase::execute(&db, device_id, prekeys(1..=stock))
+        .await
+        .unwrap();
+    assert_eq!(
+        CheckPreKeyStockUseCase::execute(&db, device_id)
+            .await
+            .unwrap(),
+        None
+    );
+
+    let mut redis = common::connect_redis().await;
+    GetPreKeyBundleUseCase::execute(&db, &mut redis, Uuid::new_v4(), user_id, device_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        CheckPreKeyStockUseCase::execute(&db, device_id)
+            .await
+            .unwrap(),
+        Some(PREKEY_LOW_THRESHOLD - 1)
+    );
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
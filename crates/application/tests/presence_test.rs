@@ -0,0 +1,234 @@
+mod common;
+
+use application::presence::dtos::{LastSeenVisibility, PresenceSettingsDto};
+use application::presence::use_cases::{
+    ExpirePresenceUseCase, MarkDeviceOfflineUseCase, MarkDeviceOnlineUseCase,
+    RefreshDevicePresenceUseCase, SubscribePresenceUseCase, UnsubscribePresenceUseCase,
+    UpdatePresenceSettingsUseCase,
+};
+use chat_core::entities::users;
+use redis::aio::MultiplexedConnection;
+use sea_orm::EntityTrait;
+use uuid::Uuid;
+
+const TTL: i64 = 90;
+
+/// Backdate a device's presence as if its last heartbeat was long ago.
+async fn lapse(redis: &mut MultiplexedConnection, user_id: Uuid, device_id: i64) {
+    redis::cmd("ZADD")
+        .arg(format!("user:{}:online", user_id))
+        .arg(chrono::Utc::now().timestamp() - 1)
+        .arg(device_id)
+        .query_async::<()>(redis)
+        .await
+        .unwrap();
+}
+
+/// When a device's presence lapses without another heartbeat.
+async fn expires_at(redis: &mut MultiplexedConnection, user_id: Uuid, device_id: i64) -> i64 {
+    redis::cmd("ZSCORE")
+        .arg(format!("user:{}:online", user_id))
+        .arg(device_id)
+        .query_async::<f64>(redis)
+        .await
+        .unwrap() as i64
+}
+
+async fn stored_user(db: &sea_orm::DatabaseConnection, user_id: Uuid) -> users::Model {
+    users::Entity::find_by_id(user_id)
+        .one(db)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn presence_follows_devices_and_last_seen_privacy() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let carol = common::seed_user(&db).await;
+    let alice_phone = common::seed_device(&db, alice, vec![1; 32]).await;
+    let alice_laptop = common::seed_device(&db, alice, vec![1; 32]).await;
+    // Bob is a contact of Alice's, Carol is not
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    for watcher in [bob, carol] {
+        let snapshot = SubscribePresenceUseCase::execute(&db, &mut redis, watcher, vec![alice])
+            .await
+            .unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert!(!snapshot[0].online);
+    }
+
+    // Only the first device online and the last one offline change the user's presence
+    let change = MarkDeviceOnlineUseCase::execute(&db, &mut redis, alice, alice_phone, TTL)
+        .await
+        .unwrap()
+        .expect("first device should bring Alice online");
+    assert!(change.presence.online);
+    let mut recipients = change.recipients;
+    recipients.sort();
+    let mut watchers = vec![bob, carol];
+    watchers.sort();
+    assert_eq!(recipients, watchers);
+
+    assert!(
+        MarkDeviceOnlineUseCase::execute(&db, &mut redis, alice, alice_laptop, TTL)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        MarkDeviceOfflineUseCase::execute(&db, &mut redis, alice, alice_phone)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let change = MarkDeviceOfflineUseCase::execute(&db, &mut redis, alice, alice_laptop)
+        .await
+        .unwrap()
+        .expect("last device should take Alice offline");
+    assert!(!change.presence.online);
+    assert!(change.presence.last_seen_at.is_some());
+    let stored = users::Entity::find_by_id(alice)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!stored.is_online);
+    assert!(stored.last_seen_at.is_some());
+
+    UpdatePresenceSettingsUseCase::execute(
+        &db,
+        alice,
+        PresenceSettingsDto {
+            last_seen_visibility: LastSeenVisibility::Contacts,
+        },
+    )
+    .await
+    .unwrap();
+    let change = MarkDeviceOnlineUseCase::execute(&db, &mut redis, alice, alice_phone, TTL)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(change.recipients, vec![bob]);
+    assert!(
+        SubscribePresenceUseCase::execute(&db, &mut redis, carol, vec![alice])
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let snapshot = SubscribePresenceUseCase::execute(&db, &mut redis, bob, vec![alice])
+        .await
+        .unwrap();
+    assert!(snapshot[0].online);
+
+    UpdatePresenceSettingsUseCase::execute(
+        &db,
+        alice,
+        PresenceSettingsDto {
+            last_seen_visibility: LastSeenVisibility::Nobody,
+        },
+    )
+    .await
+    .unwrap();
+    let change = MarkDeviceOfflineUseCase::execute(&db, &mut redis, alice, alice_phone)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(change.recipients.is_empty());
+
+    for watcher in [bob, carol] {
+        UnsubscribePresenceUseCase::execute(&mut redis, watcher, vec![alice])
+            .await
+            .unwrap();
+    }
+    common::cleanup(&db, &[conv_id], &[alice, bob, carol]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn heartbeats_extend_presence_and_silent_devices_are_swept_offline() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let mut raw = common::connect_redis_raw().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_phone = common::seed_device(&db, alice, vec![1; 32]).await;
+    let alice_laptop = common::seed_device(&db, alice, vec![1; 32]).await;
+    SubscribePresenceUseCase::execute(&db, &mut redis, bob, vec![alice])
+        .await
+        .unwrap();
+
+    MarkDeviceOnlineUseCase::execute(&db, &mut redis, alice, alice_phone, TTL)
+        .await
+        .unwrap()
+        .unwrap();
+    MarkDeviceOnlineUseCase::execute(&db, &mut redis, alice, alice_laptop, TTL)
+        .await
+        .unwrap();
+    let came_online = stored_user(&db, alice).await.last_seen_at;
+
+    // A heartbeat neither writes to Postgres nor tells watchers anything
+    assert!(
+        RefreshDevicePresenceUseCase::execute(&db, &mut redis, alice, alice_phone, TTL)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(stored_user(&db, alice).await.last_seen_at, came_online);
+
+    // Each heartbeat pushes the expiry out by the TTL it was given
+    let before = chrono::Utc::now().timestamp();
+    RefreshDevicePresenceUseCase::execute(&db, &mut redis, alice, alice_phone, 10 * TTL)
+        .await
+        .unwrap();
+    assert!(expires_at(&mut raw, alice, alice_phone).await >= before + 10 * TTL);
+
+    // One silent device leaves Alice online through the other
+    lapse(&mut raw, alice, alice_phone).await;
+    let changes = ExpirePresenceUseCase::execute(&db, &mut redis)
+        .await
+        .unwrap();
+    assert!(changes.iter().all(|c| c.presence.user_id != alice));
+    assert!(stored_user(&db, alice).await.is_online);
+
+    lapse(&mut raw, alice, alice_laptop).await;
+    let changes = ExpirePresenceUseCase::execute(&db, &mut redis)
+        .await
+        .unwrap();
+    let change = changes
+        .iter()
+        .find(|c| c.presence.user_id == alice)
+        .expect("Alice should be swept offline");
+    assert!(!change.presence.online);
+    assert_eq!(change.recipients, vec![bob]);
+    let stored = stored_user(&db, alice).await;
+    assert!(!stored.is_online);
+    assert!(stored.last_seen_at > came_online);
+
+    // Swept once only
+    let changes = ExpirePresenceUseCase::execute(&db, &mut redis)
+        .await
+        .unwrap();
+    assert!(changes.iter().all(|c| c.presence.user_id != alice));
+
+    // A heartbeat from a device that was swept brings the user back like a new connection
+    let change = RefreshDevicePresenceUseCase::execute(&db, &mut redis, alice, alice_laptop, TTL)
+        .await
+        .unwrap()
+        .expect("heartbeat after expiry should bring Alice online");
+    assert!(change.presence.online);
+    assert!(stored_user(&db, alice).await.is_online);
+
+    MarkDeviceOfflineUseCase::execute(&db, &mut redis, alice, alice_laptop)
+        .await
+        .unwrap();
+    UnsubscribePresenceUseCase::execute(&mut redis, bob, vec![alice])
+        .await
+        .unwrap();
+    common::cleanup(&db, &[], &[alice, bob]).await;
+}
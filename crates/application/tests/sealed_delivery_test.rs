@@ -0,0 +1,187 @@
+mod common;
+
+use application::sealed_sender::dtos::SendSealedMessageRequest;
+use application::sealed_sender::use_cases::{
+    AckSealedMessagesUseCase, SendSealedMessageUseCase, SetUnidentifiedAccessUseCase,
+    SyncSealedMessagesUseCase, MAX_SEALED_MESSAGE_LEN, SEALED_ACCESS_DENIED,
+    SEALED_MESSAGE_TOO_LARGE, SEALED_SENDS_PER_RECIPIENT, SEALED_SEND_RATE_LIMITED,
+};
+
+fn sealed_to(
+    recipient: uuid::Uuid,
+    device_id: i64,
+    key: Option<[u8; 16]>,
+) -> SendSealedMessageRequest {
+    SendSealedMessageRequest {
+        recipient_id: recipient,
+        recipient_device_id: device_id,
+        access_key: key.map(|k| k.to_vec()),
+        content: b"sealed".to_vec(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn sealed_delivery_requires_the_recipients_access_key() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let bob = common::seed_user(&db).await;
+    let bob_device = common::seed_device(&db, bob, vec![7; 32]).await;
+
+    // Nothing is accepted until Bob publishes an access key
+    assert_eq!(
+        SendSealedMessageUseCase::execute(
+            &db,
+            &mut redis,
+            sealed_to(bob, bob_device, Some([1; 16]))
+        )
+        .await
+        .unwrap_err(),
+        SEALED_ACCESS_DENIED
+    );
+
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], false)
+        .await
+        .unwrap();
+    for key in [Some([2; 16]), None] {
+        assert_eq!(
+            SendSealedMessageUseCase::execute(&db, &mut redis, sealed_to(bob, bob_device, key))
+                .await
+                .unwrap_err(),
+            SEALED_ACCESS_DENIED
+        );
+    }
+
+    let delivered = SendSealedMessageUseCase::execute(
+        &db,
+        &mut redis,
+        sealed_to(bob, bob_device, Some([1; 16])),
+    )
+    .await
+    .unwrap();
+
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], true)
+        .await
+        .unwrap();
+    let unrestricted =
+        SendSealedMessageUseCase::execute(&db, &mut redis, sealed_to(bob, bob_device, None))
+            .await
+            .unwrap();
+
+    let queued = SyncSealedMessagesUseCase::execute(&db, bob_device, None, 100)
+        .await
+        .unwrap();
+    assert!(!queued.has_more);
+    let ids: Vec<i64> = queued.messages.iter().map(|m| m.delivery_id).collect();
+    assert_eq!(ids, vec![delivered.delivery_id, unrestricted.delivery_id]);
+    assert_eq!(queued.messages[0].content, b"sealed");
+
+    let acked = AckSealedMessagesUseCase::execute(&db, bob_device, ids)
+        .await
+        .unwrap();
+    assert_eq!(acked, 2);
+    assert!(
+        SyncSealedMessagesUseCase::execute(&db, bob_device, None, 100)
+            .await
+            .unwrap()
+            .messages
+            .is_empty()
+    );
+
+    common::cleanup(&db, &[], &[bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn sealed_messages_are_paged_and_restart_without_a_cursor() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let bob = common::seed_user(&db).await;
+    let bob_device = common::seed_device(&db, bob, vec![7; 32]).await;
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], false)
+        .await
+        .unwrap();
+
+    let mut sent = Vec::new();
+    for _ in 0..5 {
+        let message = SendSealedMessageUseCase::execute(
+            &db,
+            &mut redis,
+            sealed_to(bob, bob_device, Some([1; 16])),
+        )
+        .await
+        .unwrap();
+        sent.push(message.delivery_id);
+    }
+
+    let mut after = None;
+    let mut paged = Vec::new();
+    loop {
+        let page = SyncSealedMessagesUseCase::execute(&db, bob_device, after, 2)
+            .await
+            .unwrap();
+        assert!(page.messages.len() <= 2);
+        paged.extend(page.messages.iter().map(|m| m.delivery_id));
+        after = paged.last().copied();
+        if !page.has_more {
+            break;
+        }
+    }
+    assert_eq!(paged, sent);
+
+    // Nothing was acked, so a new session starts over from the oldest
+    let first = SyncSealedMessagesUseCase::execute(&db, bob_device, None, 2)
+        .await
+        .unwrap();
+    assert_eq!(first.messages[0].delivery_id, sent[0]);
+    assert!(first.has_more);
+
+    common::cleanup(&db, &[], &[bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres and Redis (docker-compose up -d)"]
+async fn oversized_and_excess_sealed_messages_are_rejected() {
+    let db = common::connect().await;
+    let mut redis = common::connect_redis().await;
+    let bob = common::seed_user(&db).await;
+    let bob_device = common::seed_device(&db, bob, vec![7; 32]).await;
+    SetUnidentifiedAccessUseCase::execute(&db, bob, vec![1; 16], false)
+        .await
+        .unwrap();
+
+    let mut oversized = sealed_to(bob, bob_device, Some([1; 16]));
+    oversized.content = vec![0; MAX_SEALED_MESSAGE_LEN + 1];
+    assert_eq!(
+        SendSealedMessageUseCase::execute(&db, &mut redis, oversized)
+            .await
+            .unwrap_err(),
+        SEALED_MESSAGE_TOO_LARGE
+    );
+
+    for _ in 0..SEALED_SENDS_PER_RECIPIENT {
+        SendSealedMessageUseCase::execute(
+            &db,
+            &mut redis,
+            sealed_to(bob, bob_device, Some([1; 16])),
+        )
+        .await
+        .unwrap();
+    }
+    assert_eq!(
+        SendSealedMessageUseCase::execute(
+            &db,
+            &mut redis,
+            sealed_to(bob, bob_device, Some([1; 16]))
+        )
+        .await
+        .unwrap_err(),
+        SEALED_SEND_RATE_LIMITED
+    );
+    let queued = SyncSealedMessagesUseCase::execute(&db, bob_device, None, 100)
+        .await
+        .unwrap();
+    assert!(queued.has_more);
+
+    common::cleanup(&db, &[], &[bob]).await;
+}
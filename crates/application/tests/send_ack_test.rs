@@ -0,0 +1,101 @@
+mod common;
+
+use application::chat::dtos::SendMessageRequest;
+use application::chat::use_cases::SendMessageUseCase;
+use uuid::Uuid;
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn retried_send_is_acknowledged_with_the_original_message() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let client_message_id = Uuid::new_v4();
+    let request = || SendMessageRequest {
+        sender_id: alice,
+        sender_device_id: alice_device,
+        recipient_id: bob,
+        recipient_device_id: bob_device,
+        conversation_id: conv_id,
+        client_message_id,
+        content: b"ciphertext".to_vec(),
+    };
+
+    let sent = SendMessageUseCase::execute(&db, request()).await.unwrap();
+    let retried = SendMessageUseCase::execute(&db, request()).await.unwrap();
+    assert_eq!(retried, sent);
+
+    let synced = common::queued_messages(&db, bob, bob_device).await;
+    assert_eq!(synced.len(), 1);
+    assert_eq!(synced[0].message_id, sent.message_id);
+    assert_eq!(synced[0].sent_at, sent.sent_at);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn another_sender_reusing_a_client_message_id_gets_a_new_message() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let mallory = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let mallory_device = common::seed_device(&db, mallory, vec![3; 32]).await;
+    let with_alice = common::seed_conversation(&db, &[alice, bob]).await;
+    let with_mallory = common::seed_conversation(&db, &[mallory, bob]).await;
+
+    let client_message_id = Uuid::new_v4();
+    let sent = SendMessageUseCase::execute(
+        &db,
+        SendMessageRequest {
+            sender_id: alice,
+            sender_device_id: alice_device,
+            recipient_id: bob,
+            recipient_device_id: bob_device,
+            conversation_id: with_alice,
+            client_message_id,
+            content: b"from alice".to_vec(),
+        },
+    )
+    .await
+    .unwrap();
+    let replayed = SendMessageUseCase::execute(
+        &db,
+        SendMessageRequest {
+            sender_id: mallory,
+            sender_device_id: mallory_device,
+            recipient_id: bob,
+            recipient_device_id: bob_device,
+            conversation_id: with_mallory,
+            client_message_id,
+            content: b"from mallory".to_vec(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_ne!(replayed.message_id, sent.message_id);
+
+    // Alice's message keeps only her ciphertext and Mallory's is attributed to Mallory
+    let synced = common::queued_messages(&db, bob, bob_device).await;
+    assert_eq!(synced.len(), 2);
+    let from_alice = synced
+        .iter()
+        .find(|m| m.message_id == sent.message_id)
+        .unwrap();
+    assert_eq!(from_alice.sender_id, alice);
+    assert_eq!(from_alice.content, b"from alice".to_vec());
+    let from_mallory = synced
+        .iter()
+        .find(|m| m.message_id == replayed.message_id)
+        .unwrap();
+    assert_eq!(from_mallory.sender_id, mallory);
+    assert_eq!(from_mallory.content, b"from mallory".to_vec());
+
+    common::cleanup(&db, &[with_alice, with_mallory], &[alice, bob, mallory]).await;
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use application::keys::dtos::SignedPreKeyDto;
+use application::keys::use_cases::{
+    PruneSignedPreKeysUseCase, RotateSignedPreKeyUseCase, SIGNED_PREKEY_ID_EXISTS,
+};
+use chat_core::entities::{devices, previous_signed_prekeys};
+use chrono::{Duration, Utc};
+use infrastructure::crypto::signal::{self, IdentityKeyPair};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+
+fn signed_prekey(identity: &IdentityKeyPair, id: u32) -> SignedPreKeyDto {
+    let signed_prekey = signal::generate_signed_prekey(identity, id).unwrap();
+    SignedPreKeyDto {
+        id: id as i32,
+        key: signed_prekey.public_key,
+        signature: signed_prekey.signature,
+    }
+}
+
+async fn history(
+    db: &sea_orm::DatabaseConnection,
+    device_id: i64,
+) -> Vec<previous_signed_prekeys::Model> {
+    previous_signed_prekeys::Entity::find()
+        .filter(previous_signed_prekeys::Column::DeviceId.eq(device_id))
+        .order_by_asc(previous_signed_prekeys::Column::SignedPrekeyId)
+        .all(db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn rotation_keeps_the_replaced_key_for_the_grace_period() {
+    let db = common::connect().await;
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, identity.public_key.clone()).await;
+    let original = devices::Entity::find_by_id(device_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let before = Utc::now();
+    let rotated = RotateSignedPreKeyUseCase::execute(
+        &db,
+        device_id,
+        signed_prekey(&identity, 2),
+        Duration::days(2),
+    )
+    .await
+    .unwrap();
+    assert_eq!(rotated.signed_prekey_id, 2);
+    assert_eq!(rotated.previous_signed_prekey_id, original.signed_prekey_id);
+    assert!(rotated.previous_expires_at >= before + Duration::days(2));
+
+    let device = devices::Entity::find_by_id(device_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(device.signed_prekey_id, 2);
+
+    let history = history(&db, device_id).await;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].signed_prekey_id, original.signed_prekey_id);
+    assert_eq!(history[0].public_key, original.signed_prekey_public);
+    assert_eq!(history[0].signature, original.signed_prekey_signature);
+    assert_eq!(
+        history[0].expires_at.timestamp(),
+        rotated.previous_expires_at.timestamp()
+    );
+
+    // Neither the current id nor one still in the grace window can be reused
+    for reused in [2, original.signed_prekey_id as u32] {
+        assert_eq!(
+            RotateSignedPreKeyUseCase::execute(
+                &db,
+                device_id,
+                signed_prekey(&identity, reused),
+                Duration::days(2),
+            )
+            .await
+            .unwrap_err(),
+            SIGNED_PREKEY_ID_EXISTS
+        );
+    }
+
+    // A key signed by someone else's identity is rejected
+    let (other, _) = signal::generate_identity_keypair().unwrap();
+    assert!(RotateSignedPreKeyUseCase::execute(
+        &db,
+        device_id,
+        signed_prekey(&other, 3),
+        Duration::days(2),
+    )
+    .await
+    .unwrap_err()
+    .starts_with("Invalid key bundle"));
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn concurrent_rotations_each_archive_the_key_they_replace() {
+    let db = common::connect().await;
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, identity.public_key.clone()).await;
+
+    let rotations: Vec<_> = (2..=9)
+        .map(|id| {
+            let db = db.clone();
+            let signed_prekey = signed_prekey(&identity, id);
+            tokio::spawn(async move {
+                RotateSignedPreKeyUseCase::execute(&db, device_id, signed_prekey, Duration::days(2))
+                    .await
+            })
+        })
+        .collect();
+    for rotation in rotations {
+        rotation.await.unwrap().unwrap();
+    }
+
+    // Serialized on the device row: every key but the last one ends up in history exactly once
+    let device = devices::Entity::find_by_id(device_id)
+        .one(&db)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut ids: Vec<i32> = history(&db, device_id)
+        .await
+        .iter()
+        .map(|row| row.signed_prekey_id)
+        .collect();
+    ids.push(device.signed_prekey_id);
+    ids.sort_unstable();
+    assert_eq!(ids, (1..=9).collect::<Vec<_>>());
+
+    // Racing the same new id: one rotation wins, the rest conflict
+    let rotations: Vec<_> = (0..8)
+        .map(|_| {
+            let db = db.clone();
+            let signed_prekey = signed_prekey(&identity, 10);
+            tokio::spawn(async move {
+                RotateSignedPreKeyUseCase::execute(&db, device_id, signed_prekey, Duration::days(2))
+                    .await
+            })
+        })
+        .collect();
+    let mut rotated = 0;
+    for rotation in rotations {
+        match rotation.await.unwrap() {
+            Ok(_) => rotated += 1,
+            Err(e) => assert_eq!(e, SIGNED_PREKEY_ID_EXISTS),
+        }
+    }
+    assert_eq!(rotated, 1);
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn pruning_removes_only_keys_past_their_grace_period() {
+    let db = common::connect().await;
+    let user_id = common::seed_user(&db).await;
+    let device_id = common::seed_device(&db, user_id, vec![1; 32]).await;
+
+    for (signed_prekey_id, expires_in) in [(7, Duration::minutes(-1)), (8, Duration::days(1))] {
+        previous_signed_prekeys::ActiveModel {
+            device_id: Set(device_id),
+            signed_prekey_id: Set(signed_prekey_id),
+            public_key: Set(vec![2; 32]),
+            signature: Set(vec![3; 64]),
+            replaced_at: Set((Utc::now() - Duration::days(2)).into()),
+            expires_at: Set((Utc::now() + expires_in).into()),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+    }
+
+    assert!(PruneSignedPreKeysUseCase::execute(&db).await.unwrap() >= 1);
+    let remaining: Vec<i32> = history(&db, device_id)
+        .await
+        .iter()
+        .map(|row| row.signed_prekey_id)
+        .collect();
+    assert_eq!(remaining, vec![8]);
+
+    common::cleanup(&db, &[], &[user_id]).await;
+}
@@ -0,0 +1,260 @@
+mod common;
+
+use application::chat::dtos::{SendMessageRequest, SyncMessagesResponse};
+use application::chat::sync_messages::SyncMessagesUseCase;
+use application::chat::use_cases::SendMessageUseCase;
+use chat_core::entities::{devices, message_deliveries, messages, sync_checkpoints};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, DatabaseConnection, EntityTrait, QuerySelect, Set, TransactionTrait,
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+async fn checkpoint(db: &DatabaseConnection, device_id: i64) -> Option<i64> {
+    sync_checkpoints::Entity::find_by_id(device_id)
+        .one(db)
+        .await
+        .unwrap()
+        .map(|checkpoint| checkpoint.last_message_id)
+}
+
+fn send_request(
+    sender: Uuid,
+    sender_device: i64,
+    recipient: Uuid,
+    recipient_device: i64,
+    conv_id: Uuid,
+) -> SendMessageRequest {
+    SendMessageRequest {
+        sender_id: sender,
+        sender_device_id: sender_device,
+        recipient_id: recipient,
+        recipient_device_id: recipient_device,
+        conversation_id: conv_id,
+        client_message_id: Uuid::new_v4(),
+        content: b"ciphertext".to_vec(),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn sync_pages_in_order_and_resumes_from_the_checkpoint() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let mut sent = Vec::new();
+    for _ in 0..5 {
+        let message = SendMessageUseCase::execute(
+            &db,
+            send_request(alice, alice_device, bob, bob_device, conv_id),
+        )
+        .await
+        .unwrap();
+        sent.push(message.message_id);
+    }
+    let ids = |page: &SyncMessagesResponse| {
+        page.messages
+            .iter()
+            .map(|m| m.message_id)
+            .collect::<Vec<_>>()
+    };
+
+    let first = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 2)
+        .await
+        .unwrap();
+    assert_eq!(ids(&first), sent[..2]);
+    assert!(first.has_more);
+    assert_eq!(checkpoint(&db, bob_device).await, None);
+
+    // Dropping the connection before asking for the next page replays the same page
+    let replay = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 2)
+        .await
+        .unwrap();
+    assert_eq!(ids(&replay), sent[..2]);
+
+    let second = SyncMessagesUseCase::execute(&db, bob, bob_device, first.cursor.as_deref(), 2)
+        .await
+        .unwrap();
+    assert_eq!(ids(&second), sent[2..4]);
+    assert_eq!(checkpoint(&db, bob_device).await, Some(sent[1]));
+
+    let third = SyncMessagesUseCase::execute(&db, bob, bob_device, second.cursor.as_deref(), 2)
+        .await
+        .unwrap();
+    assert_eq!(ids(&third), sent[4..]);
+    assert!(!third.has_more);
+
+    let last = SyncMessagesUseCase::execute(&db, bob, bob_device, third.cursor.as_deref(), 10)
+        .await
+        .unwrap();
+    assert!(last.messages.is_empty());
+    assert_eq!(last.cursor, third.cursor);
+    assert_eq!(checkpoint(&db, bob_device).await, Some(sent[4]));
+
+    // An old cursor cannot rewind the checkpoint
+    SyncMessagesUseCase::execute(&db, bob, bob_device, first.cursor.as_deref(), 10)
+        .await
+        .unwrap();
+    assert_eq!(checkpoint(&db, bob_device).await, Some(sent[4]));
+
+    assert_eq!(
+        SyncMessagesUseCase::execute(&db, bob, bob_device, Some("not a cursor"), 10)
+            .await
+            .unwrap_err(),
+        "Invalid cursor"
+    );
+    assert_eq!(
+        SyncMessagesUseCase::execute(&db, alice, bob_device, None, 10)
+            .await
+            .unwrap_err(),
+        "Device not found"
+    );
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn device_without_a_cursor_resumes_from_its_checkpoint() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    let mut sent = Vec::new();
+    for _ in 0..3 {
+        let message = SendMessageUseCase::execute(
+            &db,
+            send_request(alice, alice_device, bob, bob_device, conv_id),
+        )
+        .await
+        .unwrap();
+        sent.push(message.message_id);
+    }
+
+    let first = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 2)
+        .await
+        .unwrap();
+    SyncMessagesUseCase::execute(&db, bob, bob_device, first.cursor.as_deref(), 2)
+        .await
+        .unwrap();
+
+    // Reconnecting skips the page the device confirmed but not the one it may have missed
+    let resumed = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 10)
+        .await
+        .unwrap();
+    let ids: Vec<_> = resumed.messages.iter().map(|m| m.message_id).collect();
+    assert_eq!(ids, sent[2..]);
+
+    let reply = SendMessageUseCase::execute(
+        &db,
+        send_request(bob, bob_device, alice, alice_device, conv_id),
+    )
+    .await
+    .unwrap();
+    let resumed = SyncMessagesUseCase::execute(&db, bob, bob_device, resumed.cursor.as_deref(), 10)
+        .await
+        .unwrap();
+    assert!(resumed.messages.is_empty());
+    assert_eq!(checkpoint(&db, bob_device).await, Some(sent[2]));
+    assert!(SyncMessagesUseCase::execute(&db, bob, bob_device, None, 10)
+        .await
+        .unwrap()
+        .messages
+        .is_empty());
+
+    // A cursor naming a message that was never queued for the device is refused
+    let alice_page = SyncMessagesUseCase::execute(&db, alice, alice_device, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(alice_page.messages[0].message_id, reply.message_id);
+    assert_eq!(
+        SyncMessagesUseCase::execute(&db, bob, bob_device, alice_page.cursor.as_deref(), 10)
+            .await
+            .unwrap_err(),
+        "Invalid cursor"
+    );
+    assert_eq!(checkpoint(&db, bob_device).await, Some(sent[2]));
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
+
+#[tokio::test]
+#[ignore = "requires Postgres (docker-compose up -d)"]
+async fn sends_to_a_device_commit_in_message_id_order() {
+    let db = common::connect().await;
+    let alice = common::seed_user(&db).await;
+    let bob = common::seed_user(&db).await;
+    let alice_device = common::seed_device(&db, alice, vec![1; 32]).await;
+    let bob_device = common::seed_device(&db, bob, vec![2; 32]).await;
+    let conv_id = common::seed_conversation(&db, &[alice, bob]).await;
+
+    // A slow send holds Bob's device and has taken a message id but not committed yet
+    let slow = db.begin().await.unwrap();
+    devices::Entity::find_by_id(bob_device)
+        .lock_exclusive()
+        .one(&slow)
+        .await
+        .unwrap();
+    let late = messages::ActiveModel {
+        conv_id: Set(conv_id),
+        client_message_id: Set(Some(Uuid::new_v4())),
+        sender_user_id: Set(alice),
+        sender_device_id: Set(alice_device),
+        message_type: Set(1),
+        content: Set(String::new()),
+        iv: Set(Vec::new()),
+        sent_at: Set(Utc::now().into()),
+        extra: Set(serde_json::json!({})),
+        ..Default::default()
+    }
+    .insert(&slow)
+    .await
+    .unwrap();
+    message_deliveries::ActiveModel {
+        message_id: Set(late.message_id),
+        device_id: Set(bob_device),
+        content: Set(Some(b"late".to_vec())),
+        ..Default::default()
+    }
+    .insert(&slow)
+    .await
+    .unwrap();
+
+    // A second send waits for it, so it can never be paged past the slow one
+    let send = tokio::spawn({
+        let db = db.clone();
+        async move {
+            SendMessageUseCase::execute(
+                &db,
+                send_request(alice, alice_device, bob, bob_device, conv_id),
+            )
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!send.is_finished());
+    let page = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 10)
+        .await
+        .unwrap();
+    assert!(page.messages.is_empty());
+
+    slow.commit().await.unwrap();
+    let early = send.await.unwrap().unwrap();
+    assert!(early.message_id > late.message_id);
+
+    let page = SyncMessagesUseCase::execute(&db, bob, bob_device, None, 10)
+        .await
+        .unwrap();
+    let ids: Vec<_> = page.messages.iter().map(|m| m.message_id).collect();
+    assert_eq!(ids, vec![late.message_id, early.message_id]);
+
+    common::cleanup(&db, &[conv_id], &[alice, bob]).await;
+}
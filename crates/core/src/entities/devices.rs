@@ -37,6 +37,8 @@ pub enum Relation {
     Users,
     #[sea_orm(has_many = "super::one_time_prekeys::Entity")]
     OneTimePrekeys,
+    #[sea_orm(has_many = "super::previous_signed_prekeys::Entity")]
+    PreviousSignedPrekeys,
     #[sea_orm(has_many = "super::signal_sessions::Entity")]
     SignalSessions,
 }
@@ -53,6 +55,12 @@ impl Related<super::one_time_prekeys::Entity> for Entity {
     }
 }
 
+impl Related<super::previous_signed_prekeys::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PreviousSignedPrekeys.def()
+    }
+}
+
 impl Related<super::signal_sessions::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::SignalSessions.def()
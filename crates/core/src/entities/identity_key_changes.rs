@@ -0,0 +1,50 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The set of a user's active identity keys changed (re-registration, or linking or
+/// unlinking a device); `device_id` is the device whose key joined or left the set.
+/// Contacts sync these to warn that the safety number changed.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "identity_key_changes")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub change_id: i64,
+    pub user_id: Uuid,
+    pub device_id: i64,
+    pub identity_key: Vec<u8>,
+    pub changed_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::users::Entity",
+        from = "Column::UserId",
+        to = "super::users::Column::UserId",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Users,
+    #[sea_orm(
+        belongs_to = "super::devices::Entity",
+        from = "Column::DeviceId",
+        to = "super::devices::Column::DeviceId",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Devices,
+}
+
+impl Related<super::users::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Users.def()
+    }
+}
+
+impl Related<super::devices::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Devices.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -16,6 +16,9 @@ pub struct Model {
     pub attachment_url: Option<String>,
     pub thumbnail_url: Option<String>,
     pub sender_key_distribution: Option<Vec<u8>>,
+    /// Sender Key ciphertext shared by every device a group message is delivered to.
+    /// Pairwise messages keep their per-device ciphertext in `message_deliveries.content`.
+    pub ciphertext: Option<Vec<u8>>,
     pub reply_to_message_id: Option<i64>,
     pub sent_at: DateTimeWithTimeZone,
     pub edited_at: Option<DateTimeWithTimeZone>,
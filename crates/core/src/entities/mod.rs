@@ -1,12 +1,19 @@
 pub mod prelude;
 
 pub mod conv_members;
+pub mod conversation_invites;
 pub mod conversations;
 pub mod device_linking_sessions;
 pub mod devices;
+pub mod identity_key_changes;
 pub mod message_deliveries;
 pub mod messages;
 pub mod one_time_prekeys;
+pub mod previous_signed_prekeys;
 pub mod push_tokens;
+pub mod sealed_deliveries;
 pub mod signal_sessions;
+pub mod sync_checkpoints;
+pub mod unidentified_access;
 pub mod users;
+pub mod verified_contacts;
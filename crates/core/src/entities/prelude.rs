@@ -1,9 +1,16 @@
 pub use super::conv_members::Entity as ConvMembers;
+pub use super::conversation_invites::Entity as ConversationInvites;
 pub use super::conversations::Entity as Conversations;
 pub use super::devices::Entity as Devices;
+pub use super::identity_key_changes::Entity as IdentityKeyChanges;
 pub use super::message_deliveries::Entity as MessageDeliveries;
 pub use super::messages::Entity as Messages;
 pub use super::one_time_prekeys::Entity as OneTimePrekeys;
+pub use super::previous_signed_prekeys::Entity as PreviousSignedPrekeys;
 pub use super::push_tokens::Entity as PushTokens;
+pub use super::sealed_deliveries::Entity as SealedDeliveries;
 pub use super::signal_sessions::Entity as SignalSessions;
+pub use super::sync_checkpoints::Entity as SyncCheckpoints;
+pub use super::unidentified_access::Entity as UnidentifiedAccess;
 pub use super::users::Entity as Users;
+pub use super::verified_contacts::Entity as VerifiedContacts;
@@ -17,6 +17,8 @@ pub struct Model {
     pub profile_picture: Option<String>,
     pub last_seen_at: Option<DateTimeWithTimeZone>,
     pub is_online: bool,
+    /// Who may see `is_online`/`last_seen_at`: 0 everyone, 1 contacts, 2 nobody
+    pub last_seen_visibility: i16,
     pub is_deleted: bool,
     pub deleted_at: Option<DateTimeWithTimeZone>,
     pub created_at: DateTimeWithTimeZone,
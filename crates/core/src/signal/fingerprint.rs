@@ -0,0 +1,134 @@
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha512};
+
+const FINGERPRINT_VERSION: u8 = 0;
+const ITERATIONS: usize = 5200;
+/// Bytes of each user's hash that feed the 30-digit half of the safety number.
+const DISPLAY_BYTES: usize = 30;
+
+/// Safety number shared by two users, plus the QR payload one of them scans from the other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// 60 digits, identical on both sides regardless of who computes it.
+    pub safety_number: String,
+    /// `version || local hash || remote hash`, so it differs depending on who displays it.
+    pub scannable: Vec<u8>,
+    pub remote_hash: [u8; 32],
+}
+
+/// Derive the fingerprint between `local` and `remote`, each identified by a stable id
+/// (the user UUID) and the identity keys of all their active devices.
+pub fn fingerprint(
+    local_id: &[u8],
+    local_identity_keys: &[Vec<u8>],
+    remote_id: &[u8],
+    remote_identity_keys: &[Vec<u8>],
+) -> Fingerprint {
+    let local_hash = user_hash(local_id, local_identity_keys);
+    let remote_hash = user_hash(remote_id, remote_identity_keys);
+
+    let mut halves = [displayable(&local_hash), displayable(&remote_hash)];
+    halves.sort();
+
+    let mut scannable = Vec::with_capacity(65);
+    scannable.push(FINGERPRINT_VERSION);
+    scannable.extend_from_slice(&local_hash);
+    scannable.extend_from_slice(&remote_hash);
+
+    Fingerprint {
+        safety_number: halves.concat(),
+        scannable,
+        remote_hash,
+    }
+}
+
+/// Hash of a single user's identity, which changes whenever any of their device keys does.
+pub fn user_hash(id: &[u8], identity_keys: &[Vec<u8>]) -> [u8; 32] {
+    let mut keys = identity_keys.to_vec();
+    keys.sort();
+    let keys = keys.concat();
+
+    let mut hash = Sha512::new()
+        .chain_update([0, FINGERPRINT_VERSION])
+        .chain_update(&keys)
+        .chain_update(id)
+        .finalize();
+    for _ in 1..ITERATIONS {
+        hash = Sha512::new()
+            .chain_update(hash)
+            .chain_update(&keys)
+            .finalize();
+    }
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&hash[..32]);
+    out
+}
+
+/// Check a payload scanned from the peer's screen against the one we would display:
+/// their local hash must be our remote hash and vice versa.
+pub fn matches_scanned(ours: &[u8], scanned: &[u8]) -> Result<bool> {
+    if ours.len() != 65 || scanned.len() != 65 {
+        bail!("Invalid fingerprint payload");
+    }
+    if scanned[0] != FINGERPRINT_VERSION {
+        bail!("Unsupported fingerprint version {}", scanned[0]);
+    }
+    Ok(ours[1..33] == scanned[33..] && ours[33..] == scanned[1..33])
+}
+
+fn displayable(hash: &[u8; 32]) -> String {
+    hash[..DISPLAY_BYTES]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &[u8] = b"alice";
+    const BOB: &[u8] = b"bob";
+
+    fn keys(seed: u8, count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|i| vec![seed.wrapping_add(i); 32]).collect()
+    }
+
+    #[test]
+    fn both_sides_see_the_same_safety_number() {
+        let alice = fingerprint(ALICE, &keys(1, 2), BOB, &keys(9, 1));
+        let bob = fingerprint(BOB, &keys(9, 1), ALICE, &keys(1, 2));
+
+        assert_eq!(alice.safety_number, bob.safety_number);
+        assert_eq!(alice.safety_number.len(), 60);
+        assert!(alice.safety_number.bytes().all(|b| b.is_ascii_digit()));
+        assert!(matches_scanned(&alice.scannable, &bob.scannable).unwrap());
+    }
+
+    #[test]
+    fn device_order_does_not_matter() {
+        let mut reordered = keys(1, 3);
+        reordered.reverse();
+        assert_eq!(user_hash(ALICE, &keys(1, 3)), user_hash(ALICE, &reordered));
+    }
+
+    #[test]
+    fn changes_when_a_key_changes() {
+        let before = fingerprint(ALICE, &keys(1, 1), BOB, &keys(9, 1));
+        let after = fingerprint(ALICE, &keys(1, 1), BOB, &keys(9, 2));
+
+        assert_ne!(before.safety_number, after.safety_number);
+        let bob_after = fingerprint(BOB, &keys(9, 2), ALICE, &keys(1, 1));
+        assert!(!matches_scanned(&before.scannable, &bob_after.scannable).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_payload() {
+        let alice = fingerprint(ALICE, &keys(1, 1), BOB, &keys(9, 1));
+        assert!(matches_scanned(&alice.scannable, &alice.scannable[..10]).is_err());
+    }
+}
@@ -1 +1,8 @@
+pub mod fingerprint;
+pub mod ratchet;
+pub mod sealed_sender;
+pub mod sender_key;
+pub mod session;
 pub mod wrapper;
+pub mod x3dh;
+pub mod xeddsa;
@@ -0,0 +1,356 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Result};
+use hkdf::hmac::{Hmac, Mac};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::x3dh::dh;
+
+/// Most message keys a single header may force us to derive and stash.
+pub const MAX_SKIP: u32 = 1000;
+/// Oldest skipped keys are dropped beyond this so a session record cannot grow unbounded.
+const MAX_STORED_SKIPPED_KEYS: usize = 2000;
+
+const ROOT_KDF_INFO: &[u8] = b"chat-rs Ratchet";
+const MESSAGE_KDF_INFO: &[u8] = b"chat-rs MessageKeys";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageHeader {
+    pub ratchet_key: [u8; 32],
+    pub previous_chain_length: u32,
+    pub message_number: u32,
+}
+
+impl MessageHeader {
+    fn encode(&self) -> [u8; 40] {
+        let mut out = [0u8; 40];
+        out[..32].copy_from_slice(&self.ratchet_key);
+        out[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        out[36..].copy_from_slice(&self.message_number.to_be_bytes());
+        out
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RatchetMessage {
+    pub header: MessageHeader,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    ratchet_key: [u8; 32],
+    message_number: u32,
+    message_key: [u8; 32],
+}
+
+/// Double Ratchet state for one side of a session.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RatchetState {
+    sending_secret: [u8; 32],
+    sending_public: [u8; 32],
+    receiving_key: Option<[u8; 32]>,
+    root_key: [u8; 32],
+    sending_chain: Option<[u8; 32]>,
+    receiving_chain: Option<[u8; 32]>,
+    sent_count: u32,
+    received_count: u32,
+    previous_sending_count: u32,
+    skipped: VecDeque<SkippedKey>,
+}
+
+impl RatchetState {
+    /// Initiator starts with a sending chain towards the responder's signed prekey.
+    pub fn init_initiator(shared_secret: [u8; 32], their_ratchet_key: [u8; 32]) -> Result<Self> {
+        let sending_secret = StaticSecret::random_from_rng(OsRng);
+        let dh_out = dh(&sending_secret, &PublicKey::from(their_ratchet_key))?;
+        let (root_key, sending_chain) = kdf_root(&shared_secret, &dh_out);
+
+        Ok(Self {
+            sending_public: PublicKey::from(&sending_secret).to_bytes(),
+            sending_secret: sending_secret.to_bytes(),
+            receiving_key: Some(their_ratchet_key),
+            root_key,
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_sending_count: 0,
+            skipped: VecDeque::new(),
+        })
+    }
+
+    /// Responder reuses its signed prekey as the first ratchet key and can only send
+    /// once the initiator's first message has been decrypted.
+    pub fn init_responder(shared_secret: [u8; 32], our_ratchet_secret: [u8; 32]) -> Self {
+        let sending_secret = StaticSecret::from(our_ratchet_secret);
+
+        Self {
+            sending_public: PublicKey::from(&sending_secret).to_bytes(),
+            sending_secret: sending_secret.to_bytes(),
+            receiving_key: None,
+            root_key: shared_secret,
+            sending_chain: None,
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_sending_count: 0,
+            skipped: VecDeque::new(),
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8], associated_data: &[u8]) -> Result<RatchetMessage> {
+        let chain_key = self
+            .sending_chain
+            .ok_or_else(|| anyhow!("Cannot send before the first message has been received"))?;
+        let (next_chain, message_key) = kdf_chain(&chain_key);
+
+        let header = MessageHeader {
+            ratchet_key: self.sending_public,
+            previous_chain_length: self.previous_sending_count,
+            message_number: self.sent_count,
+        };
+        let ciphertext = seal(&message_key, plaintext, associated_data, &header)?;
+
+        self.sending_chain = Some(next_chain);
+        self.sent_count += 1;
+        Ok(RatchetMessage { header, ciphertext })
+    }
+
+    /// Decrypt `message`, leaving the state untouched if it is rejected.
+    pub fn decrypt(&mut self, message: &RatchetMessage, associated_data: &[u8]) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(message, associated_data)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn decrypt_in_place(
+        &mut self,
+        message: &RatchetMessage,
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>> {
+        let header = &message.header;
+
+        if let Some(pos) = self.skipped.iter().position(|k| {
+            k.ratchet_key == header.ratchet_key && k.message_number == header.message_number
+        }) {
+            let skipped = self.skipped.remove(pos).expect("position is in bounds");
+            return open(
+                &skipped.message_key,
+                &message.ciphertext,
+                associated_data,
+                header,
+            );
+        }
+
+        if self.receiving_key != Some(header.ratchet_key) || self.receiving_chain.is_none() {
+            self.skip_message_keys(header.previous_chain_length)?;
+            self.step(header.ratchet_key)?;
+        } else if header.message_number < self.received_count {
+            bail!("Duplicate or expired message");
+        }
+
+        self.skip_message_keys(header.message_number)?;
+        let chain_key = self
+            .receiving_chain
+            .expect("receiving chain exists after ratchet step");
+        let (next_chain, message_key) = kdf_chain(&chain_key);
+        self.receiving_chain = Some(next_chain);
+        self.received_count += 1;
+
+        open(&message_key, &message.ciphertext, associated_data, header)
+    }
+
+    /// Stash keys for messages of the current receiving chain that have not arrived yet.
+    fn skip_message_keys(&mut self, until: u32) -> Result<()> {
+        let (Some(mut chain_key), Some(ratchet_key)) = (self.receiving_chain, self.receiving_key)
+        else {
+            return Ok(());
+        };
+        if until.saturating_sub(self.received_count) > MAX_SKIP {
+            bail!("Too many skipped messages");
+        }
+
+        while self.received_count < until {
+            let (next_chain, message_key) = kdf_chain(&chain_key);
+            self.skipped.push_back(SkippedKey {
+                ratchet_key,
+                message_number: self.received_count,
+                message_key,
+            });
+            chain_key = next_chain;
+            self.received_count += 1;
+        }
+        self.receiving_chain = Some(chain_key);
+
+        while self.skipped.len() > MAX_STORED_SKIPPED_KEYS {
+            self.skipped.pop_front();
+        }
+        Ok(())
+    }
+
+    /// DH ratchet step on receipt of a new ratchet key from the remote side.
+    fn step(&mut self, their_ratchet_key: [u8; 32]) -> Result<()> {
+        let their_public = PublicKey::from(their_ratchet_key);
+
+        self.previous_sending_count = self.sent_count;
+        self.sent_count = 0;
+        self.received_count = 0;
+        self.receiving_key = Some(their_ratchet_key);
+
+        let dh_out = dh(&StaticSecret::from(self.sending_secret), &their_public)?;
+        let (root_key, receiving_chain) = kdf_root(&self.root_key, &dh_out);
+        self.receiving_chain = Some(receiving_chain);
+
+        let sending_secret = StaticSecret::random_from_rng(OsRng);
+        let dh_out = dh(&sending_secret, &their_public)?;
+        let (root_key, sending_chain) = kdf_root(&root_key, &dh_out);
+        self.root_key = root_key;
+        self.sending_chain = Some(sending_chain);
+        self.sending_public = PublicKey::from(&sending_secret).to_bytes();
+        self.sending_secret = sending_secret.to_bytes();
+        Ok(())
+    }
+}
+
+fn kdf_root(root_key: &[u8; 32], dh_out: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_out)
+        .expand(ROOT_KDF_INFO, &mut okm)
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+
+    let (mut root, mut chain) = ([0u8; 32], [0u8; 32]);
+    root.copy_from_slice(&okm[..32]);
+    chain.copy_from_slice(&okm[32..]);
+    (root, chain)
+}
+
+/// Returns `(next_chain_key, message_key)`.
+pub(crate) fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hmac = |input: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[input]);
+        mac.finalize().into_bytes().into()
+    };
+    (hmac(0x02), hmac(0x01))
+}
+
+pub(crate) fn message_cipher(message_key: &[u8; 32]) -> (Aes256Gcm, [u8; 12]) {
+    let mut okm = [0u8; 44];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(MESSAGE_KDF_INFO, &mut okm)
+        .expect("44 bytes is a valid HKDF-SHA256 output length");
+
+    let cipher = Aes256Gcm::new_from_slice(&okm[..32]).expect("AES-256 key is 32 bytes");
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&okm[32..]);
+    (cipher, nonce)
+}
+
+fn seal(
+    message_key: &[u8; 32],
+    plaintext: &[u8],
+    associated_data: &[u8],
+    header: &MessageHeader,
+) -> Result<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.encode()].concat();
+    cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| anyhow!("Encryption failed"))
+}
+
+fn open(
+    message_key: &[u8; 32],
+    ciphertext: &[u8],
+    associated_data: &[u8],
+    header: &MessageHeader,
+) -> Result<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.encode()].concat();
+    cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| anyhow!("Message authentication failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AD: &[u8] = b"alice-identity|bob-identity";
+
+    fn pair() -> (RatchetState, RatchetState) {
+        let shared_secret = [7u8; 32];
+        let bob_ratchet = StaticSecret::random_from_rng(OsRng);
+        let alice =
+            RatchetState::init_initiator(shared_secret, PublicKey::from(&bob_ratchet).to_bytes())
+                .unwrap();
+        let bob = RatchetState::init_responder(shared_secret, bob_ratchet.to_bytes());
+        (alice, bob)
+    }
+
+    #[test]
+    fn kdf_chain_separates_message_and_chain_keys() {
+        let (next, message_key) = kdf_chain(&[1u8; 32]);
+        assert_ne!(next, message_key);
+        assert_eq!(kdf_chain(&[1u8; 32]), (next, message_key));
+    }
+
+    #[test]
+    fn responder_cannot_send_first() {
+        let (_, mut bob) = pair();
+        assert!(bob.encrypt(b"hi", AD).is_err());
+    }
+
+    #[test]
+    fn rejects_gap_larger_than_max_skip() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.encrypt(b"0", AD).unwrap();
+        bob.decrypt(&first, AD).unwrap();
+
+        let mut far = alice.encrypt(b"far", AD).unwrap();
+        far.header.message_number = MAX_SKIP + 2;
+        assert!(bob.decrypt(&far, AD).is_err());
+    }
+
+    #[test]
+    fn rejected_message_leaves_state_untouched() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.encrypt(b"0", AD).unwrap();
+        let second = alice.encrypt(b"1", AD).unwrap();
+
+        let mut tampered = second.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(bob.decrypt(&tampered, AD).is_err());
+
+        assert_eq!(bob.decrypt(&first, AD).unwrap(), b"0");
+        assert_eq!(bob.decrypt(&second, AD).unwrap(), b"1");
+    }
+
+    #[test]
+    fn header_is_authenticated() {
+        let (mut alice, mut bob) = pair();
+        let mut message = alice.encrypt(b"0", AD).unwrap();
+        message.header.previous_chain_length = 5;
+        assert!(bob.decrypt(&message, AD).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, Result};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::wrapper::IdentityKeyPair;
+use super::x3dh::{dh, key_bytes};
+use super::xeddsa;
+
+const SEALED_SENDER_VERSION: u8 = 1;
+const SEALED_SENDER_INFO: &[u8] = b"chat-rs SealedSender";
+const SERVER_KEY_INFO: &[u8] = b"chat-rs SenderCertificate";
+
+/// The server's certificate-signing key. Clients pin `public_key` as their trust root.
+#[derive(Clone)]
+pub struct ServerKey {
+    pub private_key: [u8; 32],
+    pub public_key: [u8; 32],
+}
+
+impl ServerKey {
+    /// Derive the key deterministically from a configured secret, so every server
+    /// instance signs with the same key.
+    pub fn from_secret(secret: &[u8]) -> Self {
+        let mut private_key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, secret)
+            .expand(SERVER_KEY_INFO, &mut private_key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        let secret = StaticSecret::from(private_key);
+        Self {
+            private_key: secret.to_bytes(),
+            public_key: PublicKey::from(&secret).to_bytes(),
+        }
+    }
+}
+
+/// Server-signed statement that a device owns an identity key, carried inside sealed
+/// envelopes so the recipient (but not the server) learns who sent a message.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderCertificate {
+    pub sender_user_id: Uuid,
+    pub sender_device_id: i64,
+    pub identity_key: Vec<u8>,
+    pub expires_at: i64,
+    pub signature: Vec<u8>,
+}
+
+impl SenderCertificate {
+    /// Issue a certificate signed with the server's X25519 key (via XEdDSA).
+    pub fn issue(
+        server_private_key: &[u8; 32],
+        sender_user_id: Uuid,
+        sender_device_id: i64,
+        identity_key: Vec<u8>,
+        expires_at: i64,
+    ) -> Self {
+        let mut certificate = Self {
+            sender_user_id,
+            sender_device_id,
+            identity_key,
+            expires_at,
+            signature: Vec::new(),
+        };
+        certificate.signature =
+            xeddsa::sign(server_private_key, &certificate.signed_bytes()).to_vec();
+        certificate
+    }
+
+    /// Check the server signature against `trust_root` and that the certificate is unexpired.
+    pub fn verify(&self, trust_root: &[u8; 32], now: i64) -> Result<()> {
+        xeddsa::verify(trust_root, &self.signed_bytes(), &self.signature)
+            .map_err(|_| anyhow!("Sender certificate signature is invalid"))?;
+        if now >= self.expires_at {
+            bail!("Sender certificate has expired");
+        }
+        Ok(())
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 + self.identity_key.len());
+        bytes.extend_from_slice(self.sender_user_id.as_bytes());
+        bytes.extend_from_slice(&self.sender_device_id.to_be_bytes());
+        bytes.extend_from_slice(&self.expires_at.to_be_bytes());
+        bytes.extend_from_slice(&self.identity_key);
+        bytes
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedContent {
+    certificate: SenderCertificate,
+    content: Vec<u8>,
+}
+
+/// Wrap `content` (normally a session `CiphertextMessage`) and the sender certificate so
+/// only the holder of `recipient_identity_key` can see either.
+pub fn seal(
+    certificate: &SenderCertificate,
+    content: &[u8],
+    recipient_identity_key: &[u8],
+) -> Result<Vec<u8>> {
+    let recipient = PublicKey::from(key_bytes(recipient_identity_key)?);
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+
+    let cipher = envelope_cipher(
+        &dh(&ephemeral, &recipient)?,
+        &ephemeral_public,
+        recipient.as_bytes(),
+    );
+    let plaintext = serde_json::to_vec(&SealedContent {
+        certificate: certificate.clone(),
+        content: content.to_vec(),
+    })?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&[0u8; 12]), plaintext.as_slice())
+        .map_err(|_| anyhow!("Encryption failed"))?;
+
+    let mut sealed = Vec::with_capacity(1 + 32 + ciphertext.len());
+    sealed.push(SEALED_SENDER_VERSION);
+    sealed.extend_from_slice(&ephemeral_public);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Open a sealed envelope and validate its sender certificate.
+///
+/// The caller must still check that `certificate.identity_key` matches the identity key of
+/// the session that decrypts the returned content; otherwise a sender could claim any
+/// certificate it holds.
+pub fn unseal(
+    our_identity: &IdentityKeyPair,
+    sealed: &[u8],
+    trust_root: &[u8; 32],
+    now: i64,
+) -> Result<(SenderCertificate, Vec<u8>)> {
+    if sealed.len() < 33 {
+        bail!("Sealed message is too short");
+    }
+    if sealed[0] != SEALED_SENDER_VERSION {
+        bail!("Unsupported sealed sender version {}", sealed[0]);
+    }
+    let ephemeral_public = key_bytes(&sealed[1..33])?;
+
+    let our_secret = StaticSecret::from(key_bytes(&our_identity.private_key)?);
+    let cipher = envelope_cipher(
+        &dh(&our_secret, &PublicKey::from(ephemeral_public))?,
+        &ephemeral_public,
+        &key_bytes(&our_identity.public_key)?,
+    );
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&[0u8; 12]), &sealed[33..])
+        .map_err(|_| anyhow!("Sealed message authentication failed"))?;
+    let SealedContent {
+        certificate,
+        content,
+    } = serde_json::from_slice(&plaintext)?;
+
+    certificate.verify(trust_root, now)?;
+    Ok((certificate, content))
+}
+
+/// The key is unique per envelope (fresh ephemeral), so a fixed nonce is safe.
+fn envelope_cipher(
+    shared: &[u8; 32],
+    ephemeral_public: &[u8; 32],
+    recipient: &[u8; 32],
+) -> Aes256Gcm {
+    let salt = [ephemeral_public.as_slice(), recipient.as_slice()].concat();
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(SEALED_SENDER_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Aes256Gcm::new_from_slice(&key).expect("AES-256 key is 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::wrapper::generate_identity_keypair;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn server_key() -> ([u8; 32], [u8; 32]) {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        (secret.to_bytes(), PublicKey::from(&secret).to_bytes())
+    }
+
+    #[test]
+    fn server_key_is_stable_for_a_secret() {
+        let key = ServerKey::from_secret(b"secret");
+        assert_eq!(key.public_key, ServerKey::from_secret(b"secret").public_key);
+        assert_ne!(key.public_key, ServerKey::from_secret(b"other").public_key);
+
+        let certificate =
+            SenderCertificate::issue(&key.private_key, Uuid::new_v4(), 1, vec![9; 32], NOW + 60);
+        assert!(certificate.verify(&key.public_key, NOW).is_ok());
+    }
+
+    #[test]
+    fn recipient_learns_sender_from_sealed_envelope() {
+        let (server_private, trust_root) = server_key();
+        let alice = generate_identity_keypair().unwrap();
+        let bob = generate_identity_keypair().unwrap();
+        let certificate = SenderCertificate::issue(
+            &server_private,
+            Uuid::new_v4(),
+            7,
+            alice.public_key.clone(),
+            NOW + 60,
+        );
+
+        let sealed = seal(&certificate, b"ciphertext", &bob.public_key).unwrap();
+        let (sender, content) = unseal(&bob, &sealed, &trust_root, NOW).unwrap();
+
+        assert_eq!(sender, certificate);
+        assert_eq!(content, b"ciphertext");
+    }
+
+    #[test]
+    fn only_the_recipient_can_unseal() {
+        let (server_private, trust_root) = server_key();
+        let alice = generate_identity_keypair().unwrap();
+        let bob = generate_identity_keypair().unwrap();
+        let mallory = generate_identity_keypair().unwrap();
+        let certificate = SenderCertificate::issue(
+            &server_private,
+            Uuid::new_v4(),
+            1,
+            alice.public_key,
+            NOW + 60,
+        );
+
+        let sealed = seal(&certificate, b"secret", &bob.public_key).unwrap();
+        assert!(unseal(&mallory, &sealed, &trust_root, NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_or_foreign_certificates() {
+        let (server_private, trust_root) = server_key();
+        let (other_server, _) = server_key();
+        let alice = generate_identity_keypair().unwrap();
+        let bob = generate_identity_keypair().unwrap();
+
+        let expired = SenderCertificate::issue(
+            &server_private,
+            Uuid::new_v4(),
+            1,
+            alice.public_key.clone(),
+            NOW,
+        );
+        let sealed = seal(&expired, b"late", &bob.public_key).unwrap();
+        assert!(unseal(&bob, &sealed, &trust_root, NOW).is_err());
+
+        let forged =
+            SenderCertificate::issue(&other_server, Uuid::new_v4(), 1, alice.public_key, NOW + 60);
+        let sealed = seal(&forged, b"forged", &bob.public_key).unwrap();
+        assert!(unseal(&bob, &sealed, &trust_root, NOW).is_err());
+    }
+
+    #[test]
+    fn certificate_fields_are_signed() {
+        let (server_private, trust_root) = server_key();
+        let alice = generate_identity_keypair().unwrap();
+        let mut certificate = SenderCertificate::issue(
+            &server_private,
+            Uuid::new_v4(),
+            1,
+            alice.public_key,
+            NOW + 60,
+        );
+
+        certificate.sender_device_id = 2;
+        assert!(certificate.verify(&trust_root, NOW).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::Nonce;
+use anyhow::{anyhow, bail, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::ratchet::{kdf_chain, message_cipher};
+use super::xeddsa;
+
+/// Most message keys a single group message may force a receiver to derive.
+pub const MAX_SENDER_KEY_SKIP: u32 = 2000;
+/// Oldest skipped keys are dropped beyond this so a record cannot grow unbounded.
+const MAX_STORED_SKIPPED_KEYS: usize = 2000;
+
+/// Everything a group member needs to decrypt one sender's messages from `iteration`
+/// onwards. Sent to each member device over its pairwise session, never in the clear.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderKeyDistributionMessage {
+    pub key_id: u32,
+    pub iteration: u32,
+    pub chain_key: [u8; 32],
+    pub signing_key: [u8; 32],
+}
+
+/// A group message, encrypted once for every member and signed by the sender.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SenderKeyMessage {
+    pub key_id: u32,
+    pub iteration: u32,
+    pub ciphertext: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl SenderKeyMessage {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.ciphertext.len());
+        bytes.extend_from_slice(&self.key_id.to_be_bytes());
+        bytes.extend_from_slice(&self.iteration.to_be_bytes());
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    iteration: u32,
+    message_key: [u8; 32],
+}
+
+/// One sender's symmetric chain within a group. The sender holds the signing private
+/// key; receivers built from a distribution message can only verify and decrypt.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SenderKeyState {
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; 32],
+    signing_public: [u8; 32],
+    signing_private: Option<[u8; 32]>,
+    skipped: VecDeque<SkippedKey>,
+}
+
+impl SenderKeyState {
+    /// Fresh sending state. Generate a new one (and redistribute it) whenever a member
+    /// leaves, so they cannot read later messages.
+    pub fn generate() -> Self {
+        let signing = StaticSecret::random_from_rng(OsRng);
+        let mut chain_key = [0u8; 32];
+        OsRng.fill_bytes(&mut chain_key);
+
+        Self {
+            key_id: OsRng.next_u32(),
+            iteration: 0,
+            chain_key,
+            signing_public: PublicKey::from(&signing).to_bytes(),
+            signing_private: Some(signing.to_bytes()),
+            skipped: VecDeque::new(),
+        }
+    }
+
+    /// Receiving state for another member's sender key.
+    pub fn from_distribution(message: &SenderKeyDistributionMessage) -> Self {
+        Self {
+            key_id: message.key_id,
+            iteration: message.iteration,
+            chain_key: message.chain_key,
+            signing_public: message.signing_key,
+            signing_private: None,
+            skipped: VecDeque::new(),
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Distribution for the current position in the chain; earlier messages stay unreadable.
+    pub fn distribution_message(&self) -> SenderKeyDistributionMessage {
+        SenderKeyDistributionMessage {
+            key_id: self.key_id,
+            iteration: self.iteration,
+            chain_key: self.chain_key,
+            signing_key: self.signing_public,
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<SenderKeyMessage> {
+        let signing_private = self
+            .signing_private
+            .ok_or_else(|| anyhow!("Cannot send with another member's sender key"))?;
+
+        let (next_chain, message_key) = kdf_chain(&self.chain_key);
+        let mut message = SenderKeyMessage {
+            key_id: self.key_id,
+            iteration: self.iteration,
+            ciphertext: Vec::new(),
+            signature: Vec::new(),
+        };
+        let (cipher, nonce) = message_cipher(&message_key);
+        message.ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &associated_data(self.key_id, self.iteration),
+                },
+            )
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        message.signature = xeddsa::sign(&signing_private, &message.signed_bytes()).to_vec();
+
+        self.chain_key = next_chain;
+        self.iteration += 1;
+        Ok(message)
+    }
+
+    /// Verify and decrypt; the state is only advanced if the message is authentic.
+    pub fn decrypt(&mut self, message: &SenderKeyMessage) -> Result<Vec<u8>> {
+        if message.key_id != self.key_id {
+            bail!("Unknown sender key {}", message.key_id);
+        }
+        xeddsa::verify(
+            &self.signing_public,
+            &message.signed_bytes(),
+            &message.signature,
+        )
+        .map_err(|_| anyhow!("Sender key signature is invalid"))?;
+
+        let mut next = self.clone();
+        let message_key = next.message_key(message.iteration)?;
+        let (cipher, nonce) = message_cipher(&message_key);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &message.ciphertext,
+                    aad: &associated_data(message.key_id, message.iteration),
+                },
+            )
+            .map_err(|_| anyhow!("Sender key message authentication failed"))?;
+
+        *self = next;
+        Ok(plaintext)
+    }
+
+    pub fn to_record(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_record(record: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(record)?)
+    }
+
+    fn message_key(&mut self, iteration: u32) -> Result<[u8; 32]> {
+        if iteration < self.iteration {
+            let position = self
+                .skipped
+                .iter()
+                .position(|k| k.iteration == iteration)
+                .ok_or_else(|| anyhow!("Duplicate or expired sender key message"))?;
+            return Ok(self
+                .skipped
+                .remove(position)
+                .expect("position is in range")
+                .message_key);
+        }
+        if iteration - self.iteration > MAX_SENDER_KEY_SKIP {
+            bail!("Too many skipped sender key messages");
+        }
+
+        while self.iteration < iteration {
+            let (next_chain, message_key) = kdf_chain(&self.chain_key);
+            self.skipped.push_back(SkippedKey {
+                iteration: self.iteration,
+                message_key,
+            });
+            if self.skipped.len() > MAX_STORED_SKIPPED_KEYS {
+                self.skipped.pop_front();
+            }
+            self.chain_key = next_chain;
+            self.iteration += 1;
+        }
+
+        let (next_chain, message_key) = kdf_chain(&self.chain_key);
+        self.chain_key = next_chain;
+        self.iteration += 1;
+        Ok(message_key)
+    }
+}
+
+fn associated_data(key_id: u32, iteration: u32) -> [u8; 8] {
+    let mut aad = [0u8; 8];
+    aad[..4].copy_from_slice(&key_id.to_be_bytes());
+    aad[4..].copy_from_slice(&iteration.to_be_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_decrypt_out_of_order() {
+        let mut alice = SenderKeyState::generate();
+        let mut bob = SenderKeyState::from_distribution(&alice.distribution_message());
+
+        let first = alice.encrypt(b"first").unwrap();
+        let second = alice.encrypt(b"second").unwrap();
+        let third = alice.encrypt(b"third").unwrap();
+
+        assert_eq!(bob.decrypt(&third).unwrap(), b"third");
+        assert_eq!(bob.decrypt(&first).unwrap(), b"first");
+        assert_eq!(bob.decrypt(&second).unwrap(), b"second");
+        assert!(bob.decrypt(&second).is_err());
+    }
+
+    #[test]
+    fn late_joiner_cannot_read_earlier_messages() {
+        let mut alice = SenderKeyState::generate();
+        let before = alice.encrypt(b"before").unwrap();
+
+        let mut carol = SenderKeyState::from_distribution(&alice.distribution_message());
+        let after = alice.encrypt(b"after").unwrap();
+
+        assert!(carol.decrypt(&before).is_err());
+        assert_eq!(carol.decrypt(&after).unwrap(), b"after");
+    }
+
+    #[test]
+    fn rotated_key_locks_out_old_members() {
+        let mut alice = SenderKeyState::generate();
+        let mut mallory = SenderKeyState::from_distribution(&alice.distribution_message());
+
+        alice = SenderKeyState::generate();
+        let message = alice.encrypt(b"after rotation").unwrap();
+        assert!(mallory.decrypt(&message).is_err());
+    }
+
+    #[test]
+    fn receivers_cannot_forge_messages() {
+        let mut alice = SenderKeyState::generate();
+        let mut bob = SenderKeyState::from_distribution(&alice.distribution_message());
+        let mut eve = SenderKeyState::from_distribution(&alice.distribution_message());
+        assert!(eve.encrypt(b"forged").is_err());
+
+        let mut message = alice.encrypt(b"hello").unwrap();
+        message.ciphertext[0] ^= 1;
+        assert!(bob.decrypt(&message).is_err());
+    }
+
+    #[test]
+    fn state_survives_a_record_round_trip() {
+        let mut alice = SenderKeyState::generate();
+        let bob = SenderKeyState::from_distribution(&alice.distribution_message());
+        let mut bob = SenderKeyState::from_record(&bob.to_record().unwrap()).unwrap();
+
+        let message = alice.encrypt(b"persisted").unwrap();
+        assert_eq!(bob.decrypt(&message).unwrap(), b"persisted");
+    }
+}
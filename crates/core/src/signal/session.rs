@@ -0,0 +1,329 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::ratchet::{RatchetMessage, RatchetState};
+use super::wrapper::{IdentityKeyPair, PreKey, SignedPreKey};
+use super::x3dh::{self, key_bytes, PreKeyBundle};
+
+/// First messages of a session carry the X3DH parameters until the responder answers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreKeyMessage {
+    pub identity_key: Vec<u8>,
+    pub base_key: [u8; 32],
+    pub signed_prekey_id: u32,
+    pub one_time_prekey_id: Option<u32>,
+    pub message: RatchetMessage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum CiphertextMessage {
+    PreKey(PreKeyMessage),
+    Whisper(RatchetMessage),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct PendingPreKey {
+    signed_prekey_id: u32,
+    one_time_prekey_id: Option<u32>,
+}
+
+/// An established pairwise session, stored in `signal_sessions.session_record`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    local_identity_key: Vec<u8>,
+    remote_identity_key: Vec<u8>,
+    associated_data: Vec<u8>,
+    base_key: [u8; 32],
+    pending_prekey: Option<PendingPreKey>,
+    ratchet: RatchetState,
+}
+
+impl Session {
+    /// Start a session with a remote device from its fetched prekey bundle.
+    pub fn initiate(our_identity: &IdentityKeyPair, bundle: &PreKeyBundle) -> Result<Self> {
+        let init = x3dh::initiate(our_identity, bundle)?;
+        let ratchet =
+            RatchetState::init_initiator(init.agreement.shared_secret, init.signed_prekey)?;
+
+        Ok(Self {
+            local_identity_key: our_identity.public_key.clone(),
+            remote_identity_key: bundle.identity_key.clone(),
+            associated_data: init.agreement.associated_data,
+            base_key: init.base_key,
+            pending_prekey: Some(PendingPreKey {
+                signed_prekey_id: init.signed_prekey_id,
+                one_time_prekey_id: init.one_time_prekey_id,
+            }),
+            ratchet,
+        })
+    }
+
+    /// Accept a session from an incoming pre-key message, returning its plaintext.
+    ///
+    /// The caller looks up the private prekeys named in `message` and must delete the
+    /// one-time prekey once this succeeds.
+    pub fn respond(
+        our_identity: &IdentityKeyPair,
+        signed_prekey: &SignedPreKey,
+        one_time_prekey: Option<&PreKey>,
+        message: &PreKeyMessage,
+    ) -> Result<(Self, Vec<u8>)> {
+        if message.signed_prekey_id != signed_prekey.id {
+            bail!("Signed prekey {} does not match message", signed_prekey.id);
+        }
+        if message.one_time_prekey_id != one_time_prekey.map(|pk| pk.id) {
+            bail!("One-time prekey does not match message");
+        }
+
+        let agreement = x3dh::respond(
+            our_identity,
+            signed_prekey,
+            one_time_prekey,
+            &message.identity_key,
+            &message.base_key,
+        )?;
+        let ratchet = RatchetState::init_responder(
+            agreement.shared_secret,
+            key_bytes(&signed_prekey.private_key)?,
+        );
+
+        let mut session = Self {
+            local_identity_key: our_identity.public_key.clone(),
+            remote_identity_key: message.identity_key.clone(),
+            associated_data: agreement.associated_data,
+            base_key: message.base_key,
+            pending_prekey: None,
+            ratchet,
+        };
+        let plaintext = session
+            .ratchet
+            .decrypt(&message.message, &session.associated_data)?;
+        Ok((session, plaintext))
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<CiphertextMessage> {
+        let message = self.ratchet.encrypt(plaintext, &self.associated_data)?;
+
+        Ok(match &self.pending_prekey {
+            Some(pending) => CiphertextMessage::PreKey(PreKeyMessage {
+                identity_key: self.local_identity_key.clone(),
+                base_key: self.base_key,
+                signed_prekey_id: pending.signed_prekey_id,
+                one_time_prekey_id: pending.one_time_prekey_id,
+                message,
+            }),
+            None => CiphertextMessage::Whisper(message),
+        })
+    }
+
+    /// Decrypt a message for this session. Pre-key messages are accepted as long as they
+    /// belong to the same X3DH handshake, which happens when they arrive out of order.
+    pub fn decrypt(&mut self, message: &CiphertextMessage) -> Result<Vec<u8>> {
+        let message = match message {
+            CiphertextMessage::PreKey(prekey_message) => {
+                if prekey_message.base_key != self.base_key
+                    || prekey_message.identity_key != self.remote_identity_key
+                {
+                    bail!("Pre-key message belongs to a different session");
+                }
+                &prekey_message.message
+            }
+            CiphertextMessage::Whisper(message) => message,
+        };
+
+        let plaintext = self.ratchet.decrypt(message, &self.associated_data)?;
+        // The peer has our ratchet key now, so there is no need to keep resending X3DH data.
+        self.pending_prekey = None;
+        Ok(plaintext)
+    }
+
+    pub fn remote_identity_key(&self) -> &[u8] {
+        &self.remote_identity_key
+    }
+
+    pub fn to_record(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_record(record: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(record)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::wrapper::{create_signal_keys, SignalKeys};
+    use crate::signal::x3dh::PublicPreKey;
+
+    fn bundle(keys: &SignalKeys, with_one_time_prekey: bool) -> PreKeyBundle {
+        PreKeyBundle {
+            identity_key: keys.identity_key_pair.public_key.clone(),
+            signed_prekey_id: keys.signed_prekey.id,
+            signed_prekey: keys.signed_prekey.public_key.clone(),
+            signed_prekey_signature: keys.signed_prekey.signature.clone(),
+            one_time_prekey: with_one_time_prekey.then(|| PublicPreKey {
+                id: keys.one_time_prekeys[0].id,
+                public_key: keys.one_time_prekeys[0].public_key.clone(),
+            }),
+        }
+    }
+
+    fn prekey_message(message: CiphertextMessage) -> PreKeyMessage {
+        match message {
+            CiphertextMessage::PreKey(message) => message,
+            CiphertextMessage::Whisper(_) => panic!("expected a pre-key message"),
+        }
+    }
+
+    /// Alice sends `order.len()` messages, Bob receives them in `order`, then Bob replies with
+    /// `bob_batch` messages delivered in reverse.
+    fn run_delivery_order(order: &[usize], bob_batch: usize) {
+        let alice_keys = create_signal_keys().unwrap();
+        let bob_keys = create_signal_keys().unwrap();
+
+        let mut alice =
+            Session::initiate(&alice_keys.identity_key_pair, &bundle(&bob_keys, true)).unwrap();
+        let outgoing: Vec<_> = (0..order.len())
+            .map(|i| alice.encrypt(format!("alice {i}").as_bytes()).unwrap())
+            .collect();
+
+        let first = prekey_message(outgoing[order[0]].clone());
+        let (mut bob, plaintext) = Session::respond(
+            &bob_keys.identity_key_pair,
+            &bob_keys.signed_prekey,
+            Some(&bob_keys.one_time_prekeys[0]),
+            &first,
+        )
+        .unwrap();
+        assert_eq!(plaintext, format!("alice {}", order[0]).as_bytes());
+
+        for &i in &order[1..] {
+            assert_eq!(
+                bob.decrypt(&outgoing[i]).unwrap(),
+                format!("alice {i}").as_bytes()
+            );
+        }
+
+        let replies: Vec<_> = (0..bob_batch)
+            .map(|i| bob.encrypt(format!("bob {i}").as_bytes()).unwrap())
+            .collect();
+        for (i, reply) in replies.iter().enumerate().rev() {
+            assert!(matches!(reply, CiphertextMessage::Whisper(_)));
+            assert_eq!(alice.decrypt(reply).unwrap(), format!("bob {i}").as_bytes());
+        }
+
+        assert!(matches!(
+            alice.encrypt(b"after reply").unwrap(),
+            CiphertextMessage::Whisper(_)
+        ));
+    }
+
+    #[test]
+    fn delivers_in_every_order() {
+        let vectors: &[&[usize]] = &[
+            &[0],
+            &[0, 1, 2, 3],
+            &[3, 2, 1, 0],
+            &[1, 0, 3, 2],
+            &[2, 0, 3, 1],
+            &[4, 0, 1, 3, 2],
+        ];
+        for order in vectors {
+            run_delivery_order(order, 3);
+        }
+    }
+
+    #[test]
+    fn out_of_order_across_ratchet_steps() {
+        let alice_keys = create_signal_keys().unwrap();
+        let bob_keys = create_signal_keys().unwrap();
+
+        let mut alice =
+            Session::initiate(&alice_keys.identity_key_pair, &bundle(&bob_keys, false)).unwrap();
+        let hello = prekey_message(alice.encrypt(b"hello").unwrap());
+        let (mut bob, _) = Session::respond(
+            &bob_keys.identity_key_pair,
+            &bob_keys.signed_prekey,
+            None,
+            &hello,
+        )
+        .unwrap();
+
+        // Bob's first chain: b0 arrives late, after Bob has already ratcheted again.
+        let b0 = bob.encrypt(b"b0").unwrap();
+        let b1 = bob.encrypt(b"b1").unwrap();
+        assert_eq!(alice.decrypt(&b1).unwrap(), b"b1");
+
+        let a1 = alice.encrypt(b"a1").unwrap();
+        assert_eq!(bob.decrypt(&a1).unwrap(), b"a1");
+
+        let b2 = bob.encrypt(b"b2").unwrap();
+        assert_eq!(alice.decrypt(&b2).unwrap(), b"b2");
+        assert_eq!(alice.decrypt(&b0).unwrap(), b"b0");
+
+        // A skipped key is consumed on use, so the same message cannot be replayed.
+        assert!(alice.decrypt(&b0).is_err());
+        assert!(alice.decrypt(&b2).is_err());
+    }
+
+    #[test]
+    fn skipped_keys_survive_session_record_round_trip() {
+        let alice_keys = create_signal_keys().unwrap();
+        let bob_keys = create_signal_keys().unwrap();
+
+        let mut alice =
+            Session::initiate(&alice_keys.identity_key_pair, &bundle(&bob_keys, true)).unwrap();
+        let m0 = prekey_message(alice.encrypt(b"m0").unwrap());
+        let m1 = alice.encrypt(b"m1").unwrap();
+        let m2 = alice.encrypt(b"m2").unwrap();
+
+        let (bob, _) = Session::respond(
+            &bob_keys.identity_key_pair,
+            &bob_keys.signed_prekey,
+            Some(&bob_keys.one_time_prekeys[0]),
+            &m0,
+        )
+        .unwrap();
+        let mut bob = Session::from_record(&bob.to_record().unwrap()).unwrap();
+        assert_eq!(bob.decrypt(&m2).unwrap(), b"m2");
+
+        let mut bob = Session::from_record(&bob.to_record().unwrap()).unwrap();
+        assert_eq!(bob.decrypt(&m1).unwrap(), b"m1");
+        assert_eq!(
+            bob.remote_identity_key(),
+            alice_keys.identity_key_pair.public_key
+        );
+    }
+
+    #[test]
+    fn rejects_bundle_with_forged_signature() {
+        let alice_keys = create_signal_keys().unwrap();
+        let bob_keys = create_signal_keys().unwrap();
+        let mallory_keys = create_signal_keys().unwrap();
+
+        let mut forged = bundle(&bob_keys, true);
+        forged.signed_prekey = mallory_keys.signed_prekey.public_key.clone();
+        assert!(Session::initiate(&alice_keys.identity_key_pair, &forged).is_err());
+    }
+
+    #[test]
+    fn responder_with_wrong_identity_cannot_decrypt() {
+        let alice_keys = create_signal_keys().unwrap();
+        let bob_keys = create_signal_keys().unwrap();
+        let mallory_keys = create_signal_keys().unwrap();
+
+        let mut alice =
+            Session::initiate(&alice_keys.identity_key_pair, &bundle(&bob_keys, true)).unwrap();
+        let hello = prekey_message(alice.encrypt(b"hello").unwrap());
+
+        assert!(Session::respond(
+            &mallory_keys.identity_key_pair,
+            &bob_keys.signed_prekey,
+            Some(&bob_keys.one_time_prekeys[0]),
+            &hello,
+        )
+        .is_err());
+    }
+}
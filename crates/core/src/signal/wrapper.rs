@@ -1,8 +1,10 @@
-use anyhow::Result;
-use ed25519_dalek::{Signature, Signer, SigningKey};
+use anyhow::{anyhow, Result};
 use rand::rngs::OsRng;
 use x25519_dalek::{PublicKey, StaticSecret};
 
+use super::xeddsa;
+
+/// An X25519 key pair, used directly for X3DH and via XEdDSA for signing.
 #[derive(Clone)]
 pub struct IdentityKeyPair {
     pub public_key: Vec<u8>,
@@ -33,12 +35,12 @@ pub struct SignalKeys {
 }
 
 pub fn generate_identity_keypair() -> Result<IdentityKeyPair> {
-    let signing_key = SigningKey::generate(&mut OsRng);
-    let verifying_key = signing_key.verifying_key();
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
 
     Ok(IdentityKeyPair {
-        public_key: verifying_key.to_bytes().to_vec(),
-        private_key: signing_key.to_bytes().to_vec(),
+        public_key: public.to_bytes().to_vec(),
+        private_key: secret.to_bytes().to_vec(),
     })
 }
 
@@ -57,14 +59,18 @@ pub fn generate_signed_prekey(
         .duration_since(std::time::UNIX_EPOCH)?
         .as_millis() as u64;
 
-    let signing_key = SigningKey::from_bytes(&identity_key_pair.private_key.as_slice().try_into()?);
-    let signature: Signature = signing_key.sign(public.as_bytes());
+    let identity_private: [u8; 32] = identity_key_pair
+        .private_key
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow!("identity private key must be 32 bytes"))?;
+    let signature = xeddsa::sign(&identity_private, public.as_bytes());
 
     Ok(SignedPreKey {
         id: signed_prekey_id,
         public_key: public.to_bytes().to_vec(),
         private_key: secret.to_bytes().to_vec(),
-        signature: signature.to_bytes().to_vec(),
+        signature: signature.to_vec(),
         timestamp,
     })
 }
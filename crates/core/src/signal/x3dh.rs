@@ -0,0 +1,145 @@
+use anyhow::{anyhow, bail, Result};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::wrapper::{IdentityKeyPair, PreKey, SignedPreKey};
+use super::xeddsa;
+
+const X3DH_INFO: &[u8] = b"chat-rs X3DH";
+
+/// Public keys of a remote device, as served by the prekey bundle endpoints.
+#[derive(Clone, Debug)]
+pub struct PreKeyBundle {
+    pub identity_key: Vec<u8>,
+    pub signed_prekey_id: u32,
+    pub signed_prekey: Vec<u8>,
+    pub signed_prekey_signature: Vec<u8>,
+    pub one_time_prekey: Option<PublicPreKey>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PublicPreKey {
+    pub id: u32,
+    pub public_key: Vec<u8>,
+}
+
+/// Secret both parties arrive at, plus the identity binding used as AEAD associated data.
+pub struct Agreement {
+    pub shared_secret: [u8; 32],
+    pub associated_data: Vec<u8>,
+}
+
+/// What the initiator must send alongside its first messages so the responder can
+/// reproduce the agreement.
+pub struct InitiatorAgreement {
+    pub agreement: Agreement,
+    pub base_key: [u8; 32],
+    pub signed_prekey_id: u32,
+    pub signed_prekey: [u8; 32],
+    pub one_time_prekey_id: Option<u32>,
+}
+
+/// Run X3DH against a remote bundle (Alice's side).
+pub fn initiate(
+    our_identity: &IdentityKeyPair,
+    bundle: &PreKeyBundle,
+) -> Result<InitiatorAgreement> {
+    let their_identity = key_bytes(&bundle.identity_key)?;
+    xeddsa::verify(
+        &their_identity,
+        &bundle.signed_prekey,
+        &bundle.signed_prekey_signature,
+    )
+    .map_err(|_| anyhow!("Signed prekey signature does not match identity key"))?;
+
+    let signed_prekey = PublicKey::from(key_bytes(&bundle.signed_prekey)?);
+    let their_identity_dh = PublicKey::from(their_identity);
+    let our_identity_dh = identity_secret(our_identity)?;
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+
+    let mut dh_outputs = vec![
+        dh(&our_identity_dh, &signed_prekey)?,
+        dh(&ephemeral, &their_identity_dh)?,
+        dh(&ephemeral, &signed_prekey)?,
+    ];
+    if let Some(one_time_prekey) = &bundle.one_time_prekey {
+        let one_time_prekey = PublicKey::from(key_bytes(&one_time_prekey.public_key)?);
+        dh_outputs.push(dh(&ephemeral, &one_time_prekey)?);
+    }
+
+    Ok(InitiatorAgreement {
+        agreement: Agreement {
+            shared_secret: derive_shared_secret(&dh_outputs),
+            associated_data: associated_data(&our_identity.public_key, &bundle.identity_key),
+        },
+        base_key: PublicKey::from(&ephemeral).to_bytes(),
+        signed_prekey_id: bundle.signed_prekey_id,
+        signed_prekey: signed_prekey.to_bytes(),
+        one_time_prekey_id: bundle.one_time_prekey.as_ref().map(|pk| pk.id),
+    })
+}
+
+/// Reproduce the initiator's agreement from our private prekeys (Bob's side).
+pub fn respond(
+    our_identity: &IdentityKeyPair,
+    signed_prekey: &SignedPreKey,
+    one_time_prekey: Option<&PreKey>,
+    their_identity_key: &[u8],
+    base_key: &[u8; 32],
+) -> Result<Agreement> {
+    let their_identity_dh = PublicKey::from(key_bytes(their_identity_key)?);
+    let base_key = PublicKey::from(*base_key);
+
+    let signed_prekey_secret = StaticSecret::from(key_bytes(&signed_prekey.private_key)?);
+    let mut dh_outputs = vec![
+        dh(&signed_prekey_secret, &their_identity_dh)?,
+        dh(&identity_secret(our_identity)?, &base_key)?,
+        dh(&signed_prekey_secret, &base_key)?,
+    ];
+    if let Some(one_time_prekey) = one_time_prekey {
+        let one_time_secret = StaticSecret::from(key_bytes(&one_time_prekey.private_key)?);
+        dh_outputs.push(dh(&one_time_secret, &base_key)?);
+    }
+
+    Ok(Agreement {
+        shared_secret: derive_shared_secret(&dh_outputs),
+        associated_data: associated_data(their_identity_key, &our_identity.public_key),
+    })
+}
+
+pub(crate) fn dh(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32]> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        bail!("Low-order public key");
+    }
+    Ok(shared.to_bytes())
+}
+
+pub(crate) fn key_bytes(key: &[u8]) -> Result<[u8; 32]> {
+    key.try_into()
+        .map_err(|_| anyhow!("Expected a 32-byte key, got {} bytes", key.len()))
+}
+
+fn identity_secret(identity: &IdentityKeyPair) -> Result<StaticSecret> {
+    Ok(StaticSecret::from(key_bytes(&identity.private_key)?))
+}
+
+fn derive_shared_secret(dh_outputs: &[[u8; 32]]) -> [u8; 32] {
+    // 32 0xFF bytes precede the DH outputs so the KDF input never collides with XEdDSA's.
+    let mut ikm = vec![0xFF; 32];
+    for output in dh_outputs {
+        ikm.extend_from_slice(output);
+    }
+
+    let mut shared_secret = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm)
+        .expand(X3DH_INFO, &mut shared_secret)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    shared_secret
+}
+
+fn associated_data(initiator_identity: &[u8], responder_identity: &[u8]) -> Vec<u8> {
+    [initiator_identity, responder_identity].concat()
+}
@@ -0,0 +1,109 @@
+use anyhow::{anyhow, Result};
+use curve25519_dalek::edwards::EdwardsPoint;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::{clamp_integer, Scalar};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+
+/// Sign `message` with an X25519 private key, producing a signature that verifies as
+/// Ed25519 against the key's Edwards form (XEdDSA).
+pub fn sign(private_key: &[u8; 32], message: &[u8]) -> [u8; 64] {
+    let mut random = [0u8; 64];
+    OsRng.fill_bytes(&mut random);
+    sign_with_random(private_key, message, &random)
+}
+
+/// Verify an XEdDSA signature against an X25519 public key.
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> Result<()> {
+    let verifying_key = VerifyingKey::from_bytes(&edwards_public_key(public_key)?)
+        .map_err(|_| anyhow!("Invalid public key"))?;
+    let signature = Signature::from_slice(signature).map_err(|_| anyhow!("Invalid signature"))?;
+
+    verifying_key
+        .verify_strict(message, &signature)
+        .map_err(|_| anyhow!("Signature verification failed"))
+}
+
+fn sign_with_random(private_key: &[u8; 32], message: &[u8], random: &[u8; 64]) -> [u8; 64] {
+    let k = Scalar::from_bytes_mod_order(clamp_integer(*private_key));
+    let mut public_key = EdwardsPoint::mul_base(&k).compress().to_bytes();
+
+    // The Montgomery form drops the Edwards sign bit, so verifiers assume it is zero;
+    // negate the private scalar whenever that would not hold.
+    let a = if public_key[31] & 0x80 != 0 { -k } else { k };
+    public_key[31] &= 0x7F;
+
+    let r = Scalar::from_bytes_mod_order_wide(
+        &Sha512::new()
+            .chain_update([0xFE])
+            .chain_update([0xFF; 31])
+            .chain_update(a.as_bytes())
+            .chain_update(message)
+            .chain_update(random)
+            .finalize()
+            .into(),
+    );
+    let big_r = EdwardsPoint::mul_base(&r).compress().to_bytes();
+
+    let h = Scalar::from_bytes_mod_order_wide(
+        &Sha512::new()
+            .chain_update(big_r)
+            .chain_update(public_key)
+            .chain_update(message)
+            .finalize()
+            .into(),
+    );
+    let s = r + h * a;
+
+    let mut signature = [0u8; 64];
+    signature[..32].copy_from_slice(&big_r);
+    signature[32..].copy_from_slice(s.as_bytes());
+    signature
+}
+
+/// Edwards encoding (sign bit zero) of a canonical Montgomery `u` coordinate.
+fn edwards_public_key(public_key: &[u8; 32]) -> Result<[u8; 32]> {
+    let point = MontgomeryPoint(*public_key);
+    let edwards = point
+        .to_edwards(0)
+        .ok_or_else(|| anyhow!("Invalid public key"))?;
+    // MontgomeryPoint equality compares field elements, so check the encoding itself.
+    if edwards.to_montgomery().to_bytes() != *public_key {
+        return Err(anyhow!("Invalid public key"));
+    }
+    Ok(edwards.compress().to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    #[test]
+    fn signature_is_bound_to_key_and_message() {
+        // Enough keys that both Edwards sign bits are exercised.
+        for _ in 0..32 {
+            let secret = StaticSecret::random_from_rng(OsRng);
+            let public = PublicKey::from(&secret).to_bytes();
+            let signature = sign(&secret.to_bytes(), b"message");
+
+            assert!(verify(&public, b"message", &signature).is_ok());
+            assert!(verify(&public, b"massage", &signature).is_err());
+
+            let other = PublicKey::from(&StaticSecret::random_from_rng(OsRng)).to_bytes();
+            assert!(verify(&other, b"message", &signature).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_non_canonical_public_key() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let mut public = PublicKey::from(&secret).to_bytes();
+        let signature = sign(&secret.to_bytes(), b"message");
+
+        public[31] |= 0x80;
+        assert!(verify(&public, b"message", &signature).is_err());
+    }
+}
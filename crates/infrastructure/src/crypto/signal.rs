@@ -1,8 +1,9 @@
-use anyhow::Result;
-use ed25519_dalek::{Signature, Signer, SigningKey};
+use anyhow::{anyhow, Result};
+use chat_core::signal::xeddsa;
 use rand::rngs::OsRng;
 use x25519_dalek::{PublicKey, StaticSecret};
 
+/// An X25519 key pair, used directly for X3DH and via XEdDSA for signing.
 #[derive(Clone)]
 pub struct IdentityKeyPair {
     pub public_key: Vec<u8>,
@@ -26,15 +27,15 @@ pub struct SignedPreKey {
 }
 
 pub fn generate_identity_keypair() -> Result<(IdentityKeyPair, Vec<u8>)> {
-    let signing_key = SigningKey::generate(&mut OsRng);
-    let verifying_key = signing_key.verifying_key();
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
 
     let key_pair = IdentityKeyPair {
-        public_key: verifying_key.to_bytes().to_vec(),
-        private_key: signing_key.to_bytes().to_vec(),
+        public_key: public.to_bytes().to_vec(),
+        private_key: secret.to_bytes().to_vec(),
     };
 
-    Ok((key_pair, verifying_key.to_bytes().to_vec()))
+    Ok((key_pair, public.to_bytes().to_vec()))
 }
 
 pub fn generate_registration_id() -> u32 {
@@ -52,14 +53,18 @@ pub fn generate_signed_prekey(
         .duration_since(std::time::UNIX_EPOCH)?
         .as_millis() as u64;
 
-    let signing_key = SigningKey::from_bytes(&identity_key_pair.private_key.as_slice().try_into()?);
-    let signature: Signature = signing_key.sign(public.as_bytes());
+    let identity_private: [u8; 32] = identity_key_pair
+        .private_key
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow!("identity private key must be 32 bytes"))?;
+    let signature = xeddsa::sign(&identity_private, public.as_bytes());
 
     Ok(SignedPreKey {
         id: signed_prekey_id,
         public_key: public.to_bytes().to_vec(),
         private_key: secret.to_bytes().to_vec(),
-        signature: signature.to_bytes().to_vec(),
+        signature: signature.to_vec(),
         timestamp,
     })
 }
@@ -78,3 +83,20 @@ pub fn generate_prekeys(start_id: u32, count: u32) -> Result<Vec<PreKey>> {
     }
     Ok(prekeys)
 }
+
+/// Verify that `signature` is the identity key's XEdDSA signature over a signed prekey's public key.
+pub fn verify_signed_prekey(
+    identity_key_public: &[u8],
+    signed_prekey_public: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let identity_key_public: [u8; 32] = identity_key_public
+        .try_into()
+        .map_err(|_| anyhow!("identity key must be 32 bytes"))?;
+    if signature.len() != 64 {
+        return Err(anyhow!("signature must be 64 bytes"));
+    }
+
+    xeddsa::verify(&identity_key_public, signed_prekey_public, signature)
+        .map_err(|_| anyhow!("signed prekey signature does not match identity key"))
+}
@@ -1,4 +1,4 @@
-pub use redis::aio::MultiplexedConnection;
+pub use redis::aio::{MultiplexedConnection, PubSub};
 pub use sea_orm::DatabaseConnection;
 
 pub async fn init_database(database_url: &str) -> anyhow::Result<DatabaseConnection> {
@@ -13,3 +13,11 @@ pub async fn init_redis(redis_url: &str) -> anyhow::Result<MultiplexedConnection
     tracing::info!("Redis connected successfully");
     Ok(conn)
 }
+
+/// A dedicated connection for subscriptions; it cannot be shared with regular commands.
+pub async fn init_redis_pubsub(redis_url: &str) -> anyhow::Result<PubSub> {
+    let client = redis::Client::open(redis_url)?;
+    let pubsub = client.get_async_pubsub().await?;
+    tracing::info!("Redis pub/sub connected successfully");
+    Ok(pubsub)
+}
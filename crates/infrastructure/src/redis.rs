@@ -1,5 +1,6 @@
 use redis::aio::MultiplexedConnection;
 use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct RedisClient {
     conn: MultiplexedConnection,
@@ -10,42 +11,264 @@ impl RedisClient {
         Self { conn }
     }
 
+    /// Returns the number of subscribers that received the message.
     pub async fn publish<T: Serialize>(
         &mut self,
         channel: &str,
         message: &T,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<usize> {
         let payload = serde_json::to_string(message)?;
-        redis::cmd("PUBLISH")
+        let receivers = redis::cmd("PUBLISH")
             .arg(channel)
             .arg(payload)
-            .query_async::<()>(&mut self.conn)
+            .query_async::<usize>(&mut self.conn)
             .await?;
-        Ok(())
+        Ok(receivers)
     }
 
-    pub async fn set_user_online(&mut self, user_id: &str, device_id: i64) -> anyhow::Result<()> {
-        let key = format!("user:{}:online", user_id);
-        redis::cmd("SADD")
+    /// Marks one device online for `ttl_seconds`. Devices are tracked separately so a silent
+    /// one expires without the others. Returns `true` if no device of the user was online
+    /// before.
+    pub async fn set_user_online(
+        &mut self,
+        user_id: &str,
+        device_id: i64,
+        ttl_seconds: i64,
+    ) -> anyhow::Result<bool> {
+        let key = presence_key(user_id);
+        let now = unix_now();
+        let (online_devices,): (usize,) = redis::pipe()
+            .atomic()
+            .cmd("ZREMRANGEBYSCORE")
             .arg(&key)
+            .arg("-inf")
+            .arg(now)
+            .ignore()
+            .cmd("ZCARD")
+            .arg(&key)
+            .cmd("ZADD")
+            .arg(&key)
+            .arg(now + ttl_seconds)
             .arg(device_id)
-            .query_async::<()>(&mut self.conn)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(presence_key_ttl(ttl_seconds))
+            .ignore()
+            .query_async(&mut self.conn)
             .await?;
-        redis::cmd("EXPIRE")
+        Ok(online_devices == 0)
+    }
+
+    /// Pushes a tracked device's expiry `ttl_seconds` out, for heartbeats. Returns `false`
+    /// without adding it if the device is not tracked, e.g. because it already expired.
+    pub async fn refresh_user_online(
+        &mut self,
+        user_id: &str,
+        device_id: i64,
+        ttl_seconds: i64,
+    ) -> anyhow::Result<bool> {
+        let key = presence_key(user_id);
+        let (expires_at,): (Option<f64>,) = redis::pipe()
+            .atomic()
+            .cmd("ZADD")
+            .arg(&key)
+            .arg("XX")
+            .arg(unix_now() + ttl_seconds)
+            .arg(device_id)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(presence_key_ttl(ttl_seconds))
+            .ignore()
+            .cmd("ZSCORE")
             .arg(&key)
-            .arg(3600)
+            .arg(device_id)
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(expires_at.is_some())
+    }
+
+    /// Drops every device whose presence has expired. Returns the users left with no device
+    /// online, exactly once each even when several sweepers run.
+    pub async fn expire_presence(&mut self) -> anyhow::Result<Vec<String>> {
+        let now = unix_now();
+        let pattern = presence_key("*");
+        let mut offline = Vec::new();
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(100)
+                .query_async(&mut self.conn)
+                .await?;
+
+            for key in keys {
+                let (expired, online_devices): (usize, usize) = redis::pipe()
+                    .atomic()
+                    .cmd("ZREMRANGEBYSCORE")
+                    .arg(&key)
+                    .arg("-inf")
+                    .arg(now)
+                    .cmd("ZCARD")
+                    .arg(&key)
+                    .query_async(&mut self.conn)
+                    .await?;
+                if expired > 0 && online_devices == 0 {
+                    if let Some(user_id) = user_id_of_presence_key(&key) {
+                        offline.push(user_id.to_string());
+                    }
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        Ok(offline)
+    }
+
+    /// Returns `true` if no device of the user is online any more.
+    pub async fn set_user_offline(
+        &mut self,
+        user_id: &str,
+        device_id: i64,
+    ) -> anyhow::Result<bool> {
+        let key = presence_key(user_id);
+        let (online_devices,): (usize,) = redis::pipe()
+            .atomic()
+            .cmd("ZREM")
+            .arg(&key)
+            .arg(device_id)
+            .ignore()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(&key)
+            .arg("-inf")
+            .arg(unix_now())
+            .ignore()
+            .cmd("ZCARD")
+            .arg(&key)
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(online_devices == 0)
+    }
+
+    /// Whether each user has at least one device whose presence has not expired.
+    pub async fn users_online(&mut self, user_ids: &[String]) -> anyhow::Result<Vec<bool>> {
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let now = unix_now();
+        let mut pipe = redis::pipe();
+        for user_id in user_ids {
+            pipe.cmd("ZCOUNT")
+                .arg(presence_key(user_id))
+                .arg(format!("({}", now))
+                .arg("+inf");
+        }
+        let counts: Vec<usize> = pipe.query_async(&mut self.conn).await?;
+        Ok(counts.into_iter().map(|count| count > 0).collect())
+    }
+
+    /// Records that `watcher_id` wants presence updates for `user_id` for the next
+    /// `ttl_seconds`.
+    pub async fn add_presence_watcher(
+        &mut self,
+        user_id: &str,
+        watcher_id: &str,
+        ttl_seconds: i64,
+    ) -> anyhow::Result<()> {
+        let key = watchers_key(user_id);
+        redis::pipe()
+            .cmd("SADD")
+            .arg(&key)
+            .arg(watcher_id)
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(ttl_seconds)
+            .ignore()
             .query_async::<()>(&mut self.conn)
             .await?;
         Ok(())
     }
 
-    pub async fn set_user_offline(&mut self, user_id: &str, device_id: i64) -> anyhow::Result<()> {
-        let key = format!("user:{}:online", user_id);
+    pub async fn remove_presence_watcher(
+        &mut self,
+        user_id: &str,
+        watcher_id: &str,
+    ) -> anyhow::Result<()> {
         redis::cmd("SREM")
-            .arg(&key)
-            .arg(device_id)
+            .arg(watchers_key(user_id))
+            .arg(watcher_id)
             .query_async::<()>(&mut self.conn)
             .await?;
         Ok(())
     }
+
+    /// Counts one request against `key` in a fixed window of `window_seconds`. Returns
+    /// `false` once more than `limit` requests were counted in the current window.
+    pub async fn within_rate_limit(
+        &mut self,
+        key: &str,
+        limit: u64,
+        window_seconds: i64,
+    ) -> anyhow::Result<bool> {
+        let key = rate_limit_key(key);
+        let count: u64 = redis::cmd("INCR")
+            .arg(&key)
+            .query_async(&mut self.conn)
+            .await?;
+        if count == 1 {
+            redis::cmd("EXPIRE")
+                .arg(&key)
+                .arg(window_seconds)
+                .query_async::<()>(&mut self.conn)
+                .await?;
+        }
+        Ok(count <= limit)
+    }
+
+    pub async fn presence_watchers(&mut self, user_id: &str) -> anyhow::Result<Vec<String>> {
+        let watchers = redis::cmd("SMEMBERS")
+            .arg(watchers_key(user_id))
+            .query_async::<Vec<String>>(&mut self.conn)
+            .await?;
+        Ok(watchers)
+    }
+}
+
+/// Sorted set of the user's online devices, scored by when their presence expires.
+fn presence_key(user_id: &str) -> String {
+    format!("user:{}:online", user_id)
+}
+
+fn user_id_of_presence_key(key: &str) -> Option<&str> {
+    key.strip_prefix("user:")?.strip_suffix(":online")
+}
+
+/// The set outlives its devices so `expire_presence` still finds users whose last device
+/// went silent and can announce them offline.
+fn presence_key_ttl(ttl_seconds: i64) -> i64 {
+    ttl_seconds * 2
+}
+
+fn watchers_key(user_id: &str) -> String {
+    format!("user:{}:presence_watchers", user_id)
+}
+
+fn rate_limit_key(key: &str) -> String {
+    format!("rate_limit:{}", key)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
 }
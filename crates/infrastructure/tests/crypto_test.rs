@@ -1,6 +1,13 @@
 use infrastructure::crypto::signal;
 use x25519_dalek::{PublicKey, StaticSecret};
-use ed25519_dalek::{Verifier, Signature};
+
+fn public_key(bytes: &[u8]) -> PublicKey {
+    PublicKey::from(<[u8; 32]>::try_from(bytes).unwrap())
+}
+
+fn secret_key(bytes: &[u8]) -> StaticSecret {
+    StaticSecret::from(<[u8; 32]>::try_from(bytes).unwrap())
+}
 
 #[test]
 fn test_x3dh_handshake_simulation() {
@@ -11,59 +18,104 @@ fn test_x3dh_handshake_simulation() {
     let alice_one_time_prekey = &alice_one_time_prekeys[0];
 
     // 2. Bob (Client Side) fetches Alice's bundle
-    // Bob verifies the signed prekey signature
-    let alice_identity_public = ed25519_dalek::VerifyingKey::from_bytes(
-        &alice_identity.public_key.as_slice().try_into().unwrap()
-    ).unwrap();
-    
-    let signature = Signature::from_bytes(&alice_signed_prekey.signature.as_slice().try_into().unwrap());
-    assert!(alice_identity_public.verify(&alice_signed_prekey.public_key, &signature).is_ok());
+    // Bob verifies the signed prekey signature against the X25519 identity key
+    assert!(signal::verify_signed_prekey(
+        &alice_identity.public_key,
+        &alice_signed_prekey.public_key,
+        &alice_signed_prekey.signature,
+    )
+    .is_ok());
 
     // 3. Bob performs X3DH
-    let bob_identity = StaticSecret::random_from_rng(rand::rngs::OsRng);
+    let (bob_identity, _) = signal::generate_identity_keypair().unwrap();
+    let bob_identity_secret = secret_key(&bob_identity.private_key);
     let bob_ephemeral = StaticSecret::random_from_rng(rand::rngs::OsRng);
 
+    let alice_signed_pk = public_key(&alice_signed_prekey.public_key);
+    let alice_identity_pk = public_key(&alice_identity.public_key);
+    let alice_otpk = public_key(&alice_one_time_prekey.public_key);
+
     // DH1: Bob's Identity + Alice's Signed Prekey
-    let alice_signed_pk = PublicKey::from(
-        <[u8; 32]>::try_from(alice_signed_prekey.public_key.as_slice()).unwrap()
-    );
-    let _dh1 = bob_identity.diffie_hellman(&alice_signed_pk);
-
-    // DH2: Bob's Ephemeral + Alice's Identity (Converted to X25519)
-    // Note: In real Signal, Identity Key is Ed25519, but for X3DH it needs to be converted to X25519 
-    // or used as is if the curve supports it. Signal uses X25519 for handshake.
-    // Our implementation stores Ed25519 for Identity. 
-    // Standard Signal Protocol converts Ed25519 public key to X25519 for DH.
-    // For this test, we will skip DH2 involving Identity Key conversion to keep it simple,
-    // or we focus on DH3 and DH4 which are pure X25519.
-    
-    // Let's verify DH3: Bob's Ephemeral + Alice's Signed Prekey
+    let dh1 = bob_identity_secret.diffie_hellman(&alice_signed_pk);
+    // DH2: Bob's Ephemeral + Alice's Identity (identity keys are X25519, no conversion needed)
+    let dh2 = bob_ephemeral.diffie_hellman(&alice_identity_pk);
+    // DH3: Bob's Ephemeral + Alice's Signed Prekey
     let dh3 = bob_ephemeral.diffie_hellman(&alice_signed_pk);
-
     // DH4: Bob's Ephemeral + Alice's One-Time Prekey
-    let alice_otpk = PublicKey::from(
-        <[u8; 32]>::try_from(alice_one_time_prekey.public_key.as_slice()).unwrap()
-    );
     let dh4 = bob_ephemeral.diffie_hellman(&alice_otpk);
 
     // 4. Alice (Client Side receiving message) derives secrets
-    // Alice reconstructs DH3
-    let alice_signed_secret = StaticSecret::from(
-        <[u8; 32]>::try_from(alice_signed_prekey.private_key.as_slice()).unwrap()
-    );
+    let alice_identity_secret = secret_key(&alice_identity.private_key);
+    let alice_signed_secret = secret_key(&alice_signed_prekey.private_key);
+    let alice_otpk_secret = secret_key(&alice_one_time_prekey.private_key);
+    let bob_identity_public = public_key(&bob_identity.public_key);
     let bob_ephemeral_public = PublicKey::from(&bob_ephemeral);
-    
-    let alice_dh3 = alice_signed_secret.diffie_hellman(&bob_ephemeral_public);
 
-    // Alice reconstructs DH4
-    let alice_otpk_secret = StaticSecret::from(
-        <[u8; 32]>::try_from(alice_one_time_prekey.private_key.as_slice()).unwrap()
-    );
+    let alice_dh1 = alice_signed_secret.diffie_hellman(&bob_identity_public);
+    let alice_dh2 = alice_identity_secret.diffie_hellman(&bob_ephemeral_public);
+    let alice_dh3 = alice_signed_secret.diffie_hellman(&bob_ephemeral_public);
     let alice_dh4 = alice_otpk_secret.diffie_hellman(&bob_ephemeral_public);
 
     // 5. Assert Shared Secrets Match
+    assert_eq!(dh1.as_bytes(), alice_dh1.as_bytes(), "DH1 mismatch");
+    assert_eq!(dh2.as_bytes(), alice_dh2.as_bytes(), "DH2 mismatch");
     assert_eq!(dh3.as_bytes(), alice_dh3.as_bytes(), "DH3 mismatch");
     assert_eq!(dh4.as_bytes(), alice_dh4.as_bytes(), "DH4 mismatch");
+}
+
+#[test]
+fn test_identity_public_key_matches_private_key() {
+    let (identity, public) = signal::generate_identity_keypair().unwrap();
+
+    assert_eq!(identity.public_key, public);
+    assert_eq!(
+        PublicKey::from(&secret_key(&identity.private_key)).as_bytes(),
+        identity.public_key.as_slice()
+    );
+}
+
+#[test]
+fn test_signed_prekey_signature_verifies_for_many_identities() {
+    // XEdDSA has to flip the private scalar for roughly half of all keys; cover both cases.
+    for id in 1..=32 {
+        let (identity, _) = signal::generate_identity_keypair().unwrap();
+        let signed_prekey = signal::generate_signed_prekey(&identity, id).unwrap();
+
+        assert!(signal::verify_signed_prekey(
+            &identity.public_key,
+            &signed_prekey.public_key,
+            &signed_prekey.signature,
+        )
+        .is_ok());
+    }
+}
+
+#[test]
+fn test_signed_prekey_signature_rejects_wrong_identity_and_key() {
+    let (identity, _) = signal::generate_identity_keypair().unwrap();
+    let (other_identity, _) = signal::generate_identity_keypair().unwrap();
+    let signed_prekey = signal::generate_signed_prekey(&identity, 1).unwrap();
+    let other_prekey = signal::generate_prekeys(1, 1).unwrap().remove(0);
+
+    assert!(signal::verify_signed_prekey(
+        &other_identity.public_key,
+        &signed_prekey.public_key,
+        &signed_prekey.signature,
+    )
+    .is_err());
+    assert!(signal::verify_signed_prekey(
+        &identity.public_key,
+        &other_prekey.public_key,
+        &signed_prekey.signature,
+    )
+    .is_err());
 
-    println!("X3DH Key Exchange Simulation Successful!");
+    let mut tampered = signed_prekey.signature.clone();
+    tampered[40] ^= 0x01;
+    assert!(signal::verify_signed_prekey(
+        &identity.public_key,
+        &signed_prekey.public_key,
+        &tampered,
+    )
+    .is_err());
 }
@@ -16,6 +16,17 @@ mod m20251205_add_client_message_id_to_messages;
 mod m20251207000001_add_pin_to_users;
 mod m20251207000002_add_device_type_to_devices;
 mod m20251207000003_create_device_linking_sessions;
+mod m20251208000001_add_new_device_keys_to_linking_sessions;
+mod m20251208000002_create_previous_signed_prekeys;
+mod m20251208000003_create_verified_contacts;
+mod m20251208000004_create_identity_key_changes;
+mod m20251208000005_create_unidentified_access;
+mod m20251208000006_create_sealed_deliveries;
+mod m20251208000007_create_conversation_invites;
+mod m20251208000008_add_sender_key_columns;
+mod m20251208000009_create_sync_checkpoints;
+mod m20251208000010_add_last_seen_visibility_to_users;
+mod m20251208000011_add_client_message_id_index_to_messages;
 
 pub struct Migrator;
 
@@ -39,6 +50,17 @@ impl MigratorTrait for Migrator {
             Box::new(m20251207000001_add_pin_to_users::Migration),
             Box::new(m20251207000002_add_device_type_to_devices::Migration),
             Box::new(m20251207000003_create_device_linking_sessions::Migration),
+            Box::new(m20251208000001_add_new_device_keys_to_linking_sessions::Migration),
+            Box::new(m20251208000002_create_previous_signed_prekeys::Migration),
+            Box::new(m20251208000003_create_verified_contacts::Migration),
+            Box::new(m20251208000004_create_identity_key_changes::Migration),
+            Box::new(m20251208000005_create_unidentified_access::Migration),
+            Box::new(m20251208000006_create_sealed_deliveries::Migration),
+            Box::new(m20251208000007_create_conversation_invites::Migration),
+            Box::new(m20251208000008_add_sender_key_columns::Migration),
+            Box::new(m20251208000009_create_sync_checkpoints::Migration),
+            Box::new(m20251208000010_add_last_seen_visibility_to_users::Migration),
+            Box::new(m20251208000011_add_client_message_id_index_to_messages::Migration),
         ]
     }
 }
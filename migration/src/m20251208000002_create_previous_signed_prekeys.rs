@@ -0,0 +1,100 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PreviousSignedPrekeys::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::DeviceId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::SignedPrekeyId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::PublicKey)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::Signature)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::ReplacedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(PreviousSignedPrekeys::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(PreviousSignedPrekeys::DeviceId)
+                            .col(PreviousSignedPrekeys::SignedPrekeyId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_previous_signed_prekeys_device_id")
+                            .from(
+                                PreviousSignedPrekeys::Table,
+                                PreviousSignedPrekeys::DeviceId,
+                            )
+                            .to(Devices::Table, Devices::DeviceId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for the pruning job
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_previous_signed_prekeys_expires_at")
+                    .table(PreviousSignedPrekeys::Table)
+                    .col(PreviousSignedPrekeys::ExpiresAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PreviousSignedPrekeys::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PreviousSignedPrekeys {
+    Table,
+    DeviceId,
+    SignedPrekeyId,
+    PublicKey,
+    Signature,
+    ReplacedAt,
+    ExpiresAt,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DeviceId,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(VerifiedContacts::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(VerifiedContacts::UserId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(VerifiedContacts::ContactUserId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(VerifiedContacts::IdentityHash)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(VerifiedContacts::VerifiedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(VerifiedContacts::UserId)
+                            .col(VerifiedContacts::ContactUserId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_verified_contacts_user_id")
+                            .from(VerifiedContacts::Table, VerifiedContacts::UserId)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_verified_contacts_contact_user_id")
+                            .from(VerifiedContacts::Table, VerifiedContacts::ContactUserId)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(VerifiedContacts::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum VerifiedContacts {
+    Table,
+    UserId,
+    ContactUserId,
+    IdentityHash,
+    VerifiedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    UserId,
+}
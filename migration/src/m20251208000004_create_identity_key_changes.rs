@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(IdentityKeyChanges::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(IdentityKeyChanges::ChangeId)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(IdentityKeyChanges::UserId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(IdentityKeyChanges::DeviceId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(IdentityKeyChanges::IdentityKey)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(IdentityKeyChanges::ChangedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_identity_key_changes_user_id")
+                            .from(IdentityKeyChanges::Table, IdentityKeyChanges::UserId)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_identity_key_changes_device_id")
+                            .from(IdentityKeyChanges::Table, IdentityKeyChanges::DeviceId)
+                            .to(Devices::Table, Devices::DeviceId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for syncing changes per contact
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_identity_key_changes_user_id")
+                    .table(IdentityKeyChanges::Table)
+                    .col(IdentityKeyChanges::UserId)
+                    .col(IdentityKeyChanges::ChangeId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(IdentityKeyChanges::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum IdentityKeyChanges {
+    Table,
+    ChangeId,
+    UserId,
+    DeviceId,
+    IdentityKey,
+    ChangedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    UserId,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DeviceId,
+}
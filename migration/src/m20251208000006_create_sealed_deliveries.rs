@@ -0,0 +1,83 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SealedDeliveries::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SealedDeliveries::DeliveryId)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(SealedDeliveries::DeviceId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SealedDeliveries::Content)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SealedDeliveries::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_sealed_deliveries_device_id")
+                            .from(SealedDeliveries::Table, SealedDeliveries::DeviceId)
+                            .to(Devices::Table, Devices::DeviceId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for per-device sync
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sealed_deliveries_device_id")
+                    .table(SealedDeliveries::Table)
+                    .col(SealedDeliveries::DeviceId)
+                    .col(SealedDeliveries::DeliveryId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SealedDeliveries::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SealedDeliveries {
+    Table,
+    DeliveryId,
+    DeviceId,
+    Content,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Devices {
+    Table,
+    DeviceId,
+}
@@ -0,0 +1,107 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ConversationInvites::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ConversationInvites::Token)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ConversationInvites::ConvId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ConversationInvites::CreatedBy)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ConversationInvites::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(ConversationInvites::ExpiresAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(ConversationInvites::MemberCap).integer())
+                    .col(
+                        ColumnDef::new(ConversationInvites::UseCount)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(ConversationInvites::RevokedAt).timestamp_with_time_zone())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_conversation_invites_conv_id")
+                            .from(ConversationInvites::Table, ConversationInvites::ConvId)
+                            .to(Conversations::Table, Conversations::ConvId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_conversation_invites_created_by")
+                            .from(ConversationInvites::Table, ConversationInvites::CreatedBy)
+                            .to(Users::Table, Users::UserId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create index for listing a conversation's invites
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_conversation_invites_conv_id")
+                    .table(ConversationInvites::Table)
+                    .col(ConversationInvites::ConvId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ConversationInvites::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ConversationInvites {
+    Table,
+    Token,
+    ConvId,
+    CreatedBy,
+    CreatedAt,
+    ExpiresAt,
+    MemberCap,
+    UseCount,
+    RevokedAt,
+}
+
+#[derive(DeriveIden)]
+enum Conversations {
+    Table,
+    ConvId,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    UserId,
+}
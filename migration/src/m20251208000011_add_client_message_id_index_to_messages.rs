@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Client message ids are chosen by the sending device, so they are only unique per device
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_messages_sender_device_client_message_id")
+                    .table(Messages::Table)
+                    .col(Messages::SenderDeviceId)
+                    .col(Messages::ClientMessageId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_messages_sender_device_client_message_id")
+                    .table(Messages::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Messages {
+    Table,
+    SenderDeviceId,
+    ClientMessageId,
+}